@@ -0,0 +1,229 @@
+use nalgebra_glm as glm;
+use crate::bvh::{Aabb, Hit};
+use crate::math::{Ray, Sphere, Vec3};
+use crate::noise;
+use crate::orbit::KeplerOrbit;
+use rand::Rng;
+use std::f32::consts::PI;
+
+// Shape of the belt and the rocks in it. Radii are in world units.
+pub struct BeltParams {
+    pub inner_radius: f32,
+    pub outer_radius: f32,
+    pub count: usize,
+    pub min_size: f32,        // smallest rock radius
+    pub max_size: f32,        // largest rock radius
+    pub max_inclination: f32, // radians, sets the belt's thickness
+    pub max_eccentricity: f32,
+    pub roughness: f32,       // surface displacement relative to the rock's radius
+}
+
+impl Default for BeltParams {
+    // The planets' orbits leave no gap wide enough, so the default belt sits
+    // just outside the gas giant
+    fn default() -> Self {
+        BeltParams {
+            inner_radius: 4.6,
+            outer_radius: 5.6,
+            count: 3000,
+            min_size: 0.008,
+            max_size: 0.05,
+            max_inclination: 0.06,
+            max_eccentricity: 0.08,
+            roughness: 0.35,
+        }
+    }
+}
+
+pub struct Asteroid {
+    pub orbit: KeplerOrbit,
+    pub size: f32,          // mean radius
+    pub axes: Vec3,         // triaxial stretch of the base ellipsoid, max component 1
+    pub spin_axis: Vec3,
+    pub spin_speed: f32,    // radians per second
+    pub shape_offset: Vec3, // noise domain offset, gives each rock its own shape
+    pub albedo: Vec3,
+}
+
+pub struct AsteroidBelt {
+    pub params: BeltParams,
+    pub asteroids: Vec<Asteroid>,
+}
+
+// Rocks whose bounding sphere covers fewer pixels than this are drawn as
+// plain spheres instead of being sphere traced
+const DETAIL_PIXELS: f32 = 4.0;
+const MARCH_STEPS: usize = 48;
+
+impl AsteroidBelt {
+    pub fn generate(params: BeltParams, rng: &mut impl Rng) -> Self {
+        let mut asteroids = Vec::with_capacity(params.count);
+        for _ in 0..params.count {
+            // Uniform over the annulus' area
+            let r2 = rng.gen_range(params.inner_radius.powi(2)..params.outer_radius.powi(2));
+            // Power-law sizes: many pebbles, few large rocks
+            let size = params.min_size * (params.max_size / params.min_size).powf(rng.gen_range(0.0f32..1.0).powi(3));
+            let spin_axis = glm::normalize(&glm::vec3(
+                rng.gen_range(-1.0f32..1.0),
+                rng.gen_range(-1.0f32..1.0),
+                rng.gen_range(-1.0f32..1.0),
+            ));
+            let axes = glm::vec3(1.0, rng.gen_range(0.55f32..1.0), rng.gen_range(0.45f32..0.9));
+            // Grey to reddish-brown carbonaceous and stony surfaces
+            let tone = rng.gen_range(0.25f32..0.5);
+            let rust = rng.gen_range(0.0f32..0.12);
+            asteroids.push(Asteroid {
+                orbit: KeplerOrbit {
+                    semi_major_axis: r2.sqrt(),
+                    eccentricity: rng.gen_range(0.0..params.max_eccentricity.max(1e-4)),
+                    inclination: rng.gen_range(-params.max_inclination..params.max_inclination.max(1e-4)),
+                    ascending_node: rng.gen_range(0.0..2.0 * PI),
+                    periapsis_arg: rng.gen_range(0.0..2.0 * PI),
+                    mean_anomaly: rng.gen_range(0.0..2.0 * PI),
+                },
+                size,
+                axes,
+                spin_axis,
+                spin_speed: rng.gen_range(-3.0f32..3.0),
+                shape_offset: glm::vec3(rng.gen_range(0.0f32..64.0), rng.gen_range(0.0f32..64.0), rng.gen_range(0.0f32..64.0)),
+                albedo: glm::vec3(tone + rust, tone + rust * 0.4, tone),
+            });
+        }
+        AsteroidBelt { params, asteroids }
+    }
+
+    // Radius of a sphere around the rock's center that contains its surface
+    fn bound(&self, asteroid: &Asteroid) -> f32 {
+        asteroid.size * (1.0 + self.params.roughness)
+    }
+
+    // Positions at `time`. `pixel_angle` decides which rocks are large
+    // enough on screen to be worth their full shape.
+    pub fn at(&self, time: f32, pixel_angle: f32) -> BeltFrame<'_> {
+        let centers = self.asteroids.iter().map(|a| a.orbit.position(time)).collect();
+        let bounds = self.asteroids.iter().map(|a| self.bound(a)).collect();
+        BeltFrame { belt: self, time, pixel_angle, centers, bounds }
+    }
+}
+
+// The belt frozen at one instant. Rocks are addressed by index, so the
+// scene hierarchy can hold them next to everything else.
+pub struct BeltFrame<'a> {
+    belt: &'a AsteroidBelt,
+    time: f32,
+    pixel_angle: f32,
+    centers: Vec<Vec3>,
+    bounds: Vec<f32>,
+}
+
+impl BeltFrame<'_> {
+    pub fn len(&self) -> usize {
+        self.centers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.centers.is_empty()
+    }
+
+    pub fn rock_bounds(&self, i: usize) -> Aabb {
+        Aabb::around_sphere(&self.centers[i], self.bounds[i])
+    }
+
+    fn is_impostor(&self, i: usize, distance: f32) -> bool {
+        self.bounds[i] / (distance * self.pixel_angle) < DETAIL_PIXELS
+    }
+
+    // Plain sphere standing in for rock `i` seen from `distance`: the
+    // rock's mean size, grown to half a pixel when smaller
+    pub fn impostor(&self, i: usize, distance: f32) -> Sphere {
+        Sphere::new(self.centers[i], impostor_radius(&self.belt.asteroids[i], distance * self.pixel_angle))
+    }
+
+    // Surface color of rock `i` seen from `distance`. Impostors grown to
+    // half a pixel darken to match, so they fade out instead of flickering.
+    pub fn albedo(&self, i: usize, distance: f32) -> Vec3 {
+        let asteroid = &self.belt.asteroids[i];
+        if !self.is_impostor(i, distance) {
+            return asteroid.albedo;
+        }
+        asteroid.albedo * (asteroid.size / impostor_radius(asteroid, distance * self.pixel_angle)).powi(2)
+    }
+
+    pub fn intersect_rock(&self, i: usize, ray: &Ray, t_max: f32) -> Option<Hit> {
+        let asteroid = &self.belt.asteroids[i];
+        let center = self.centers[i];
+        let bound = self.bounds[i];
+        let oc = ray.origin - center;
+        let b = glm::dot(&oc, &ray.direction);
+        let c = glm::dot(&oc, &oc) - bound * bound;
+        let disc = b * b - c;
+        if disc < 0.0 {
+            return None;
+        }
+        let sq = disc.sqrt();
+        let (t0, t1) = (-b - sq, -b + sq);
+        if t1 <= 0.0 || t0 >= t_max {
+            return None;
+        }
+
+        // Far rocks: a plain sphere of the rock's mean size
+        let distance = glm::length(&oc).max(1e-4);
+        if self.is_impostor(i, distance) {
+            let sphere = self.impostor(i, distance);
+            let hit = sphere.intersect(ray).filter(|t| *t < t_max)?;
+            let point = ray.origin + ray.direction * hit;
+            return Some(Hit { t: hit, point, normal: (point - center) / sphere.radius });
+        }
+
+        // Sphere trace the displaced surface in the rock's spinning frame
+        let angle = -asteroid.spin_speed * self.time;
+        let to_local = |v: &Vec3| glm::rotate_vec3(v, angle, &asteroid.spin_axis);
+        let origin = to_local(&oc);
+        let dir = to_local(&ray.direction);
+        let mut t = t0.max(0.0);
+        let end = t1.min(t_max);
+        for _ in 0..MARCH_STEPS {
+            let p = origin + dir * t;
+            let d = self.surface_distance(asteroid, &p);
+            if d < asteroid.size * 2e-3 {
+                let normal = glm::rotate_vec3(&self.surface_normal(asteroid, &p), -angle, &asteroid.spin_axis);
+                return Some(Hit { t, point: ray.origin + ray.direction * t, normal });
+            }
+            // The displacement is not distance-preserving; under-step a little
+            t += d * 0.7;
+            if t > end {
+                break;
+            }
+        }
+        None
+    }
+
+    // Approximate signed distance to the displaced ellipsoid surface, in the
+    // rock's local frame
+    fn surface_distance(&self, asteroid: &Asteroid, p: &Vec3) -> f32 {
+        let len = glm::length(p).max(1e-6);
+        let dir = p / len;
+        // Ellipsoid radius along dir, then lumps and craters from noise
+        let stretched = glm::vec3(dir.x / asteroid.axes.x, dir.y / asteroid.axes.y, dir.z / asteroid.axes.z);
+        let base = 1.0 / glm::length(&stretched);
+        let lumps = noise::fbm(&(dir * 2.5 + asteroid.shape_offset), 4) * 2.0 - 1.0;
+        let radius = asteroid.size * base * (1.0 + self.belt.params.roughness * lumps);
+        len - radius
+    }
+
+    fn surface_normal(&self, asteroid: &Asteroid, p: &Vec3) -> Vec3 {
+        let e = asteroid.size * 0.01;
+        let d = |q: Vec3| self.surface_distance(asteroid, &q);
+        let grad = glm::vec3(
+            d(p + glm::vec3(e, 0.0, 0.0)) - d(p - glm::vec3(e, 0.0, 0.0)),
+            d(p + glm::vec3(0.0, e, 0.0)) - d(p - glm::vec3(0.0, e, 0.0)),
+            d(p + glm::vec3(0.0, 0.0, e)) - d(p - glm::vec3(0.0, 0.0, e)),
+        );
+        glm::normalize(&grad)
+    }
+}
+
+// Impostors smaller than a pixel grow to half a pixel across
+fn impostor_radius(asteroid: &Asteroid, pixel_size: f32) -> f32 {
+    asteroid.size.max(pixel_size * 0.5)
+}
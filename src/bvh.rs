@@ -0,0 +1,238 @@
+use nalgebra_glm as glm;
+use crate::math::{Ray, Sphere, Vec3};
+
+// Axis-aligned bounding box
+#[derive(Clone, Copy)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn empty() -> Self {
+        let inf = f32::INFINITY;
+        Aabb { min: glm::vec3(inf, inf, inf), max: glm::vec3(-inf, -inf, -inf) }
+    }
+
+    pub fn around_sphere(center: &Vec3, radius: f32) -> Self {
+        let r = glm::vec3(radius, radius, radius);
+        Aabb { min: center - r, max: center + r }
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb { min: glm::min2(&self.min, &other.min), max: glm::max2(&self.max, &other.max) }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let e = self.max - self.min;
+        if e.x < 0.0 {
+            return 0.0;
+        }
+        2.0 * (e.x * e.y + e.y * e.z + e.z * e.x)
+    }
+
+    // Distance at which the ray enters the box, if it does before `t_max`
+    pub fn hit(&self, origin: &Vec3, inv_dir: &Vec3, t_max: f32) -> Option<f32> {
+        let t0 = (self.min - origin).component_mul(inv_dir);
+        let t1 = (self.max - origin).component_mul(inv_dir);
+        let near = glm::min2(&t0, &t1);
+        let far = glm::max2(&t0, &t1);
+        let enter = near.x.max(near.y).max(near.z).max(0.0);
+        let exit = far.x.min(far.y).min(far.z).min(t_max);
+        (enter <= exit).then_some(enter)
+    }
+}
+
+pub struct Hit {
+    pub t: f32,
+    pub point: Vec3,
+    pub normal: Vec3, // unit, facing out of the surface
+}
+
+// Anything rays can hit. Primitives go into a `Bvh` together, whatever
+// their shape.
+pub trait Hittable {
+    fn bounds(&self) -> Aabb;
+    // Closest hit in (0, t_max)
+    fn intersect(&self, ray: &Ray, t_max: f32) -> Option<Hit>;
+}
+
+impl Hittable for Sphere {
+    fn bounds(&self) -> Aabb {
+        Aabb::around_sphere(&self.center, self.radius)
+    }
+
+    fn intersect(&self, ray: &Ray, t_max: f32) -> Option<Hit> {
+        let t = Sphere::intersect(self, ray).filter(|t| *t < t_max)?;
+        let point = ray.origin + ray.direction * t;
+        Some(Hit { t, point, normal: self.normal_at(&point) })
+    }
+}
+
+// Surface area heuristic costs, relative to one primitive test
+const TRAVERSAL_COST: f32 = 1.0;
+const SAH_BINS: usize = 12;
+const MAX_LEAF: usize = 4;
+
+// Inner nodes keep their left child right after themselves and the right
+// child at `first`; leaves hold `count` entries of `order` from `first`.
+struct Node {
+    bounds: Aabb,
+    first: u32,
+    count: u32,
+}
+
+// Bounding volume hierarchy over a slice of primitives, built with a binned
+// surface area heuristic. It only stores indices: pass the same slice, in
+// the same order, to `intersect`, or test primitives yourself in `traverse`.
+pub struct Bvh {
+    nodes: Vec<Node>,
+    order: Vec<u32>,
+}
+
+impl Bvh {
+    pub fn build<T: Hittable>(primitives: &[T]) -> Self {
+        let bounds: Vec<Aabb> = primitives.iter().map(|p| p.bounds()).collect();
+        Self::from_bounds(&bounds)
+    }
+
+    // Hierarchy over primitives known only by their boxes
+    pub fn from_bounds(bounds: &[Aabb]) -> Self {
+        let centers: Vec<Vec3> = bounds.iter().map(|b| b.center()).collect();
+        let mut order: Vec<u32> = (0..bounds.len() as u32).collect();
+        let mut nodes = Vec::with_capacity(bounds.len().max(1) * 2);
+        if !order.is_empty() {
+            build_node(&mut nodes, &mut order, 0, bounds, &centers);
+        }
+        Bvh { nodes, order }
+    }
+
+    // Closest hit before `t_max`, with the index of the primitive hit
+    pub fn intersect<T: Hittable>(&self, primitives: &[T], ray: &Ray, t_max: f32) -> Option<(usize, Hit)> {
+        self.traverse(ray, t_max, |i, t_max| primitives[i].intersect(ray, t_max))
+    }
+
+    // Closest hit before `t_max`, where `test(i, t_max)` intersects the
+    // ray with primitive `i`
+    pub fn traverse(&self, ray: &Ray, t_max: f32, mut test: impl FnMut(usize, f32) -> Option<Hit>) -> Option<(usize, Hit)> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let inv_dir = glm::vec3(1.0 / ray.direction.x, 1.0 / ray.direction.y, 1.0 / ray.direction.z);
+        let mut closest = t_max;
+        let mut best = None;
+        let mut stack = Vec::with_capacity(64);
+        stack.push(0);
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if node.bounds.hit(&ray.origin, &inv_dir, closest).is_none() {
+                continue;
+            }
+            if node.count > 0 {
+                let range = node.first as usize..(node.first + node.count) as usize;
+                for &i in &self.order[range] {
+                    if let Some(hit) = test(i as usize, closest) {
+                        closest = hit.t;
+                        best = Some((i as usize, hit));
+                    }
+                }
+                continue;
+            }
+            // Visit the nearer child first so the far one is often culled
+            let (left, right) = (index + 1, node.first as usize);
+            let left_t = self.nodes[left].bounds.hit(&ray.origin, &inv_dir, closest);
+            let right_t = self.nodes[right].bounds.hit(&ray.origin, &inv_dir, closest);
+            let (near, far) = match (left_t, right_t) {
+                (Some(a), Some(b)) if b < a => (Some(right), Some(left)),
+                (Some(_), Some(_)) => (Some(left), Some(right)),
+                (Some(_), None) => (Some(left), None),
+                (None, Some(_)) => (Some(right), None),
+                (None, None) => (None, None),
+            };
+            stack.extend([far, near].into_iter().flatten());
+        }
+        best
+    }
+}
+
+fn build_node(nodes: &mut Vec<Node>, order: &mut [u32], offset: usize, bounds: &[Aabb], centers: &[Vec3]) -> usize {
+    let node_bounds = order.iter().fold(Aabb::empty(), |b, &i| b.union(&bounds[i as usize]));
+    let index = nodes.len();
+    nodes.push(Node { bounds: node_bounds, first: offset as u32, count: order.len() as u32 });
+    if order.len() <= 1 {
+        return index;
+    }
+
+    // Bin the centroids along each axis and keep the cheapest split
+    let centroid_bounds = order.iter().fold(Aabb::empty(), |b, &i| b.union(&Aabb { min: centers[i as usize], max: centers[i as usize] }));
+    let extent = centroid_bounds.max - centroid_bounds.min;
+    let mut best: Option<(f32, usize, f32)> = None; // cost, axis, split position
+    for axis in 0..3 {
+        if extent[axis] <= 1e-6 {
+            continue;
+        }
+        let mut bins = [(Aabb::empty(), 0usize); SAH_BINS];
+        let scale = SAH_BINS as f32 / extent[axis];
+        let bin_of = |i: u32| (((centers[i as usize][axis] - centroid_bounds.min[axis]) * scale) as usize).min(SAH_BINS - 1);
+        for &i in order.iter() {
+            let b = &mut bins[bin_of(i)];
+            b.0 = b.0.union(&bounds[i as usize]);
+            b.1 += 1;
+        }
+        // Sweep from the right, then from the left, to cost every boundary
+        let mut right_area = [0.0f32; SAH_BINS];
+        let mut right_count = [0usize; SAH_BINS];
+        let mut acc = (Aabb::empty(), 0usize);
+        for k in (1..SAH_BINS).rev() {
+            acc = (acc.0.union(&bins[k].0), acc.1 + bins[k].1);
+            right_area[k] = acc.0.surface_area();
+            right_count[k] = acc.1;
+        }
+        let mut left = (Aabb::empty(), 0usize);
+        for k in 1..SAH_BINS {
+            left = (left.0.union(&bins[k - 1].0), left.1 + bins[k - 1].1);
+            if left.1 == 0 || right_count[k] == 0 {
+                continue;
+            }
+            let cost = left.0.surface_area() * left.1 as f32 + right_area[k] * right_count[k] as f32;
+            if best.is_none_or(|b| cost < b.0) {
+                best = Some((cost, axis, centroid_bounds.min[axis] + k as f32 / scale));
+            }
+        }
+    }
+
+    let leaf_cost = order.len() as f32;
+    let split = best.filter(|(cost, _, _)| {
+        TRAVERSAL_COST + cost / node_bounds.surface_area().max(1e-12) < leaf_cost || order.len() > MAX_LEAF
+    });
+    let mid = match split {
+        Some((_, axis, position)) => partition(order, |i| centers[i as usize][axis] < position),
+        // Every centroid in one spot: split the list in half if it is too long
+        None if order.len() > MAX_LEAF => order.len() / 2,
+        None => return index,
+    };
+    let mid = if mid == 0 || mid == order.len() { order.len() / 2 } else { mid };
+
+    let (left, right) = order.split_at_mut(mid);
+    build_node(nodes, left, offset, bounds, centers);
+    let right_index = build_node(nodes, right, offset + mid, bounds, centers);
+    nodes[index].first = right_index as u32;
+    nodes[index].count = 0;
+    index
+}
+
+// In-place partition; returns how many entries satisfy `pred` (now in front)
+fn partition(order: &mut [u32], pred: impl Fn(u32) -> bool) -> usize {
+    let mut mid = 0;
+    for i in 0..order.len() {
+        if pred(order[i]) {
+            order.swap(i, mid);
+            mid += 1;
+        }
+    }
+    mid
+}
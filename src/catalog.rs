@@ -0,0 +1,196 @@
+use nalgebra_glm as glm;
+use crate::color::{self, Color};
+use crate::math::Vec3;
+use crate::starfield::Star;
+use std::fmt;
+use std::path::Path;
+
+// Bright stars shipped with the program (HYG column layout)
+const BUNDLED: &str = include_str!("../assets/bright_stars.csv");
+
+#[derive(Debug)]
+pub struct CatalogError {
+    pub source: String, // file the catalog came from
+    pub line: usize,    // 1-based, 0 when the file could not be read at all
+    pub message: String,
+}
+
+impl fmt::Display for CatalogError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: {}", self.source, self.message)
+        } else {
+            write!(f, "{}:{}: {}", self.source, self.line, self.message)
+        }
+    }
+}
+
+impl std::error::Error for CatalogError {}
+
+pub fn bundled() -> Vec<Star> {
+    parse_catalog(BUNDLED, "assets/bright_stars.csv").expect("bundled star catalog is valid")
+}
+
+pub fn load_catalog(path: &Path) -> Result<Vec<Star>, CatalogError> {
+    let source = path.display().to_string();
+    let text = std::fs::read_to_string(path).map_err(|e| CatalogError {
+        source: source.clone(),
+        line: 0,
+        message: e.to_string(),
+    })?;
+    parse_catalog(&text, &source)
+}
+
+// Right ascension can be given in hours (HYG `ra`) or degrees (Yale Bright
+// Star exports, `RAdeg`/`RAJ2000`); everything else in degrees/magnitudes.
+enum RaUnit {
+    Hours,
+    Degrees,
+}
+
+struct Columns {
+    ra: (usize, RaUnit),
+    dec: usize,
+    mag: usize,
+    ci: Option<usize>,
+}
+
+fn find_columns(header: &[&str]) -> Result<Columns, String> {
+    let find = |names: &[&str]| header.iter().position(|h| names.iter().any(|n| h.eq_ignore_ascii_case(n)));
+    let ra = match (find(&["ra"]), find(&["radeg", "ra_deg", "raj2000"])) {
+        (Some(i), _) => (i, RaUnit::Hours),
+        (None, Some(i)) => (i, RaUnit::Degrees),
+        (None, None) => return Err("missing right ascension column (ra or RAdeg)".to_string()),
+    };
+    let dec = find(&["dec", "dedeg", "dec_deg", "dej2000"]).ok_or("missing declination column (dec or DEdeg)")?;
+    let mag = find(&["mag", "vmag"]).ok_or("missing magnitude column (mag or Vmag)")?;
+    let ci = find(&["ci", "b-v", "bv"]);
+    Ok(Columns { ra, dec, mag, ci })
+}
+
+// Parse a CSV star catalog. Blank lines and lines starting with '#' are
+// skipped; the first remaining line names the columns.
+pub fn parse_catalog(text: &str, source: &str) -> Result<Vec<Star>, CatalogError> {
+    let err = |line: usize, message: String| CatalogError { source: source.to_string(), line, message };
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .map(|(i, l)| (i + 1, l.trim()))
+        .filter(|(_, l)| !l.is_empty() && !l.starts_with('#'));
+
+    let (header_line, header) = lines.next().ok_or_else(|| err(0, "catalog is empty".to_string()))?;
+    let header: Vec<&str> = header.split(',').map(unquote).collect();
+    let cols = find_columns(&header).map_err(|m| err(header_line, m))?;
+
+    let mut stars = Vec::new();
+    for (line, row) in lines {
+        let fields: Vec<&str> = row.split(',').map(unquote).collect();
+        let number = |i: usize, name: &str| -> Result<f32, CatalogError> {
+            let field = fields.get(i).ok_or_else(|| err(line, format!("missing {} field", name)))?;
+            field.parse().map_err(|_| err(line, format!("invalid {} value {:?}", name, field)))
+        };
+        let ra = match cols.ra.1 {
+            RaUnit::Hours => number(cols.ra.0, "ra")? * 15.0,
+            RaUnit::Degrees => number(cols.ra.0, "ra")?,
+        };
+        let dec = number(cols.dec, "dec")?;
+        let mag = number(cols.mag, "mag")?;
+        // Some entries have no measured color; treat those as sun-like
+        let bv = match cols.ci {
+            Some(i) if fields.get(i).is_some_and(|f| !f.is_empty()) => number(i, "ci")?,
+            _ => 0.65,
+        };
+        // HYG lists the Sun itself as entry 0
+        if mag < -5.0 {
+            continue;
+        }
+        stars.push(catalog_star(ra.to_radians(), dec.to_radians(), mag, bv));
+    }
+    Ok(stars)
+}
+
+fn unquote(s: &str) -> &str {
+    s.trim().trim_matches('"')
+}
+
+// Equatorial coordinates (radians) -> scene direction. The celestial north
+// pole is +Y and RA 6h looks down -Z, so the sky is not mirrored as seen
+// from inside.
+pub fn equatorial_to_dir(ra: f32, dec: f32) -> Vec3 {
+    glm::vec3(dec.cos() * ra.cos(), dec.sin(), -dec.cos() * ra.sin())
+}
+
+pub fn catalog_star(ra: f32, dec: f32, mag: f32, bv: f32) -> Star {
+    let dir = equatorial_to_dir(ra, dec);
+    let (h, s, v) = color::blackbody(bv_to_temperature(bv)).to_hsv();
+    Star {
+        dir,
+        brightness: magnitude_to_brightness(mag),
+        color: Color::from_hsv(h, s * 0.6, v).to_vec3(),
+    }
+}
+
+// Ballesteros (2012) fit of effective temperature to the B-V index
+pub fn bv_to_temperature(bv: f32) -> f32 {
+    4600.0 * (1.0 / (0.92 * bv + 1.7) + 1.0 / (0.92 * bv + 0.62))
+}
+
+// Pogson flux ratio relative to a 1st magnitude star, compressed a little so
+// the dimmest naked-eye stars still register on screen
+pub fn magnitude_to_brightness(mag: f32) -> f32 {
+    10f32.powf(-0.4 * (mag - 1.0) * 0.6)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: &Vec3, b: &Vec3) -> bool {
+        glm::distance(a, b) < 1e-5
+    }
+
+    #[test]
+    fn reads_ra_in_hours_or_degrees() {
+        let hours = parse_catalog("id,ra,dec,mag,ci\n1,6,30,1.5,0.2\n", "hyg.csv").unwrap();
+        let degrees = parse_catalog("HR,RAdeg,DEdeg,Vmag,B-V\n1,90,30,1.5,0.2\n", "bsc.csv").unwrap();
+        let expected = equatorial_to_dir(90f32.to_radians(), 30f32.to_radians());
+        assert!(close(&hours[0].dir, &expected));
+        assert!(close(&degrees[0].dir, &expected));
+        assert_eq!(hours[0].brightness, degrees[0].brightness);
+    }
+
+    #[test]
+    fn reports_missing_columns_and_fields_by_line() {
+        let error = parse_catalog("# stars\n\nra,mag\n1,2\n", "bad.csv").err().unwrap();
+        assert_eq!(error.line, 3);
+        assert!(error.message.contains("declination"), "{}", error.message);
+
+        let error = parse_catalog("ra,dec,mag\n1,2,3\n1,2\n", "bad.csv").err().unwrap();
+        assert_eq!((error.line, error.message.as_str()), (3, "missing mag field"));
+        let error = parse_catalog("ra,dec,mag\n1,x,3\n", "bad.csv").err().unwrap();
+        assert_eq!(error.line, 2);
+        assert_eq!(error.to_string(), "bad.csv:2: invalid dec value \"x\"");
+    }
+
+    #[test]
+    fn missing_color_index_is_sun_like() {
+        let stars = parse_catalog("ra,dec,mag,ci\n1,2,3,\n1,2,3\n", "stars.csv").unwrap();
+        let sun_like = catalog_star(15f32.to_radians(), 2f32.to_radians(), 3.0, 0.65);
+        assert_eq!(stars.len(), 2);
+        for star in &stars {
+            assert!(close(&star.color, &sun_like.color));
+        }
+    }
+
+    #[test]
+    fn skips_the_sun() {
+        let stars = parse_catalog("ra,dec,mag\n0,0,-26.7\n6.75,-16.7,-1.46\n", "hyg.csv").unwrap();
+        assert_eq!(stars.len(), 1);
+        assert!(close(&stars[0].dir, &equatorial_to_dir((6.75f32 * 15.0).to_radians(), (-16.7f32).to_radians())));
+    }
+
+    #[test]
+    fn bundled_catalog_parses() {
+        assert!(!bundled().is_empty());
+    }
+}
@@ -1,3 +1,8 @@
+use crate::math::Vec3;
+use nalgebra_glm as glm;
+use std::sync::OnceLock;
+
+
 #[derive(Clone)]
 pub struct Color {
     pub r: f32,
@@ -12,10 +17,15 @@ Self { r, g, b }
 }
 
 
+pub fn from_vec3(v: &Vec3) -> Self {
+Self::new(v.x, v.y, v.z)
+}
+
+
 pub fn clamp_u8(&self) -> [u8; 3] {
 fn c(v: f32) -> u8 {
 let v = if v.is_finite() { v } else { 0.0 };
-(v.max(0.0).min(1.0) * 255.0 + 0.5) as u8
+(v.clamp(0.0, 1.0) * 255.0 + 0.5) as u8
 }
 [c(self.r), c(self.g), c(self.b)]
 }
@@ -29,7 +39,240 @@ Self::new(self.r * s, self.g * s, self.b * s)
 pub fn add(&self, o: &Color) -> Self {
 Self::new(self.r + o.r, self.g + o.g, self.b + o.b)
 }
+
+
+pub fn lerp(&self, o: &Color, t: f32) -> Self {
+self.mul_scalar(1.0 - t).add(&o.mul_scalar(t))
+}
+
+
+pub fn to_vec3(&self) -> Vec3 {
+glm::vec3(self.r, self.g, self.b)
+}
+
+
+// Relative luminance (Rec. 709 / sRGB primaries). Expects linear values.
+pub fn luminance(&self) -> f32 {
+0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+}
+
+
+pub fn to_linear(&self) -> Self {
+Self::new(srgb_to_linear(self.r), srgb_to_linear(self.g), srgb_to_linear(self.b))
+}
+
+
+pub fn to_srgb(&self) -> Self {
+Self::new(linear_to_srgb(self.r), linear_to_srgb(self.g), linear_to_srgb(self.b))
+}
+
+
+// Hue in degrees [0, 360), saturation and value in [0, 1]
+pub fn to_hsv(&self) -> (f32, f32, f32) {
+let max = self.r.max(self.g).max(self.b);
+let min = self.r.min(self.g).min(self.b);
+let delta = max - min;
+let s = if max > 0.0 { delta / max } else { 0.0 };
+(self.hue(max, delta), s, max)
+}
+
+
+pub fn from_hsv(h: f32, s: f32, v: f32) -> Self {
+let c = v * s;
+Self::from_hue_chroma(h, c, v - c)
+}
+
+
+// Hue in degrees [0, 360), saturation and lightness in [0, 1]
+pub fn to_hsl(&self) -> (f32, f32, f32) {
+let max = self.r.max(self.g).max(self.b);
+let min = self.r.min(self.g).min(self.b);
+let delta = max - min;
+let l = (max + min) * 0.5;
+let s = if delta > 0.0 { delta / (1.0 - (2.0 * l - 1.0).abs()) } else { 0.0 };
+(self.hue(max, delta), s, l)
+}
+
+
+pub fn from_hsl(h: f32, s: f32, l: f32) -> Self {
+let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+Self::from_hue_chroma(h, c, l - c * 0.5)
+}
+
+
+fn hue(&self, max: f32, delta: f32) -> f32 {
+if delta <= 0.0 {
+return 0.0;
+}
+let h = if max == self.r {
+((self.g - self.b) / delta).rem_euclid(6.0)
+} else if max == self.g {
+(self.b - self.r) / delta + 2.0
+} else {
+(self.r - self.g) / delta + 4.0
+};
+h * 60.0
+}
+
+
+// Shared tail of HSV/HSL -> RGB: place chroma `c` on the hue hexagon and lift by `m`
+fn from_hue_chroma(h: f32, c: f32, m: f32) -> Self {
+let hp = h.rem_euclid(360.0) / 60.0;
+let x = c * (1.0 - (hp % 2.0 - 1.0).abs());
+let (r, g, b) = match hp as u32 {
+0 => (c, x, 0.0),
+1 => (x, c, 0.0),
+2 => (0.0, c, x),
+3 => (0.0, x, c),
+4 => (x, 0.0, c),
+_ => (c, 0.0, x),
+};
+Self::new(r + m, g + m, b + m)
+}
+}
+
+
+// sRGB transfer function (IEC 61966-2-1)
+pub fn srgb_to_linear(c: f32) -> f32 {
+if c <= 0.04045 {
+c / 12.92
+} else {
+((c + 0.055) / 1.055).powf(2.4)
+}
+}
+
+
+pub fn linear_to_srgb(c: f32) -> f32 {
+let c = c.max(0.0);
+if c <= 0.003_130_8 {
+c * 12.92
+} else {
+1.055 * c.powf(1.0 / 2.4) - 0.055
+}
+}
+
+
+// Piecewise Gaussian used by the CIE 1931 fit below
+fn lobe(x: f32, mu: f32, sigma_lo: f32, sigma_hi: f32) -> f32 {
+let sigma = if x < mu { sigma_lo } else { sigma_hi };
+let t = (x - mu) / sigma;
+(-0.5 * t * t).exp()
+}
+
+
+// CIE 1931 2-degree color matching functions, multi-lobe fit from
+// Wyman, Sloan & Shirley (2013). Wavelength in nanometers.
+fn cie_xyz(lambda: f32) -> [f32; 3] {
+let x = 1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7)
+- 0.065 * lobe(lambda, 501.1, 20.4, 26.2);
+let y = 0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1);
+let z = 1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8);
+[x, y, z]
+}
+
+
+// Planck's law, spectral radiance up to a constant factor. Wavelength in nm.
+fn planck(lambda_nm: f32, kelvin: f32) -> f64 {
+// second radiation constant hc/k in nm*K
+const C2: f64 = 1.438_777e7;
+let l = lambda_nm as f64;
+1.0 / (l.powi(5) * ((C2 / (l * kelvin as f64)).exp() - 1.0))
+}
+
+
+// Linear sRGB (D65) of a blackbody: Planck's law integrated against the CIE
+// matching functions and converted from XYZ. Normalized so the brightest
+// channel is 1; channels outside the sRGB gamut are clipped to 0.
+pub fn kelvin_to_linear_srgb(kelvin: f32) -> Color {
+let mut xyz = [0.0f64; 3];
+let mut lambda = 380.0;
+while lambda <= 780.0 {
+let p = planck(lambda, kelvin);
+let cmf = cie_xyz(lambda);
+for (acc, c) in xyz.iter_mut().zip(cmf) {
+*acc += p * c as f64;
+}
+lambda += 5.0;
+}
+let [x, y, z] = xyz;
+let r = 3.2406 * x - 1.5372 * y - 0.4986 * z;
+let g = -0.9689 * x + 1.8758 * y + 0.0415 * z;
+let b = 0.0557 * x - 0.2040 * y + 1.0570 * z;
+let (r, g, b) = (r.max(0.0), g.max(0.0), b.max(0.0));
+let max = r.max(g).max(b).max(1e-30);
+Color::new((r / max) as f32, (g / max) as f32, (b / max) as f32)
+}
+
+
+const BLACKBODY_MIN: f32 = 1000.0;
+const BLACKBODY_MAX: f32 = 40000.0;
+const BLACKBODY_STEP: f32 = 100.0;
+
+
+// The spectral integral is too slow to run per pixel, so it is tabulated once
+fn blackbody_table() -> &'static [Color] {
+static TABLE: OnceLock<Vec<Color>> = OnceLock::new();
+TABLE.get_or_init(|| {
+let n = ((BLACKBODY_MAX - BLACKBODY_MIN) / BLACKBODY_STEP) as usize + 1;
+(0..n)
+.map(|i| kelvin_to_linear_srgb(BLACKBODY_MIN + i as f32 * BLACKBODY_STEP))
+.collect()
+})
+}
+
+
+// Linear blackbody tint, interpolated from the table (1000K-40000K)
+pub fn blackbody_linear(kelvin: f32) -> Color {
+let table = blackbody_table();
+let f = (kelvin.clamp(BLACKBODY_MIN, BLACKBODY_MAX) - BLACKBODY_MIN) / BLACKBODY_STEP;
+let i = (f as usize).min(table.len() - 2);
+table[i].lerp(&table[i + 1], f - i as f32)
 }
 
-// handy constants
-// (Removed unused BLACK/WHITE constants to avoid dead-code warnings.)
\ No newline at end of file
+
+// Display (sRGB encoded) color of a blackbody, brightest channel 1, for
+// swatches and emitted colors. Light sources use the linear variants.
+pub fn blackbody(kelvin: f32) -> Color {
+blackbody_linear(kelvin).to_srgb()
+}
+
+
+#[cfg(test)]
+mod tests {
+use super::*;
+
+#[test]
+fn daylight_blackbody_is_near_white() {
+// D65 sits close to the Planckian locus at about 6500K
+let c = kelvin_to_linear_srgb(6500.0);
+for channel in [c.r, c.g, c.b] {
+assert!(channel > 0.85, "{} {} {}", c.r, c.g, c.b);
+}
+}
+
+#[test]
+fn blackbody_goes_from_red_to_blue_as_it_heats() {
+let warm = kelvin_to_linear_srgb(3000.0);
+assert!(warm.r > warm.g && warm.g > warm.b);
+let hot = kelvin_to_linear_srgb(10000.0);
+assert!(hot.b > hot.g && hot.g > hot.r);
+let mut last = 0.0;
+for kelvin in (3000..=10000).step_by(250) {
+let c = kelvin_to_linear_srgb(kelvin as f32);
+let blueness = c.b / c.r;
+assert!(blueness > last, "{kelvin}K");
+last = blueness;
+}
+}
+
+#[test]
+fn table_matches_the_spectral_integral() {
+for kelvin in [1000.0, 1850.0, 3000.0, 5778.0, 6500.0, 12345.0, 40000.0] {
+let table = blackbody_linear(kelvin);
+let direct = kelvin_to_linear_srgb(kelvin);
+for (a, b) in [(table.r, direct.r), (table.g, direct.g), (table.b, direct.b)] {
+assert!((a - b).abs() < 5e-3, "{kelvin}K: {a} vs {b}");
+}
+}
+}
+}
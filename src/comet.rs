@@ -0,0 +1,111 @@
+use nalgebra_glm as glm;
+use crate::math::{perpendiculars, Vec3};
+use crate::noise;
+use crate::orbit::{KeplerOrbit, SUN_GM};
+
+// Beyond this distance from the sun a comet is an inert snowball
+const ACTIVE_RADIUS: f32 = 6.0;
+// Oldest dust still visible in the tail, seconds since it left the nucleus
+const DUST_AGE: f32 = 4.0;
+const DUST_STEPS: usize = 12;
+
+// Soft round blob of light, composited additively. `color` is the radiance
+// at its center; it falls off as a Gaussian with standard deviation `radius`.
+pub struct Sprite {
+    pub pos: Vec3,
+    pub radius: f32,
+    pub color: Vec3,
+}
+
+pub struct Comet {
+    pub orbit: KeplerOrbit,
+    pub nucleus_radius: f32,
+    pub activity: f32,   // outgassing at unit distance from the sun
+    pub ion_color: Vec3,
+    pub dust_color: Vec3,
+    pub particles: usize, // sprites per tail
+    pub seed: u32,
+}
+
+impl Comet {
+    // Long-period comet that swings close by the sun, with the defaults
+    // for everything but its orbit
+    pub fn new(orbit: KeplerOrbit, seed: u32) -> Self {
+        Comet {
+            orbit,
+            nucleus_radius: 0.03,
+            activity: 1.0,
+            ion_color: glm::vec3(0.35, 0.6, 1.0),
+            dust_color: glm::vec3(1.0, 0.85, 0.55),
+            particles: 400,
+            seed,
+        }
+    }
+
+    // Outgassing rate at `time`: zero far out, growing with the sunlight
+    // the nucleus receives as it nears the sun
+    pub fn activity_at(&self, time: f32) -> f32 {
+        let r = glm::length(&self.orbit.position(time));
+        let onset = ((ACTIVE_RADIUS - r) / (ACTIVE_RADIUS * 0.4)).clamp(0.0, 1.0);
+        self.activity * onset * onset / (r * r).max(0.5)
+    }
+
+    // Coma and tail sprites at `time`. Each particle keeps its random
+    // parameters from frame to frame, so the tails move smoothly.
+    pub fn sprites(&self, time: f32, out: &mut Vec<Sprite>) {
+        let activity = self.activity_at(time);
+        if activity <= 1e-3 {
+            return;
+        }
+        let nucleus = self.orbit.position(time);
+        let r = glm::length(&nucleus);
+        let anti_sun = nucleus / r;
+
+        // Coma: a bright core inside a larger, fainter glow
+        let coma = 0.08 + 0.12 * activity.sqrt();
+        out.push(Sprite { pos: nucleus, radius: coma * 0.3, color: glm::vec3(0.9, 1.0, 0.95) * activity.min(2.0) });
+        out.push(Sprite { pos: nucleus, radius: coma, color: glm::vec3(0.45, 0.75, 0.7) * (0.25 * activity.min(2.0)) });
+
+        // Ion tail: ionized gas carried straight away from the sun by the
+        // solar wind, thin and long, with slowly waving streamers
+        let (side_a, side_b) = perpendiculars(&anti_sun);
+        let ion_length = 2.5 * activity.sqrt();
+        for i in 0..self.particles {
+            let h = noise::hash_u32(self.seed ^ (i as u32).wrapping_mul(0x27d4_eb2f));
+            let s = noise::hash_f32(h, 0);
+            let streamer = noise::hash_f32(h, 1) * 2.0 * std::f32::consts::PI;
+            let wave = 0.04 * s * (s * 9.0 - time * 1.5 + streamer).sin();
+            let spread = 0.05 * s * (noise::hash_f32(h, 2) - 0.5);
+            let pos = nucleus + anti_sun * (s * ion_length) + side_a * (wave + spread) + side_b * (spread * streamer.cos());
+            let fade = (1.0 - s) * (1.0 - s);
+            out.push(Sprite { pos, radius: 0.03 + 0.05 * s, color: self.ion_color * (0.05 * activity * fade) });
+        }
+
+        // Dust tail: grains leave the nucleus with its orbital velocity,
+        // then feel a sun whose gravity is weakened by radiation pressure
+        // (by the fraction beta). Grains released earlier drift further out
+        // and fall behind, which bends the tail back along the orbit.
+        for i in 0..self.particles {
+            let h = noise::hash_u32(self.seed ^ 0xd057 ^ (i as u32).wrapping_mul(0x1656_67b1));
+            let age = DUST_AGE * noise::hash_f32(h, 0);
+            let beta = 0.15 + 0.75 * noise::hash_f32(h, 1);
+            let released = time - age;
+            let emitted = self.activity_at(released);
+            if emitted <= 1e-3 {
+                continue;
+            }
+            let mut pos = self.orbit.position(released);
+            let kick = glm::vec3(noise::hash_f32(h, 2) - 0.5, noise::hash_f32(h, 3) - 0.5, noise::hash_f32(h, 4) - 0.5) * 0.06;
+            let mut vel = self.orbit.velocity(released) + kick;
+            let gm = SUN_GM * (1.0 - beta);
+            let dt = age / DUST_STEPS as f32;
+            for _ in 0..DUST_STEPS {
+                let d2 = glm::dot(&pos, &pos).max(0.25);
+                vel -= pos * (gm / (d2 * d2.sqrt())) * dt;
+                pos += vel * dt;
+            }
+            let fade = 1.0 - age / DUST_AGE;
+            out.push(Sprite { pos, radius: 0.05 + 0.1 * age / DUST_AGE, color: self.dust_color * (0.035 * emitted * fade) });
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use crate::math::Vec3;
+use crate::renderer::BodyId;
+use crate::shader::PbrMaterial;
+use nalgebra_glm as glm;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+// Settings read from a scene file. Anything the file leaves out keeps the
+// program's default.
+#[derive(Default)]
+pub struct SceneConfig {
+    pub seed: Option<u64>,
+    pub catalog: Option<PathBuf>,
+    pub models: Vec<ModelPlacement>,
+    pub ray_depth: Option<u32>,
+    pub shutter: Option<f32>,
+    pub motion_samples: Option<u32>,
+    pub rocky_material: MaterialSettings, // `rocky_*` keys
+    pub gas_material: MaterialSettings,   // `gas_*` keys
+}
+
+// A model file to put in orbit. `model = <file>` starts one; the `model_*`
+// keys after it adjust the latest.
+pub struct ModelPlacement {
+    pub path: PathBuf,
+    pub parent: BodyId,    // body it circles: the sun or a planet
+    pub orbit_radius: f32,
+    pub radius: f32,       // the model is scaled to reach this far from its center
+    pub material: MaterialSettings,
+}
+
+// Overrides for a body's PbrMaterial, from the `<body>_albedo`,
+// `_roughness`, `_metallic`, `_diffuse_roughness`, `_emission`,
+// `_transmission` and `_ior` keys.
+// Colors are three numbers.
+#[derive(Default)]
+pub struct MaterialSettings {
+    pub albedo: Option<Vec3>,
+    pub roughness: Option<f32>,
+    pub metallic: Option<f32>,
+    pub diffuse_roughness: Option<f32>,
+    pub emission: Option<Vec3>,
+    pub transmission: Option<f32>,
+    pub ior: Option<f32>,
+}
+
+impl MaterialSettings {
+    pub fn apply(&self, material: &mut PbrMaterial) {
+        if let Some(albedo) = self.albedo {
+            material.albedo = albedo;
+        }
+        if let Some(roughness) = self.roughness {
+            material.roughness = roughness;
+        }
+        if let Some(metallic) = self.metallic {
+            material.metallic = metallic;
+        }
+        if let Some(diffuse_roughness) = self.diffuse_roughness {
+            material.diffuse_roughness = diffuse_roughness;
+        }
+        if let Some(emission) = self.emission {
+            material.emission = emission;
+        }
+        if let Some(transmission) = self.transmission {
+            material.transmission = transmission;
+        }
+        // Reflectance follows the index, as in PbrMaterial::dielectric
+        if let Some(ior) = self.ior {
+            material.ior = ior;
+            material.reflectance = ((ior - 1.0) / (ior + 1.0)).powi(2);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ConfigError {
+    pub source: String, // file the settings came from
+    pub line: usize,    // 1-based, 0 when the file could not be read at all
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: {}", self.source, self.message)
+        } else {
+            write!(f, "{}:{}: {}", self.source, self.line, self.message)
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+pub fn load_scene_file(path: &Path) -> Result<SceneConfig, ConfigError> {
+    let source = path.display().to_string();
+    let text = std::fs::read_to_string(path).map_err(|e| ConfigError {
+        source: source.clone(),
+        line: 0,
+        message: e.to_string(),
+    })?;
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+    parse_scene_file(&text, &source, base_dir)
+}
+
+// Parse `key = value` lines; '#' starts a comment. Relative paths are taken
+// relative to `base_dir`, normally the directory holding the scene file.
+pub fn parse_scene_file(text: &str, source: &str, base_dir: &Path) -> Result<SceneConfig, ConfigError> {
+    let err = |line: usize, message: String| ConfigError { source: source.to_string(), line, message };
+    let mut config = SceneConfig::default();
+    for (i, raw) in text.lines().enumerate() {
+        let line = i + 1;
+        let content = raw.split('#').next().unwrap_or("").trim();
+        if content.is_empty() {
+            continue;
+        }
+        let (key, value) = content
+            .split_once('=')
+            .ok_or_else(|| err(line, format!("expected `key = value`, found {:?}", content)))?;
+        let value = value.trim().trim_matches('"');
+        match key.trim() {
+            "seed" => config.seed = Some(parse_seed(value).ok_or_else(|| err(line, format!("invalid seed {:?}", value)))?),
+            "catalog" => config.catalog = Some(base_dir.join(value)),
+            "ray_depth" => config.ray_depth = Some(value.parse().map_err(|_| err(line, format!("invalid ray_depth {:?}", value)))?),
+            "shutter" => config.shutter = Some(value.parse().map_err(|_| err(line, format!("invalid shutter {:?}", value)))?),
+            "motion_samples" => config.motion_samples = Some(value.parse().map_err(|_| err(line, format!("invalid motion_samples {:?}", value)))?),
+            // A small craft in a low orbit around the rocky planet
+            "model" => config.models.push(ModelPlacement {
+                path: base_dir.join(value),
+                parent: BodyId::Rocky,
+                orbit_radius: 0.8,
+                radius: 0.05,
+                material: MaterialSettings::default(),
+            }),
+            key @ ("model_parent" | "model_orbit" | "model_radius") => {
+                let model = config.models.last_mut().ok_or_else(|| err(line, format!("{} before any model", key)))?;
+                let number = || value.parse::<f32>().ok().filter(|v| *v > 0.0).ok_or_else(|| err(line, format!("invalid {} {:?}", key, value)));
+                match key {
+                    "model_parent" => {
+                        model.parent = match value {
+                            "sun" => BodyId::Sun,
+                            "rocky" => BodyId::Rocky,
+                            "gas" => BodyId::Gas,
+                            _ => return Err(err(line, format!("unknown parent {:?}, expected sun, rocky or gas", value))),
+                        }
+                    }
+                    "model_orbit" => model.orbit_radius = number()?,
+                    _ => model.radius = number()?,
+                }
+            }
+            key if material_key(key).is_some() => {
+                let (body, property) = material_key(key).unwrap_or_default();
+                let settings = match body {
+                    "rocky" => &mut config.rocky_material,
+                    "gas" => &mut config.gas_material,
+                    _ => &mut config.models.last_mut().ok_or_else(|| err(line, format!("{} before any model", key)))?.material,
+                };
+                let invalid = || err(line, format!("invalid {} {:?}", key, value));
+                match property {
+                    "albedo" => settings.albedo = Some(parse_color(value).ok_or_else(invalid)?),
+                    "emission" => settings.emission = Some(parse_color(value).ok_or_else(invalid)?),
+                    "ior" => settings.ior = Some(value.parse::<f32>().ok().filter(|v| *v >= 1.0).ok_or_else(invalid)?),
+                    _ => {
+                        let number = value.parse::<f32>().ok().filter(|v| (0.0..=1.0).contains(v)).ok_or_else(invalid)?;
+                        match property {
+                            "roughness" => settings.roughness = Some(number),
+                            "metallic" => settings.metallic = Some(number),
+                            "diffuse_roughness" => settings.diffuse_roughness = Some(number),
+                            _ => settings.transmission = Some(number),
+                        }
+                    }
+                }
+            }
+            other => return Err(err(line, format!("unknown setting {:?}", other))),
+        }
+    }
+    Ok(config)
+}
+
+// The body and property of a material key, such as ("rocky",
+// "diffuse_roughness"). Properties may hold underscores themselves, so the
+// body is matched as a prefix rather than split off.
+fn material_key(key: &str) -> Option<(&str, &str)> {
+    let (body, property) = ["rocky", "gas", "model"]
+        .into_iter()
+        .find_map(|body| Some((body, key.strip_prefix(body)?.strip_prefix('_')?)))?;
+    matches!(property, "albedo" | "roughness" | "metallic" | "diffuse_roughness" | "emission" | "transmission" | "ior").then_some((body, property))
+}
+
+// Colors are three non-negative numbers, linear RGB, separated by spaces
+// or commas
+fn parse_color(text: &str) -> Option<Vec3> {
+    let parts: Vec<f32> = text
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|part| !part.is_empty())
+        .map(|part| part.parse().ok().filter(|v: &f32| *v >= 0.0))
+        .collect::<Option<_>>()?;
+    match parts[..] {
+        [r, g, b] => Some(glm::vec3(r, g, b)),
+        _ => None,
+    }
+}
+
+// Seeds are written in decimal or as 0x-prefixed hex
+pub fn parse_seed(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
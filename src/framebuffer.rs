@@ -41,4 +41,27 @@ self.pixels[idx] = color;
 		let idx = y * self.width + x;
 		self.pixels[idx] = self.pixels[idx].add(&color);
 	}
-}
\ No newline at end of file
+
+
+// Additive light that is hidden behind anything closer than `z`, without
+// writing depth itself (glows, particle sprites).
+pub fn add_light_at(&mut self, x: i32, y: i32, z: f32, color: Color) {
+if x < 0 || y < 0 { return; }
+let x = x as usize;
+let y = y as usize;
+if x >= self.width || y >= self.height { return; }
+let idx = y * self.width + x;
+if z < self.depth[idx] {
+self.pixels[idx] = self.pixels[idx].add(&color);
+}
+}
+
+
+// Pack the pixels as 0RGB, the layout minifb windows expect
+pub fn to_u32(&self) -> Vec<u32> {
+self.pixels.iter().map(|c| {
+let [r, g, b] = c.clamp_u8();
+((r as u32) << 16) | ((g as u32) << 8) | b as u32
+}).collect()
+}
+}
@@ -0,0 +1,106 @@
+use nalgebra_glm as glm;
+use crate::catalog::equatorial_to_dir;
+use crate::color;
+use crate::math::Vec3;
+use crate::noise;
+
+// Colored emission nebula painted onto the sky
+pub struct Nebula {
+    pub dir: Vec3,    // unit direction of its center
+    pub radius: f32,  // angular radius, radians
+    pub color: Vec3,
+    pub intensity: f32,
+}
+
+// Procedural Milky Way: a diffuse glow along the great circle whose pole is
+// `pole`, brightest towards `center`, cut by dark dust lanes.
+pub struct MilkyWay {
+    pub pole: Vec3,       // galactic north pole (normal of the band's plane)
+    pub center: Vec3,     // direction of the galactic center, on the band
+    pub width: f32,       // angular half-width of the band, radians
+    pub brightness: f32,
+    pub dust: f32,        // 0 = no dust lanes, 1 = fully opaque lanes
+    pub nebulae: Vec<Nebula>,
+    pub noise_offset: Vec3, // shift of the noise domain, picks the cloud and dust pattern
+}
+
+impl Default for MilkyWay {
+    // Oriented like the real galaxy relative to the bundled star catalog.
+    // Nebulae sit at the Orion, Lagoon and Carina nebulae, drawn a few times
+    // larger than life so they read at this resolution.
+    fn default() -> Self {
+        let pole = equatorial_to_dir(192.86_f32.to_radians(), 27.13_f32.to_radians());
+        let center = equatorial_to_dir(266.40_f32.to_radians(), (-28.94_f32).to_radians());
+        let h_alpha = glm::vec3(1.0, 0.35, 0.45);
+        MilkyWay {
+            pole,
+            center,
+            width: 0.16,
+            brightness: 0.12,
+            dust: 0.75,
+            nebulae: vec![
+                Nebula { dir: equatorial_to_dir(83.82_f32.to_radians(), (-5.39_f32).to_radians()), radius: 0.05, color: h_alpha, intensity: 0.25 },
+                Nebula { dir: equatorial_to_dir(270.90_f32.to_radians(), (-24.38_f32).to_radians()), radius: 0.06, color: h_alpha, intensity: 0.2 },
+                Nebula { dir: equatorial_to_dir(161.26_f32.to_radians(), (-59.87_f32).to_radians()), radius: 0.09, color: glm::vec3(1.0, 0.45, 0.4), intensity: 0.22 },
+            ],
+            noise_offset: glm::vec3(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+impl MilkyWay {
+    // Band profile in [0, 1] at a sky direction, peaking on the galactic plane
+    pub fn band(&self, dir: &Vec3) -> f32 {
+        let b = glm::dot(dir, &self.pole).clamp(-1.0, 1.0).asin();
+        (-(b / self.width) * (b / self.width)).exp()
+    }
+
+    // Relative density of field stars, used to cluster generated stars
+    // towards the band. 1 on the plane, 0.25 towards the galactic poles.
+    pub fn star_density(&self, dir: &Vec3) -> f32 {
+        0.25 + 0.75 * self.band(dir)
+    }
+
+    // Diffuse sky radiance (band plus nebulae) along a direction
+    pub fn radiance(&self, dir: &Vec3) -> Vec3 {
+        let mut light = glm::vec3(0.0, 0.0, 0.0);
+        let pattern_dir = dir + self.noise_offset;
+
+        let band = self.band(dir);
+        if band > 1e-3 {
+            // Brighter, warmer bulge towards the galactic center
+            let to_center = glm::dot(dir, &self.center).clamp(-1.0, 1.0).acos();
+            let bulge = (-(to_center / 0.6) * (to_center / 0.6)).exp();
+            let tint = color::blackbody(7500.0 - 3000.0 * bulge).to_vec3();
+
+            // Patchy unresolved starlight
+            let clouds = noise::fbm(&(pattern_dir * 5.0), 4);
+            let glow = band * (0.4 + clouds) * (1.0 + 2.5 * bulge);
+
+            // Dust lanes hug the midplane, thinner than the glow itself
+            let b = glm::dot(dir, &self.pole);
+            let lane = (-(b / (self.width * 0.35)) * (b / (self.width * 0.35))).exp();
+            let dust = noise::fbm(&(pattern_dir * 11.0 + glm::vec3(3.1, 7.7, 1.3)), 4);
+            let absorb = 1.0 - self.dust * lane * ((dust - 0.35) * 3.0).clamp(0.0, 1.0);
+
+            light += tint * (glow * absorb * self.brightness);
+        }
+
+        for nebula in &self.nebulae {
+            let d = glm::dot(dir, &nebula.dir);
+            let cos_reach = (nebula.radius * 2.5).min(std::f32::consts::PI).cos();
+            if d < cos_reach {
+                continue;
+            }
+            let theta = d.clamp(-1.0, 1.0).acos() / nebula.radius;
+            // Wispy structure: warped noise inside a soft round envelope
+            let p = pattern_dir / nebula.radius;
+            let warp = noise::fbm(&(p * 0.8), 3);
+            let wisps = noise::fbm(&(p * 1.5 + glm::vec3(warp, warp, warp) * 2.0), 4);
+            let shape = (-theta * theta).exp() * ((wisps - 0.3) * 2.0).clamp(0.0, 1.0);
+            light += nebula.color * (shape * nebula.intensity);
+        }
+
+        light
+    }
+}
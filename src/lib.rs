@@ -0,0 +1,21 @@
+pub mod asteroids;
+pub mod bvh;
+pub mod catalog;
+pub mod color;
+pub mod comet;
+pub mod config;
+pub mod framebuffer;
+pub mod galaxy;
+pub mod json;
+pub mod lod;
+pub mod math;
+pub mod mesh;
+pub mod model_io;
+pub mod noise;
+pub mod orbit;
+pub mod pathtracer;
+pub mod raster;
+pub mod renderer;
+pub mod sdf;
+pub mod shader;
+pub mod starfield;
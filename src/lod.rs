@@ -0,0 +1,284 @@
+use nalgebra_glm as glm;
+use crate::math::{self, Vec3};
+use crate::mesh::{Mesh, Vertex, CUBE_FACES};
+use std::collections::HashSet;
+use std::f32::consts::FRAC_PI_2;
+
+// Step used to find surface normals from the height field, in radians
+const NORMAL_STEP: f32 = 1e-3;
+
+// Chunked level of detail for a sphere tessellated like Mesh::cube_sphere:
+// every cube face is a quadtree of square patches, split wherever a
+// patch's geometric error would show up larger than `tolerance` pixels.
+// Neighbouring patches are kept within one level of each other, and a
+// patch next to a coarser one folds away the in-between vertices along
+// their shared edge, so levels meet edge to edge without cracks.
+pub struct SphereLod {
+    pub resolution: usize, // grid cells along a patch edge, rounded up to even
+    pub max_level: u32,    // deepest split, with patches 2^-max_level of a face edge across
+    pub tolerance: f32,    // largest geometric error allowed on screen, in pixels
+    pub relief: f32,       // tallest the surface rises or sinks, as a fraction of the radius
+}
+
+impl Default for SphereLod {
+    fn default() -> Self {
+        SphereLod { resolution: 8, max_level: 12, tolerance: 0.75, relief: 0.0 }
+    }
+}
+
+// One square of a cube face, `level` halvings deep, at column `x` and row
+// `y` counted right and down as seen from outside
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Patch {
+    pub face: usize, // into CUBE_FACES
+    pub level: u32,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl Patch {
+    fn children(&self) -> [Patch; 4] {
+        let (level, x, y) = (self.level + 1, self.x * 2, self.y * 2);
+        [(0, 0), (1, 0), (0, 1), (1, 1)].map(|(i, j)| Patch { face: self.face, level, x: x + i, y: y + j })
+    }
+}
+
+impl SphereLod {
+    // Patches to draw for a unit sphere seen from `eye`, given in the
+    // sphere's own frame and in units of its radius. `pixel_angle` is the
+    // angle one pixel spans.
+    pub fn patches(&self, eye: &Vec3, pixel_angle: f32) -> Vec<Patch> {
+        let mut patches: Vec<Patch> = self.leaves(eye, pixel_angle).into_iter().collect();
+        patches.sort_by_key(|p| (p.face, p.level, p.y, p.x));
+        patches
+    }
+
+    // Unit sphere pushed out by `height(direction)` along each direction,
+    // tessellated for a view from `eye`, as in `patches`. Patches are laid
+    // out in cube_sphere's texture atlas.
+    pub fn mesh(&self, eye: &Vec3, pixel_angle: f32, height: impl Fn(&Vec3) -> f32) -> Mesh {
+        let leaves = self.leaves(eye, pixel_angle);
+        let mut patches: Vec<&Patch> = leaves.iter().collect();
+        patches.sort_by_key(|p| (p.face, p.level, p.y, p.x));
+        let n = self.cells();
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        for patch in patches {
+            let (out, right, down) = CUBE_FACES[patch.face];
+            let (out, right, down) = (glm::make_vec3(&out), glm::make_vec3(&right), glm::make_vec3(&down));
+            let (column, row) = ((patch.face % 3) as f32, (patch.face / 3) as f32);
+            let first = vertices.len();
+            // Grid lines of the whole face at this level, as integers so that
+            // patches sharing an edge compute bit-identical vertices
+            let m = (n << patch.level) as i64;
+            for j in 0..=n {
+                for i in 0..=n {
+                    let (ks, kt) = ((patch.x as usize * n + i) as i64, (patch.y as usize * n + j) as i64);
+                    let (cu, cv) = ((2 * ks - m) as f32 / m as f32, (2 * kt - m) as f32 / m as f32);
+                    let d = glm::normalize(&(out + right * cu + down * cv));
+                    let p = d * (1.0 + height(&d));
+                    let normal = surface_normal(&d, &height);
+                    vertices.push(Vertex {
+                        pos: [p.x, p.y, p.z],
+                        normal: [normal.x, normal.y, normal.z],
+                        uv: [(column + ks as f32 / m as f32) / 3.0, (row + kt as f32 / m as f32) / 2.0],
+                        tangent: face_tangent(&normal, &right, &down),
+                    });
+                }
+            }
+            // Edges against a coarser patch: top, right, bottom, left. Their
+            // odd vertices fold into an even neighbour, so the triangles along
+            // the edge span the coarse patch's edges exactly. Top and left
+            // fold backwards and right and bottom forwards; otherwise two
+            // folds meeting at the bottom-right corner would flatten the
+            // corner cell's diagonal into a sliver.
+            let local = |i: usize, j: usize| j * (n + 1) + i;
+            let mut remap: Vec<usize> = (first..vertices.len()).collect();
+            for edge in 0..4 {
+                let coarser = self.neighbour(&leaves, patch, edge).is_some_and(|other| other.level < patch.level);
+                if !coarser {
+                    continue;
+                }
+                let along = |k: usize| match edge {
+                    0 => local(k, 0),
+                    1 => local(n, k),
+                    2 => local(k, n),
+                    _ => local(0, k),
+                };
+                for k in (1..n).step_by(2) {
+                    remap[along(k)] = first + along(if edge == 0 || edge == 3 { k - 1 } else { k + 1 });
+                }
+            }
+            for j in 0..n {
+                for i in 0..n {
+                    let (a, b) = (local(i, j), local(i, j + 1));
+                    // Counter-clockwise seen from outside, as in cube_sphere
+                    for triangle in [[a, b, a + 1], [a + 1, b, b + 1]] {
+                        let [a, b, c] = triangle.map(|v| remap[v]);
+                        // Folding flattens one triangle per odd vertex
+                        if a != b && b != c && c != a {
+                            indices.push([a, b, c]);
+                        }
+                    }
+                }
+            }
+        }
+        Mesh::new(vertices, indices)
+    }
+
+    fn cells(&self) -> usize {
+        (self.resolution.max(2) + 1) & !1
+    }
+
+    // Quadtree leaves: split by screen-space error, then balanced so that
+    // no leaf is more than one level finer than a neighbour
+    fn leaves(&self, eye: &Vec3, pixel_angle: f32) -> HashSet<Patch> {
+        let mut leaves = HashSet::new();
+        let mut stack: Vec<Patch> = (0..6).map(|face| Patch { face, level: 0, x: 0, y: 0 }).collect();
+        while let Some(patch) = stack.pop() {
+            if patch.level < self.max_level && self.screen_error(&patch, eye, pixel_angle) > self.tolerance {
+                stack.extend(patch.children());
+            } else {
+                leaves.insert(patch);
+            }
+        }
+        loop {
+            let too_coarse: HashSet<Patch> = leaves
+                .iter()
+                .flat_map(|patch| (0..4).filter_map(|edge| self.neighbour(&leaves, patch, edge).filter(|other| other.level + 1 < patch.level)))
+                .collect();
+            if too_coarse.is_empty() {
+                return leaves;
+            }
+            for patch in too_coarse {
+                leaves.remove(&patch);
+                leaves.extend(patch.children());
+            }
+        }
+    }
+
+    // Largest gap between the patch's grid and the surface, seen from the
+    // nearest the eye gets to the patch, in pixels. Chords sag below the
+    // sphere, and relief finer than a grid cell is missed; fractal terrain
+    // puts detail there in proportion to the cell's size.
+    fn screen_error(&self, patch: &Patch, eye: &Vec3, pixel_angle: f32) -> f32 {
+        let cell = FRAC_PI_2 / (self.cells() << patch.level) as f32;
+        let error = cell * cell / 8.0 + self.relief * cell;
+        let size = 1.0 / (1u32 << patch.level) as f32;
+        let (s0, t0) = (patch.x as f32 * size, patch.y as f32 * size);
+        let center = face_direction(patch.face, s0 + 0.5 * size, t0 + 0.5 * size);
+        let bound = [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0)]
+            .iter()
+            .map(|(i, j)| glm::distance(&center, &face_direction(patch.face, s0 + i * size, t0 + j * size)))
+            .fold(0.0, f32::max);
+        let distance = (glm::distance(eye, &center) - bound - self.relief).max(1e-6);
+        error / (distance * pixel_angle)
+    }
+
+    // Leaf across edge `edge` of `patch` (top, right, bottom, left), found
+    // just past the middle of the edge, which may be on another face
+    fn neighbour(&self, leaves: &HashSet<Patch>, patch: &Patch, edge: usize) -> Option<Patch> {
+        let size = 1.0 / (1u32 << patch.level) as f32;
+        let step = 0.5 / (1u32 << self.max_level) as f32;
+        let (s0, t0) = (patch.x as f32 * size, patch.y as f32 * size);
+        let (s, t) = match edge {
+            0 => (s0 + 0.5 * size, t0 - step),
+            1 => (s0 + size + step, t0 + 0.5 * size),
+            2 => (s0 + 0.5 * size, t0 + size + step),
+            _ => (s0 - step, t0 + 0.5 * size),
+        };
+        leaf_at(leaves, &face_direction(patch.face, s, t), self.max_level)
+    }
+}
+
+// Direction through the point (s, t) of a cube face, both 0..1 across it.
+// Points past the face's edges land on the neighbouring faces.
+fn face_direction(face: usize, s: f32, t: f32) -> Vec3 {
+    let (out, right, down) = CUBE_FACES[face];
+    glm::normalize(&(glm::make_vec3(&out) + glm::make_vec3(&right) * (2.0 * s - 1.0) + glm::make_vec3(&down) * (2.0 * t - 1.0)))
+}
+
+// Leaf holding direction `d`
+fn leaf_at(leaves: &HashSet<Patch>, d: &Vec3, max_level: u32) -> Option<Patch> {
+    let (face, (out, right, down)) = CUBE_FACES
+        .iter()
+        .enumerate()
+        .max_by(|a, b| glm::dot(d, &glm::make_vec3(&a.1 .0)).total_cmp(&glm::dot(d, &glm::make_vec3(&b.1 .0))))?;
+    let q = d / glm::dot(d, &glm::make_vec3(out));
+    let s = (glm::dot(&q, &glm::make_vec3(right)) + 1.0) * 0.5;
+    let t = (glm::dot(&q, &glm::make_vec3(down)) + 1.0) * 0.5;
+    (0..=max_level).find_map(|level| {
+        let count = 1u32 << level;
+        let cell = |c: f32| ((c * count as f32) as u32).min(count - 1);
+        let patch = Patch { face, level, x: cell(s), y: cell(t) };
+        leaves.contains(&patch).then_some(patch)
+    })
+}
+
+// Normal of the displaced sphere at direction `d`, from central
+// differences of the height field. The step does not depend on the
+// patch, so vertices shared between patches get the same normal.
+fn surface_normal(d: &Vec3, height: impl Fn(&Vec3) -> f32) -> Vec3 {
+    let (e1, e2) = math::perpendiculars(d);
+    let at = |v: Vec3| {
+        let d = glm::normalize(&v);
+        d * (1.0 + height(&d))
+    };
+    let du = at(d + e1 * NORMAL_STEP) - at(d - e1 * NORMAL_STEP);
+    let dv = at(d + e2 * NORMAL_STEP) - at(d - e2 * NORMAL_STEP);
+    let n = glm::normalize(&glm::cross(&du, &dv));
+    if glm::dot(&n, d) < 0.0 { -n } else { n }
+}
+
+// Tangent along the face's right direction, with cube_sphere's handedness
+fn face_tangent(normal: &Vec3, right: &Vec3, down: &Vec3) -> [f32; 4] {
+    let tangent = glm::normalize(&(right - normal * glm::dot(normal, right)));
+    let w = if glm::dot(&glm::cross(normal, &tangent), down) >= 0.0 { 1.0 } else { -1.0 };
+    [tangent.x, tangent.y, tangent.z, w]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    // Every directed edge, by vertex position, against the number of times
+    // it appears
+    fn directed_edges(mesh: &Mesh) -> HashMap<([u32; 3], [u32; 3]), usize> {
+        let key = |i: usize| mesh.vertices[i].pos.map(f32::to_bits);
+        let mut edges = HashMap::new();
+        for &[a, b, c] in &mesh.indices {
+            for (p, q) in [(a, b), (b, c), (c, a)] {
+                *edges.entry((key(p), key(q))).or_insert(0) += 1;
+            }
+        }
+        edges
+    }
+
+    #[test]
+    fn levels_meet_edge_to_edge() {
+        let lod = SphereLod { max_level: 7, ..SphereLod::default() };
+        // Close over a cube corner and over the middle of an edge, so levels
+        // change within faces and across them
+        let eyes = [glm::vec3(1.0, 1.0, 1.0).normalize() * 1.02, glm::vec3(1.0, -1.0, 0.0).normalize() * 1.1, glm::vec3(0.3, 0.2, 1.5)];
+        let heights: [&dyn Fn(&Vec3) -> f32; 2] = [&|_| 0.0, &|d| 0.02 * (d.x * 9.0).sin() * (d.y * 7.0 + d.z).cos()];
+        for eye in &eyes {
+            let levels: HashSet<u32> = lod.patches(eye, 1e-3).iter().map(|p| p.level).collect();
+            assert!(levels.len() > 2, "levels {:?}", levels);
+            for height in heights {
+                let mesh = lod.mesh(eye, 1e-3, height);
+                let edges = directed_edges(&mesh);
+                // Closed and without T-junctions: each edge is used once
+                // each way, and by nothing that only covers part of it
+                for (&(p, q), &count) in &edges {
+                    assert_eq!(count, 1, "edge {:?} {:?} used {} times", p, q, count);
+                    assert_eq!(edges.get(&(q, p)), Some(&1), "edge {:?} {:?} has no twin", p, q);
+                }
+                for &[a, b, c] in &mesh.indices {
+                    let [a, b, c] = [a, b, c].map(|i| glm::make_vec3(&mesh.vertices[i].pos));
+                    assert!(glm::dot(&glm::cross(&(b - a), &(c - a)), &(a + b + c)) > 0.0);
+                }
+            }
+        }
+    }
+}
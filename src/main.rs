@@ -1,11 +1,162 @@
-mod math;
-mod renderer;
-mod shader;
+use minifb::{Key, KeyRepeat, Window, WindowOptions};
+use lab5::catalog;
+use lab5::config::{self, SceneConfig};
+use lab5::mesh::Model;
+use lab5::model_io;
+use lab5::pathtracer::{Accumulator, PathTraceOptions};
+use lab5::renderer::{Backend, BakeOptions, BodyId, MeshBody, Scene, DEFAULT_SEED, WIDTH, HEIGHT};
+use std::path::{Path, PathBuf};
 
-use minifb::{Key, Window, WindowOptions};
-use renderer::{Scene, WIDTH, HEIGHT};
+fn exit_with(message: impl std::fmt::Display) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
+
+// sun, rocky, gas, comet<i>, model<i> or sdf<i>, counting from 0
+fn parse_body(text: &str) -> Option<BodyId> {
+    match text {
+        "sun" => Some(BodyId::Sun),
+        "rocky" => Some(BodyId::Rocky),
+        "gas" => Some(BodyId::Gas),
+        _ => {
+            if let Some(i) = text.strip_prefix("comet") {
+                i.parse().ok().map(BodyId::Comet)
+            } else if let Some(i) = text.strip_prefix("sdf") {
+                i.parse().ok().map(BodyId::Sdf)
+            } else {
+                text.strip_prefix("model")?.parse().ok().map(BodyId::Mesh)
+            }
+        }
+    }
+}
 
 fn main() {
+    // --scene <file> reads settings from a scene file; --seed <n> and
+    // --catalog <file.csv> override what it says. --raster starts with the
+    // rasterizer instead of the ray caster. --export <body> <file.obj|.ply>
+    // writes one body as a mesh with its shading baked in, and exits.
+    // --path-trace <file.png> path traces a reference still, saving it
+    // after every pass, and exits; --passes <n> and --bounces <n> set how
+    // many passes it takes and how many times paths bounce. --record <dir>
+    // <frames> writes an animation as numbered PNG frames, --frame-step <s>
+    // scene seconds apart, and exits. --shutter <s> keeps each exposure
+    // open that many scene seconds, blurring whatever moves meanwhile.
+    let mut scene_path = None;
+    let mut backend = Backend::RayCasting;
+    let mut seed = None;
+    let mut catalog_path = None;
+    let mut export = None;
+    let mut reference = None;
+    let mut trace_options = PathTraceOptions::default();
+    let mut passes = 64;
+    let mut record = None;
+    let mut frame_step = 0.016;
+    let mut shutter = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--scene" => scene_path = args.next(),
+            "--seed" => {
+                let value = args.next().unwrap_or_default();
+                seed = Some(config::parse_seed(&value).unwrap_or_else(|| exit_with(format!("invalid seed {:?}", value))));
+            }
+            "--catalog" => catalog_path = args.next().map(PathBuf::from),
+            "--raster" => backend = Backend::Rasterization,
+            "--export" => {
+                let value = args.next().unwrap_or_default();
+                let body = parse_body(&value).unwrap_or_else(|| exit_with(format!("unknown body {:?}, expected sun, rocky, gas, comet<n>, model<n> or sdf<n>", value)));
+                let path = args.next().unwrap_or_else(|| exit_with("--export needs a body and a file"));
+                export = Some((body, PathBuf::from(path)));
+            }
+            "--path-trace" => reference = Some(PathBuf::from(args.next().unwrap_or_else(|| exit_with("--path-trace needs a file")))),
+            "--passes" | "--bounces" => {
+                let value = args.next().unwrap_or_default();
+                let n = value.parse().unwrap_or_else(|_| exit_with(format!("invalid {} {:?}", arg, value)));
+                if arg == "--passes" {
+                    passes = n;
+                } else {
+                    trace_options.max_bounces = n;
+                }
+            }
+            "--record" => {
+                let dir = args.next().unwrap_or_else(|| exit_with("--record needs a directory and a frame count"));
+                let value = args.next().unwrap_or_default();
+                let frames: u32 = value.parse().unwrap_or_else(|_| exit_with(format!("invalid frame count {:?}", value)));
+                record = Some((PathBuf::from(dir), frames));
+            }
+            "--frame-step" | "--shutter" => {
+                let value = args.next().unwrap_or_default();
+                let seconds: f32 = value.parse().unwrap_or_else(|_| exit_with(format!("invalid {} {:?}", arg, value)));
+                if arg == "--frame-step" {
+                    frame_step = seconds;
+                } else {
+                    shutter = Some(seconds);
+                }
+            }
+            other => eprintln!("ignoring unknown argument {}", other),
+        }
+    }
+
+    let settings = match scene_path {
+        Some(path) => config::load_scene_file(Path::new(&path)).unwrap_or_else(|e| exit_with(e)),
+        None => SceneConfig::default(),
+    };
+    let seed = seed.or(settings.seed).unwrap_or(DEFAULT_SEED);
+    let stars = match catalog_path.or(settings.catalog) {
+        Some(path) => catalog::load_catalog(&path).unwrap_or_else(|e| exit_with(e)),
+        None => catalog::bundled(),
+    };
+    let mut scene = Scene::with_catalog(stars, seed);
+    scene.backend = backend;
+    for placement in &settings.models {
+        let mesh = model_io::load_model(&placement.path).unwrap_or_else(|e| exit_with(e));
+        let mut body = MeshBody::orbiting(Model::new(mesh), placement.parent, placement.orbit_radius, placement.radius);
+        placement.material.apply(&mut body.material);
+        scene.mesh_bodies.push(body);
+    }
+    settings.rocky_material.apply(&mut scene.rocky_material);
+    settings.gas_material.apply(&mut scene.gas_material);
+    if let Some(depth) = settings.ray_depth {
+        scene.ray_depth = depth;
+    }
+    if let Some(seconds) = shutter.or(settings.shutter) {
+        scene.shutter = seconds;
+    }
+    if let Some(samples) = settings.motion_samples {
+        scene.motion_samples = samples;
+    }
+
+    if let Some((body, path)) = export {
+        let mesh = scene.bake_body(body, &BakeOptions::default()).unwrap_or_else(|| exit_with(format!("no {:?} in the scene", body)));
+        model_io::save_model(&mesh, &path).unwrap_or_else(|e| exit_with(e));
+        return;
+    }
+
+    if let Some(path) = reference {
+        trace_options.seed = seed;
+        let mut image = Accumulator::new(WIDTH, HEIGHT);
+        let start = std::time::Instant::now();
+        while image.passes() < passes {
+            scene.path_trace_pass(&trace_options, &mut image);
+            image.save(&path).unwrap_or_else(|e| exit_with(format!("{}: {}", path.display(), e)));
+            eprintln!("pass {}/{}, {:.1} s", image.passes(), passes, start.elapsed().as_secs_f64());
+        }
+        return;
+    }
+
+    if let Some((dir, frames)) = record {
+        std::fs::create_dir_all(&dir).unwrap_or_else(|e| exit_with(format!("{}: {}", dir.display(), e)));
+        let start = std::time::Instant::now();
+        for i in 0..frames {
+            let mut image = Accumulator::new(WIDTH, HEIGHT);
+            image.add(&scene.render_exposure(i as f32 * frame_step));
+            let path = dir.join(format!("frame_{:04}.png", i));
+            image.save(&path).unwrap_or_else(|e| exit_with(format!("{}: {}", path.display(), e)));
+            eprintln!("frame {}/{}, {:.1} s", i + 1, frames, start.elapsed().as_secs_f64());
+        }
+        return;
+    }
+
     let mut window = Window::new(
         "Sistema Solar 3D",
         WIDTH,
@@ -19,7 +170,6 @@ fn main() {
     .unwrap();
 
 
-    let mut scene = Scene::new();
     let mut time = 0.0;
 
     while window.is_open() && !window.is_key_down(Key::Escape) {
@@ -31,8 +181,19 @@ fn main() {
             scene.sky_rotation += 0.03;
         }
 
+        // B switches between the ray caster and the rasterizer
+        if window.is_key_pressed(Key::B, KeyRepeat::No) {
+            scene.backend = match scene.backend {
+                Backend::RayCasting => Backend::Rasterization,
+                Backend::Rasterization => Backend::RayCasting,
+            };
+        }
+
+        let start = std::time::Instant::now();
         let buffer = scene.render(time);
+        let elapsed = start.elapsed();
+        window.set_title(&format!("Sistema Solar 3D - {:?} {:.1} ms", scene.backend, elapsed.as_secs_f64() * 1000.0));
         window.update_with_buffer(&buffer, WIDTH, HEIGHT).unwrap();
         time += 0.016; // Aproximadamente 60 FPS
     }
-}
\ No newline at end of file
+}
@@ -27,6 +27,30 @@ impl Camera {
         }
     }
 
+    // Angle one pixel spans at the center of an image `height` pixels tall
+    pub fn pixel_angle(&self, height: usize) -> f32 {
+        2.0 * (self.fov * 0.5).tan() / height as f32
+    }
+
+    // World to clip space
+    pub fn view_projection(&self) -> Mat4 {
+        let view = glm::look_at(&self.position, &self.target, &self.up);
+        let proj = glm::perspective(self.aspect, self.fov, 0.1, 100.0);
+        proj * view
+    }
+
+    // Screen position (u, v in 0..1, matching get_ray) and distance from
+    // the camera of a world point, or None when it is behind the camera
+    pub fn project(&self, p: &Vec3) -> Option<(f32, f32, f32)> {
+        let clip = self.view_projection() * glm::vec4(p.x, p.y, p.z, 1.0);
+        if clip.w <= 1e-4 {
+            return None;
+        }
+        let u = (clip.x / clip.w + 1.0) * 0.5;
+        let v = (clip.y / clip.w + 1.0) * 0.5;
+        Some((u, v, glm::distance(p, &self.position)))
+    }
+
     pub fn get_ray(&self, u: f32, v: f32) -> Ray {
         let view = glm::look_at(&self.position, &self.target, &self.up);
         let proj = glm::perspective(self.aspect, self.fov, 0.1, 100.0);
@@ -54,6 +78,7 @@ impl Sphere {
         Sphere { center, radius }
     }
 
+    // Distance to the near side, or to the far side from inside
     pub fn intersect(&self, ray: &Ray) -> Option<f32> {
         let oc = ray.origin - self.center;
         let a = glm::dot(&ray.direction, &ray.direction);
@@ -68,7 +93,8 @@ impl Sphere {
             if t > 0.0 {
                 Some(t)
             } else {
-                None
+                let far = (-b + discriminant.sqrt()) / (2.0 * a);
+                (far > 0.0).then_some(far)
             }
         }
     }
@@ -76,4 +102,11 @@ impl Sphere {
     pub fn normal_at(&self, point: &Vec3) -> Vec3 {
         glm::normalize(&(point - self.center))
     }
-}
\ No newline at end of file
+}
+
+// Two unit vectors perpendicular to `v` and to each other
+pub fn perpendiculars(v: &Vec3) -> (Vec3, Vec3) {
+    let helper = if v.y.abs() < 0.9 { glm::vec3(0.0, 1.0, 0.0) } else { glm::vec3(1.0, 0.0, 0.0) };
+    let a = glm::normalize(&glm::cross(v, &helper));
+    (a, glm::cross(v, &a))
+}
@@ -1,16 +1,82 @@
+use nalgebra_glm as glm;
+use crate::bvh::{Aabb, Bvh, Hit};
+use crate::math::{self, Ray, Vec3};
+use std::collections::HashMap;
+
+
 pub struct Vertex {
 pub pos: [f32; 3],
 pub normal: [f32; 3],
+pub uv: [f32; 2], // texture coordinates, (0, 0) at the image's top-left corner
+// Unit vector along increasing u. w is +1 or -1: the bitangent, along
+// increasing v, is w * cross(normal, tangent).
+pub tangent: [f32; 4],
+}
+
+
+// Image sampled with repeating, bilinear lookups
+pub struct Texture {
+pub width: usize,
+pub height: usize,
+pub texels: Vec<Vec3>, // rows from the top, RGB in 0..1
+}
+
+
+impl Texture {
+pub fn from_image(image: &image::DynamicImage) -> Self {
+let rgb = image.to_rgb8();
+let texels = rgb.pixels().map(|p| glm::vec3(p[0] as f32, p[1] as f32, p[2] as f32) / 255.0).collect();
+Self { width: rgb.width() as usize, height: rgb.height() as usize, texels }
+}
+
+
+pub fn sample(&self, uv: [f32; 2]) -> Vec3 {
+let x = uv[0].rem_euclid(1.0) * self.width as f32 - 0.5;
+let y = uv[1].rem_euclid(1.0) * self.height as f32 - 0.5;
+let (fx, fy) = (x - x.floor(), y - y.floor());
+let texel = |i: f32, j: f32| {
+let i = (i as i64).rem_euclid(self.width as i64) as usize;
+let j = (j as i64).rem_euclid(self.height as i64) as usize;
+self.texels[j * self.width + i]
+};
+let (x0, y0) = (x.floor(), y.floor());
+let top = texel(x0, y0) * (1.0 - fx) + texel(x0 + 1.0, y0) * fx;
+let bottom = texel(x0, y0 + 1.0) * (1.0 - fx) + texel(x0 + 1.0, y0 + 1.0) * fx;
+top * (1.0 - fy) + bottom * fy
+}
+}
+
+
+pub struct Material {
+pub base_color: Vec3,
+pub texture: Option<Texture>, // multiplies base_color
+}
+
+
+impl Default for Material {
+fn default() -> Self {
+Self { base_color: glm::vec3(1.0, 1.0, 1.0), texture: None }
+}
 }
 
 
 pub struct Mesh {
 pub vertices: Vec<Vertex>,
 pub indices: Vec<[usize;3]>,
+pub materials: Vec<Material>,
+pub triangle_materials: Vec<usize>, // one entry per triangle, into `materials`
+pub colors: Option<Vec<Vec3>>, // one RGB color per vertex, 0..1 as displayed, if the mesh has them
 }
 
 
 impl Mesh {
+// Triangles with one plain material
+pub fn new(vertices: Vec<Vertex>, indices: Vec<[usize;3]>) -> Self {
+let triangle_materials = vec![0; indices.len()];
+Self { vertices, indices, materials: vec![Material::default()], triangle_materials, colors: None }
+}
+
+
 pub fn uv_sphere(u: usize, v: usize, radius: f32) -> Self {
 let mut vertices = Vec::new();
 for j in 0..=v {
@@ -22,7 +88,9 @@ let y = radius * phi.cos();
 let z = radius * phi.sin() * theta.sin();
 let len = (x*x + y*y + z*z).sqrt();
 let nx = x / len; let ny = y / len; let nz = z / len;
-vertices.push(Vertex { pos: [x,y,z], normal: [nx,ny,nz] });
+let uv = [i as f32 / u as f32, j as f32 / v as f32];
+let tangent = sphere_tangent(&glm::vec3(nx, ny, nz));
+vertices.push(Vertex { pos: [x,y,z], normal: [nx,ny,nz], uv, tangent });
 }
 }
 let mut indices = Vec::new();
@@ -31,10 +99,465 @@ for j in 0..v {
 for i in 0..u {
 let a = j * width + i;
 let b = (j+1) * width + i;
+// Counter-clockwise seen from outside
+indices.push([a, a+1, b]);
+indices.push([b, a+1, b+1]);
+}
+}
+Self::new(vertices, indices)
+}
+
+
+// Subdivided icosahedron: triangles of nearly equal size all over, and
+// no pinched poles. Each subdivision splits every triangle in four,
+// starting from 20. Texture coordinates follow uv_sphere's mapping, with
+// the vertices on the seam doubled.
+pub fn icosphere(subdivisions: usize, radius: f32) -> Self {
+let t = (1.0 + 5.0f32.sqrt()) / 2.0;
+let mut points: Vec<Vec3> = [
+(-1.0, t, 0.0), (1.0, t, 0.0), (-1.0, -t, 0.0), (1.0, -t, 0.0),
+(0.0, -1.0, t), (0.0, 1.0, t), (0.0, -1.0, -t), (0.0, 1.0, -t),
+(t, 0.0, -1.0), (t, 0.0, 1.0), (-t, 0.0, -1.0), (-t, 0.0, 1.0),
+].iter().map(|&(x, y, z)| glm::normalize(&glm::vec3(x, y, z))).collect();
+let mut faces: Vec<[usize; 3]> = vec![
+[0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+[1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+[3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+[4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+];
+for _ in 0..subdivisions {
+// Neighbouring triangles share the midpoint of their common edge
+let mut midpoints: HashMap<(usize, usize), usize> = HashMap::new();
+let mut midpoint = |a: usize, b: usize, points: &mut Vec<Vec3>| {
+*midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+points.push(glm::normalize(&(points[a] + points[b])));
+points.len() - 1
+})
+};
+let mut next = Vec::with_capacity(faces.len() * 4);
+for [a, b, c] in faces {
+let ab = midpoint(a, b, &mut points);
+let bc = midpoint(b, c, &mut points);
+let ca = midpoint(c, a, &mut points);
+next.extend([[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]);
+}
+faces = next;
+}
+
+let tau = 2.0 * std::f32::consts::PI;
+let mut vertices: Vec<Vertex> = points.iter().map(|n| Vertex {
+pos: [n.x * radius, n.y * radius, n.z * radius],
+normal: [n.x, n.y, n.z],
+uv: [(n.z.atan2(n.x) / tau).rem_euclid(1.0), n.y.clamp(-1.0, 1.0).acos() / std::f32::consts::PI],
+tangent: sphere_tangent(n),
+}).collect();
+// Triangles across the seam get copies of their low-u corners moved
+// past u = 1, so texture lookups do not sweep back across the map
+let mut wrapped: HashMap<usize, usize> = HashMap::new();
+for face in &mut faces {
+let us = face.map(|i| vertices[i].uv[0]);
+if us.iter().cloned().fold(0.0, f32::max) - us.iter().cloned().fold(1.0, f32::min) <= 0.5 {
+continue;
+}
+for i in face.iter_mut() {
+if vertices[*i].uv[0] < 0.5 {
+let original = *i;
+*i = *wrapped.entry(original).or_insert_with(|| {
+let v = &vertices[original];
+let copy = Vertex { pos: v.pos, normal: v.normal, uv: [v.uv[0] + 1.0, v.uv[1]], tangent: v.tangent };
+vertices.push(copy);
+vertices.len() - 1
+});
+}
+}
+}
+Self::new(vertices, faces)
+}
+
+
+// Cube with its faces pushed out onto the sphere, `resolution` squares
+// along each face edge. Every face gets its own cell of a 3x2 texture
+// atlas: +X, -X, +Y on the top row, -Y, +Z, -Z below, each upright as
+// seen from outside (+Y and -Y with their top towards -Z and +Z).
+pub fn cube_sphere(resolution: usize, radius: f32) -> Self {
+let n = resolution.max(1);
+let mut vertices = Vec::new();
+let mut indices = Vec::new();
+for (k, (out, right, down)) in CUBE_FACES.iter().enumerate() {
+let (out, right, down) = (to_vec3(out), to_vec3(right), to_vec3(down));
+let (column, row) = ((k % 3) as f32, (k / 3) as f32);
+let first = vertices.len();
+for j in 0..=n {
+for i in 0..=n {
+let (s, t) = (i as f32 / n as f32, j as f32 / n as f32);
+// Exact negatives of each other on either side of an edge, so
+// neighbouring faces put their shared vertices at the same spot
+let (x, y) = ((2.0 * i as f32 - n as f32) / n as f32, (2.0 * j as f32 - n as f32) / n as f32);
+let d = glm::normalize(&(out + right * x + down * y));
+// Moving right on the face, projected onto the sphere
+let tangent = glm::normalize(&(right - d * glm::dot(&d, &right)));
+let w = if glm::dot(&glm::cross(&d, &tangent), &down) >= 0.0 { 1.0 } else { -1.0 };
+vertices.push(Vertex {
+pos: [d.x * radius, d.y * radius, d.z * radius],
+normal: [d.x, d.y, d.z],
+uv: [(column + s) / 3.0, (row + t) / 2.0],
+tangent: [tangent.x, tangent.y, tangent.z, w],
+});
+}
+}
+for j in 0..n {
+for i in 0..n {
+let a = first + j * (n + 1) + i;
+let b = a + n + 1;
+// Right x down points inwards, so this order is counter-clockwise
+// seen from outside
 indices.push([a, b, a+1]);
-indices.push([b, b+1, a+1]);
+indices.push([a+1, b, b+1]);
+}
+}
+}
+Self::new(vertices, indices)
+}
+
+
+// Move every vertex to `f(position)`. Normals and tangents are stale
+// afterwards.
+pub fn displace(&mut self, f: impl Fn(&Vec3) -> Vec3) {
+for v in &mut self.vertices {
+let p = f(&to_vec3(&v.pos));
+v.pos = [p.x, p.y, p.z];
+}
+}
+
+
+// Push every vertex out along its normal by `height(position)`, then
+// rebuild the normals and tangents to match. On a unit sphere this turns
+// a height field over directions into real relief.
+pub fn displace_by_height(&mut self, height: impl Fn(&Vec3) -> f32) {
+for v in &mut self.vertices {
+let p = to_vec3(&v.pos);
+let n = to_vec3(&v.normal);
+let q = p + n * height(&p);
+v.pos = [q.x, q.y, q.z];
+}
+self.recompute_normals();
+// The surface only tilted, so the old tangents just need
+// straightening against the new normals
+for v in &mut self.vertices {
+let t = orthogonal_tangent(&to_vec3(&v.normal), &glm::vec3(v.tangent[0], v.tangent[1], v.tangent[2]));
+v.tangent = [t.x, t.y, t.z, v.tangent[3]];
+}
+}
+
+
+// Tangents from the texture coordinates, averaged over the triangles
+// around each vertex. Vertices without a usable mapping get an arbitrary
+// tangent perpendicular to their normal.
+pub fn recompute_tangents(&mut self) {
+let mut tangents = vec![glm::Vec3::zeros(); self.vertices.len()];
+let mut bitangents = vec![glm::Vec3::zeros(); self.vertices.len()];
+for tri in &self.indices {
+let [a, b, c] = tri.map(|i| &self.vertices[i]);
+let e1 = to_vec3(&b.pos) - to_vec3(&a.pos);
+let e2 = to_vec3(&c.pos) - to_vec3(&a.pos);
+let (du1, dv1) = (b.uv[0] - a.uv[0], b.uv[1] - a.uv[1]);
+let (du2, dv2) = (c.uv[0] - a.uv[0], c.uv[1] - a.uv[1]);
+let det = du1 * dv2 - du2 * dv1;
+if det.abs() < 1e-12 {
+continue;
+}
+let t = (e1 * dv2 - e2 * dv1) / det;
+let bt = (e2 * du1 - e1 * du2) / det;
+for &i in tri {
+tangents[i] += t;
+bitangents[i] += bt;
+}
+}
+for (v, (t, bt)) in self.vertices.iter_mut().zip(tangents.iter().zip(&bitangents)) {
+let n = to_vec3(&v.normal);
+let tangent = orthogonal_tangent(&n, t);
+let w = if glm::dot(&glm::cross(&n, &tangent), bt) < 0.0 { -1.0 } else { 1.0 };
+v.tangent = [tangent.x, tangent.y, tangent.z, w];
+}
+}
+
+
+// Area-weighted vertex normals. Vertices at the same position, like the
+// duplicated ones along a UV seam, share one normal so the seam stays hidden.
+pub fn recompute_normals(&mut self) {
+let key = |p: &[f32; 3]| [p[0].to_bits(), p[1].to_bits(), p[2].to_bits()];
+let mut sums: HashMap<[u32; 3], Vec3> = HashMap::new();
+for tri in &self.indices {
+let [a, b, c] = tri.map(|i| to_vec3(&self.vertices[i].pos));
+// Cross product length is twice the area, which gives the weighting
+let n = glm::cross(&(b - a), &(c - a));
+for &i in tri {
+*sums.entry(key(&self.vertices[i].pos)).or_insert_with(glm::Vec3::zeros) += n;
+}
+}
+for v in &mut self.vertices {
+let n = sums[&key(&v.pos)];
+let len = glm::length(&n);
+if len > 0.0 {
+v.normal = [n.x / len, n.y / len, n.z / len];
+}
+}
+}
+
+
+// Distance of the farthest vertex from the origin
+pub fn radius(&self) -> f32 {
+self.vertices.iter().map(|v| glm::length(&to_vec3(&v.pos))).fold(0.0, f32::max)
+}
+
+
+pub fn triangle_bounds(&self, i: usize) -> Aabb {
+let [a, b, c] = self.indices[i].map(|i| to_vec3(&self.vertices[i].pos));
+Aabb { min: glm::min2(&glm::min2(&a, &b), &c), max: glm::max2(&glm::max2(&a, &b), &c) }
+}
+
+
+// Moller-Trumbore ray/triangle test. The normal is interpolated from the
+// vertex normals so low-poly curved surfaces shade smoothly.
+pub fn intersect_triangle(&self, i: usize, ray: &Ray, t_max: f32) -> Option<Hit> {
+let [ia, ib, ic] = self.indices[i];
+let a = to_vec3(&self.vertices[ia].pos);
+let e1 = to_vec3(&self.vertices[ib].pos) - a;
+let e2 = to_vec3(&self.vertices[ic].pos) - a;
+let p = glm::cross(&ray.direction, &e2);
+let det = glm::dot(&e1, &p);
+if det.abs() < 1e-12 {
+return None;
+}
+let inv_det = 1.0 / det;
+let s = ray.origin - a;
+let u = glm::dot(&s, &p) * inv_det;
+if !(0.0..=1.0).contains(&u) {
+return None;
+}
+let q = glm::cross(&s, &e1);
+let v = glm::dot(&ray.direction, &q) * inv_det;
+if v < 0.0 || u + v > 1.0 {
+return None;
 }
+let t = glm::dot(&e2, &q) * inv_det;
+if t <= 1e-6 || t >= t_max {
+return None;
+}
+let n = to_vec3(&self.vertices[ia].normal) * (1.0 - u - v)
++ to_vec3(&self.vertices[ib].normal) * u
++ to_vec3(&self.vertices[ic].normal) * v;
+Some(Hit { t, point: ray.origin + ray.direction * t, normal: glm::normalize(&n) })
+}
+
+
+// Surface color of triangle `i` at `point`, a point on the triangle
+pub fn surface_color(&self, i: usize, point: &Vec3) -> Vec3 {
+let material = &self.materials[self.triangle_materials[i]];
+let Some(texture) = &material.texture else {
+return material.base_color;
+};
+let [ia, ib, ic] = self.indices[i];
+let a = to_vec3(&self.vertices[ia].pos);
+let e1 = to_vec3(&self.vertices[ib].pos) - a;
+let e2 = to_vec3(&self.vertices[ic].pos) - a;
+let p = point - a;
+// Barycentric weights of b and c
+let (d11, d12, d22) = (glm::dot(&e1, &e1), glm::dot(&e1, &e2), glm::dot(&e2, &e2));
+let (dp1, dp2) = (glm::dot(&p, &e1), glm::dot(&p, &e2));
+let denom = d11 * d22 - d12 * d12;
+if denom.abs() < 1e-20 {
+return material.base_color;
+}
+let u = (d22 * dp1 - d12 * dp2) / denom;
+let v = (d11 * dp2 - d12 * dp1) / denom;
+let w = 1.0 - u - v;
+let [ta, tb, tc] = [ia, ib, ic].map(|k| self.vertices[k].uv);
+let uv = [ta[0] * w + tb[0] * u + tc[0] * v, ta[1] * w + tb[1] * u + tc[1] * v];
+material.base_color.component_mul(&texture.sample(uv))
+}
+}
+
+
+// Faces of cube_sphere in atlas order: outward axis, then right and down
+// as seen from outside
+pub(crate) const CUBE_FACES: [([f32; 3], [f32; 3], [f32; 3]); 6] = [
+([1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, -1.0, 0.0]),
+([-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, -1.0, 0.0]),
+([0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+([0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0]),
+([0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+([0.0, 0.0, -1.0], [-1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+];
+
+
+fn to_vec3(a: &[f32; 3]) -> Vec3 {
+glm::vec3(a[0], a[1], a[2])
+}
+
+
+// Eastward tangent of a sphere at `n`, along uv_sphere's increasing u,
+// or any tangent at the poles
+fn sphere_tangent(n: &Vec3) -> [f32; 4] {
+let t = orthogonal_tangent(n, &glm::vec3(-n.z, 0.0, n.x));
+[t.x, t.y, t.z, 1.0]
+}
+
+
+// `t` made perpendicular to `n` and unit length, or any unit vector
+// perpendicular to `n` when `t` is (nearly) parallel to it
+fn orthogonal_tangent(n: &Vec3, t: &Vec3) -> Vec3 {
+let t = t - n * glm::dot(n, t);
+let len = glm::length(&t);
+if len > 1e-6 {
+t / len
+} else if glm::length(n) > 0.0 {
+math::perpendiculars(&glm::normalize(n)).0
+} else {
+glm::vec3(1.0, 0.0, 0.0)
+}
+}
+
+
+// A mesh with a triangle hierarchy, built once in the mesh's own space
+pub struct Model {
+pub mesh: Mesh,
+bvh: Bvh,
+radius: f32,
+}
+
+
+impl Model {
+pub fn new(mesh: Mesh) -> Self {
+let bounds: Vec<Aabb> = (0..mesh.indices.len()).map(|i| mesh.triangle_bounds(i)).collect();
+let bvh = Bvh::from_bounds(&bounds);
+let radius = mesh.radius();
+Self { mesh, bvh, radius }
+}
+
+
+// Radius of a sphere around the origin enclosing the whole mesh
+pub fn radius(&self) -> f32 {
+self.radius
+}
+
+
+// Closest hit with a ray given in the mesh's space, with the triangle hit
+pub fn intersect(&self, ray: &Ray, t_max: f32) -> Option<(usize, Hit)> {
+self.bvh.traverse(ray, t_max, |i, t_max| self.mesh.intersect_triangle(i, ray, t_max))
+}
+}
+
+
+#[cfg(test)]
+mod tests {
+use super::*;
+use std::collections::HashSet;
+
+// One triangle in the z = 0 plane, facing +Z, with normals tilted apart so
+// interpolation shows
+fn triangle() -> Mesh {
+let vertex = |pos: [f32; 3], normal: [f32; 3]| Vertex { pos, normal, uv: [0.0, 0.0], tangent: [1.0, 0.0, 0.0, 1.0] };
+Mesh::new(vec![
+vertex([0.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+vertex([1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+vertex([0.0, 1.0, 0.0], [0.0, 1.0, 0.0]),
+], vec![[0, 1, 2]])
+}
+
+fn ray(origin: [f32; 3], direction: [f32; 3]) -> Ray {
+Ray { origin: to_vec3(&origin), direction: glm::normalize(&to_vec3(&direction)) }
+}
+
+#[test]
+fn ray_hits_the_triangle_inside() {
+let hit = triangle().intersect_triangle(0, &ray([0.25, 0.5, 2.0], [0.0, 0.0, -1.0]), f32::INFINITY).unwrap();
+assert!((hit.t - 2.0).abs() < 1e-6);
+assert!(glm::distance(&hit.point, &glm::vec3(0.25, 0.5, 0.0)) < 1e-6);
+// Halfway to the tilted corner's normal
+let expected = glm::normalize(&glm::vec3(0.0, 0.5, 0.5));
+assert!(glm::distance(&hit.normal, &expected) < 1e-5);
+// From behind it is hit all the same
+assert!(triangle().intersect_triangle(0, &ray([0.25, 0.25, -1.0], [0.0, 0.0, 1.0]), f32::INFINITY).is_some());
+}
+
+#[test]
+fn ray_misses_outside_behind_and_beyond_t_max() {
+let mesh = triangle();
+assert!(mesh.intersect_triangle(0, &ray([0.6, 0.6, 1.0], [0.0, 0.0, -1.0]), f32::INFINITY).is_none());
+assert!(mesh.intersect_triangle(0, &ray([-0.1, 0.5, 1.0], [0.0, 0.0, -1.0]), f32::INFINITY).is_none());
+assert!(mesh.intersect_triangle(0, &ray([0.25, 0.25, 1.0], [0.0, 0.0, 1.0]), f32::INFINITY).is_none());
+assert!(mesh.intersect_triangle(0, &ray([0.25, 0.25, 1.0], [0.0, 0.0, -1.0]), 0.5).is_none());
+}
+
+#[test]
+fn edge_on_ray_misses() {
+let mesh = triangle();
+// In the triangle's plane, straight across it
+assert!(mesh.intersect_triangle(0, &ray([-1.0, 0.25, 0.0], [1.0, 0.0, 0.0]), f32::INFINITY).is_none());
+// Parallel to the plane just above it
+assert!(mesh.intersect_triangle(0, &ray([-1.0, 0.25, 1e-3], [1.0, 0.0, 0.0]), f32::INFINITY).is_none());
+}
+
+fn distinct_positions(mesh: &Mesh) -> usize {
+mesh.vertices.iter().map(|v| v.pos.map(f32::to_bits)).collect::<HashSet<_>>().len()
+}
+
+// Every vertex on the unit sphere, and every triangle counter-clockwise
+// seen from outside
+fn assert_unit_sphere(mesh: &Mesh) {
+for v in &mesh.vertices {
+assert!((glm::length(&to_vec3(&v.pos)) - 1.0).abs() < 1e-5);
+}
+for tri in &mesh.indices {
+let [a, b, c] = tri.map(|i| to_vec3(&mesh.vertices[i].pos));
+let facing = glm::cross(&(b - a), &(c - a));
+assert!(glm::dot(&facing, &(a + b + c)) > 0.0, "triangle {:?} faces inwards", tri);
+}
+}
+
+// Unit tangents perpendicular to the normals, with a handedness sign
+fn assert_tangents(mesh: &Mesh) {
+for v in &mesh.vertices {
+let t = glm::vec3(v.tangent[0], v.tangent[1], v.tangent[2]);
+assert!((glm::length(&t) - 1.0).abs() < 1e-4);
+assert!(glm::dot(&t, &to_vec3(&v.normal)).abs() < 1e-4);
+assert!(v.tangent[3].abs() == 1.0);
+}
+}
+
+#[test]
+fn icosphere_counts_and_shape() {
+for subdivisions in 0..4 {
+let mesh = Mesh::icosphere(subdivisions, 1.0);
+let split = 4usize.pow(subdivisions as u32);
+assert_eq!(mesh.indices.len(), 20 * split);
+// Seam copies add vertices but no positions
+assert_eq!(distinct_positions(&mesh), 10 * split + 2);
+assert_unit_sphere(&mesh);
+assert_tangents(&mesh);
+}
+}
+
+#[test]
+fn cube_sphere_counts_and_shape() {
+for resolution in 1..6 {
+let mesh = Mesh::cube_sphere(resolution, 1.0);
+assert_eq!(mesh.indices.len(), 12 * resolution * resolution);
+assert_eq!(mesh.vertices.len(), 6 * (resolution + 1) * (resolution + 1));
+// Face borders are doubled; the cube's corners are tripled
+assert_eq!(distinct_positions(&mesh), 6 * resolution * resolution + 2);
+assert_unit_sphere(&mesh);
+assert_tangents(&mesh);
+}
+}
+
+#[test]
+fn tangents_stay_perpendicular_after_displacement() {
+let mut mesh = Mesh::cube_sphere(6, 1.0);
+mesh.displace_by_height(|p| 0.1 * (p.x * 5.0).sin() * (p.z * 3.0).cos());
+assert_tangents(&mesh);
+mesh.recompute_tangents();
+assert_tangents(&mesh);
 }
-Self { vertices, indices }
 }
-}
\ No newline at end of file
@@ -0,0 +1,891 @@
+use nalgebra_glm as glm;
+use crate::color::Color;
+use crate::json::{self, Json};
+use crate::math::{Mat4, Vec3};
+use crate::mesh::{Material, Mesh, Texture, Vertex};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// Where in a model file an error was found
+#[derive(Debug)]
+pub enum Location {
+    File,          // the file as a whole, e.g. it could not be read
+    Line(usize),   // 1-based line of a text format
+    Offset(usize), // byte offset into a binary format
+}
+
+#[derive(Debug)]
+pub struct ModelError {
+    pub source: String, // file the model came from
+    pub location: Location,
+    pub message: String,
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.location {
+            Location::File => write!(f, "{}: {}", self.source, self.message),
+            Location::Line(line) => write!(f, "{}:{}: {}", self.source, line, self.message),
+            Location::Offset(offset) => write!(f, "{}: byte {}: {}", self.source, offset, self.message),
+        }
+    }
+}
+
+impl std::error::Error for ModelError {}
+
+fn file_error(source: &str, message: String) -> ModelError {
+    ModelError { source: source.to_string(), location: Location::File, message }
+}
+
+// Load a model, picking the format from the file extension
+pub fn load_model(path: &Path) -> Result<Mesh, ModelError> {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    match extension.as_str() {
+        "obj" => load_obj(path),
+        "glb" => load_glb(path),
+        "gltf" => Err(file_error(&path.display().to_string(), "only binary glTF (.glb) is supported".to_string())),
+        _ => Err(file_error(&path.display().to_string(), "unknown model format, expected .obj or .glb".to_string())),
+    }
+}
+
+// Save a mesh, picking the format from the file extension
+pub fn save_model(mesh: &Mesh, path: &Path) -> Result<(), ModelError> {
+    let source = path.display().to_string();
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    let write = match extension.as_str() {
+        "obj" => write_obj,
+        "ply" => write_ply,
+        _ => return Err(file_error(&source, "unknown export format, expected .obj or .ply".to_string())),
+    };
+    let file = std::fs::File::create(path).map_err(|e| file_error(&source, e.to_string()))?;
+    let mut out = BufWriter::new(file);
+    write(mesh, &mut out).and_then(|_| out.flush()).map_err(|e| file_error(&source, e.to_string()))
+}
+
+// Write a Wavefront OBJ with positions, texture coordinates, normals and,
+// when the mesh has them, vertex colors as `v x y z r g b`. Materials are
+// not written.
+pub fn write_obj(mesh: &Mesh, out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, "# {} vertices, {} triangles", mesh.vertices.len(), mesh.indices.len())?;
+    for (i, v) in mesh.vertices.iter().enumerate() {
+        let [x, y, z] = v.pos;
+        match &mesh.colors {
+            Some(colors) => {
+                let c = glm::clamp(&colors[i], 0.0, 1.0);
+                writeln!(out, "v {} {} {} {} {} {}", x, y, z, c.x, c.y, c.z)?;
+            }
+            None => writeln!(out, "v {} {} {}", x, y, z)?,
+        }
+    }
+    // Back to OBJ's v = 0 at the bottom of the image
+    for v in &mesh.vertices {
+        writeln!(out, "vt {} {}", v.uv[0], 1.0 - v.uv[1])?;
+    }
+    for v in &mesh.vertices {
+        writeln!(out, "vn {} {} {}", v.normal[0], v.normal[1], v.normal[2])?;
+    }
+    // Every vertex has all three attributes at the same 1-based index
+    for [a, b, c] in &mesh.indices {
+        let (a, b, c) = (a + 1, b + 1, c + 1);
+        writeln!(out, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}")?;
+    }
+    Ok(())
+}
+
+// Write an ASCII PLY with positions, normals, texture coordinates and,
+// when the mesh has them, 8-bit vertex colors
+pub fn write_ply(mesh: &Mesh, out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, "ply")?;
+    writeln!(out, "format ascii 1.0")?;
+    writeln!(out, "element vertex {}", mesh.vertices.len())?;
+    for property in ["x", "y", "z", "nx", "ny", "nz", "s", "t"] {
+        writeln!(out, "property float {}", property)?;
+    }
+    if mesh.colors.is_some() {
+        for property in ["red", "green", "blue"] {
+            writeln!(out, "property uchar {}", property)?;
+        }
+    }
+    writeln!(out, "element face {}", mesh.indices.len())?;
+    writeln!(out, "property list uchar int vertex_indices")?;
+    writeln!(out, "end_header")?;
+    for (i, v) in mesh.vertices.iter().enumerate() {
+        let ([x, y, z], [nx, ny, nz]) = (v.pos, v.normal);
+        // PLY tools also put t = 0 at the bottom of the image
+        write!(out, "{} {} {} {} {} {} {} {}", x, y, z, nx, ny, nz, v.uv[0], 1.0 - v.uv[1])?;
+        if let Some(colors) = &mesh.colors {
+            let [r, g, b] = Color::from_vec3(&colors[i]).clamp_u8();
+            write!(out, " {} {} {}", r, g, b)?;
+        }
+        writeln!(out)?;
+    }
+    for [a, b, c] in &mesh.indices {
+        writeln!(out, "3 {} {} {}", a, b, c)?;
+    }
+    Ok(())
+}
+
+pub fn load_obj(path: &Path) -> Result<Mesh, ModelError> {
+    let source = path.display().to_string();
+    let text = std::fs::read_to_string(path).map_err(|e| file_error(&source, e.to_string()))?;
+    parse_obj(&text, &source)
+}
+
+// Parse a Wavefront OBJ: positions, normals, texture coordinates and
+// polygonal faces, which are split into triangle fans. Vertex colors
+// written as `v x y z r g b` are kept; vertices without one are white.
+// Other statements (objects, groups, smoothing, materials) are ignored. If
+// any face leaves out normals, smooth normals are computed for the whole
+// mesh. Tangents come from the texture coordinates.
+pub fn parse_obj(text: &str, source: &str) -> Result<Mesh, ModelError> {
+    let err = |line: usize, message: String| ModelError { source: source.to_string(), location: Location::Line(line), message };
+    let mut positions: Vec<[f32; 3]> = Vec::new();
+    let mut position_colors: Vec<Option<Vec3>> = Vec::new();
+    let mut normals: Vec<[f32; 3]> = Vec::new();
+    let mut uvs: Vec<[f32; 2]> = Vec::new();
+    let mut vertices = Vec::new();
+    let mut colors = Vec::new();
+    let mut indices = Vec::new();
+    // Each distinct position/uv/normal combination becomes one vertex
+    let mut welded: HashMap<(usize, Option<usize>, Option<usize>), usize> = HashMap::new();
+    let mut missing_normals = false;
+
+    for (i, raw) in text.lines().enumerate() {
+        let line = i + 1;
+        let content = raw.split('#').next().unwrap_or("").trim();
+        let mut fields = content.split_whitespace();
+        let Some(keyword) = fields.next() else {
+            continue;
+        };
+        let floats = |fields: std::str::SplitWhitespace, min: usize| -> Result<Vec<f32>, ModelError> {
+            let values = fields
+                .map(|f| f.parse::<f32>().map_err(|_| err(line, format!("invalid number {:?}", f))))
+                .collect::<Result<Vec<f32>, _>>()?;
+            if values.len() < min {
+                return Err(err(line, format!("`{}` needs {} numbers, found {}", keyword, min, values.len())));
+            }
+            Ok(values)
+        };
+        match keyword {
+            "v" => {
+                let v = floats(fields, 3)?;
+                positions.push([v[0], v[1], v[2]]);
+                position_colors.push((v.len() >= 6).then(|| glm::vec3(v[3], v[4], v[5])));
+            }
+            "vn" => {
+                let n = floats(fields, 3)?;
+                normals.push([n[0], n[1], n[2]]);
+            }
+            "vt" => {
+                // OBJ puts v = 0 at the bottom of the image
+                let t = floats(fields, 1)?;
+                uvs.push([t[0], 1.0 - t.get(1).copied().unwrap_or(0.0)]);
+            }
+            "f" => {
+                let mut corners = Vec::new();
+                for corner in fields {
+                    let mut parts = corner.split('/');
+                    // Indices are 1-based, or negative to count back from the latest
+                    let resolve = |part: Option<&str>, count: usize, what: &str| -> Result<Option<usize>, ModelError> {
+                        let part = match part {
+                            Some(p) if !p.is_empty() => p,
+                            _ => return Ok(None),
+                        };
+                        let index: i64 = part.parse().map_err(|_| err(line, format!("invalid {} index {:?}", what, part)))?;
+                        let resolved = if index < 0 { count as i64 + index } else { index - 1 };
+                        if index == 0 || resolved < 0 || resolved >= count as i64 {
+                            return Err(err(line, format!("{} index {} out of range, {} defined so far", what, index, count)));
+                        }
+                        Ok(Some(resolved as usize))
+                    };
+                    let p = resolve(parts.next(), positions.len(), "vertex")?
+                        .ok_or_else(|| err(line, format!("face corner {:?} has no vertex index", corner)))?;
+                    let t = resolve(parts.next(), uvs.len(), "texture coordinate")?;
+                    let n = resolve(parts.next(), normals.len(), "normal")?;
+                    missing_normals |= n.is_none();
+                    let index = *welded.entry((p, t, n)).or_insert_with(|| {
+                        vertices.push(Vertex {
+                            pos: positions[p],
+                            normal: n.map_or([0.0; 3], |n| normals[n]),
+                            uv: t.map_or([0.0; 2], |t| uvs[t]),
+                            tangent: [0.0; 4],
+                        });
+                        colors.push(position_colors[p].unwrap_or(glm::vec3(1.0, 1.0, 1.0)));
+                        vertices.len() - 1
+                    });
+                    corners.push(index);
+                }
+                if corners.len() < 3 {
+                    return Err(err(line, format!("face needs at least 3 corners, found {}", corners.len())));
+                }
+                for k in 1..corners.len() - 1 {
+                    indices.push([corners[0], corners[k], corners[k + 1]]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if indices.is_empty() {
+        return Err(file_error(source, "no faces".to_string()));
+    }
+    let mut mesh = Mesh::new(vertices, indices);
+    if position_colors.iter().any(|c| c.is_some()) {
+        mesh.colors = Some(colors);
+    }
+    if missing_normals {
+        mesh.recompute_normals();
+    }
+    mesh.recompute_tangents();
+    Ok(mesh)
+}
+
+pub fn load_glb(path: &Path) -> Result<Mesh, ModelError> {
+    let source = path.display().to_string();
+    let bytes = std::fs::read(path).map_err(|e| file_error(&source, e.to_string()))?;
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+    parse_glb(&bytes, &source, base_dir)
+}
+
+const GLB_MAGIC: u32 = 0x4654_6c67; // "glTF"
+const CHUNK_JSON: u32 = 0x4e4f_534a; // "JSON"
+const CHUNK_BIN: u32 = 0x004e_4942; // "BIN\0"
+const MODE_TRIANGLES: usize = 4;
+// Most components an accessor may hold; anything larger is a corrupt file
+// rather than a model, and is refused before allocating for it
+const MAX_ACCESSOR_VALUES: usize = 1 << 28;
+
+// Parse a binary glTF 2.0 file. Every triangle primitive reachable from
+// the default scene is baked into one mesh with its node's transform
+// applied; materials keep their base color factor and texture. Primitives
+// without normals get smooth ones, and those without tangents get them
+// from their texture coordinates. External buffers and images are read
+// relative to `base_dir`.
+pub fn parse_glb(bytes: &[u8], source: &str, base_dir: &Path) -> Result<Mesh, ModelError> {
+    let err = |offset: usize, message: String| ModelError { source: source.to_string(), location: Location::Offset(offset), message };
+    let word = |offset: usize| bytes.get(offset..offset + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+
+    if word(0) != Some(GLB_MAGIC) {
+        return Err(err(0, "not a binary glTF file (bad magic)".to_string()));
+    }
+    let version = word(4).unwrap_or(0);
+    if version != 2 {
+        return Err(err(4, format!("unsupported glTF version {}", version)));
+    }
+    let length = word(8).ok_or_else(|| err(8, "truncated header".to_string()))? as usize;
+    if length > bytes.len() {
+        return Err(err(8, format!("header says {} bytes, file has {}", length, bytes.len())));
+    }
+
+    // The JSON chunk comes first; a BIN chunk may follow
+    let mut json_chunk = None;
+    let mut bin_chunk = None;
+    let mut offset = 12;
+    while offset < length {
+        let (Some(chunk_length), Some(chunk_type)) = (word(offset), word(offset + 4)) else {
+            return Err(err(offset, "truncated chunk header".to_string()));
+        };
+        let start = offset + 8;
+        let end = start + chunk_length as usize;
+        if end > length {
+            return Err(err(offset, format!("chunk of {} bytes runs past the end of the file", chunk_length)));
+        }
+        match chunk_type {
+            CHUNK_JSON if offset == 12 => json_chunk = Some(&bytes[start..end]),
+            _ if offset == 12 => return Err(err(offset, "the first chunk must be JSON".to_string())),
+            CHUNK_JSON => return Err(err(offset, "second JSON chunk".to_string())),
+            CHUNK_BIN if bin_chunk.is_none() => bin_chunk = Some(&bytes[start..end]),
+            _ => {} // unknown chunks are skipped, as the spec asks
+        }
+        // Chunks are padded to 4-byte boundaries
+        offset = (end + 3) & !3;
+    }
+    let json_chunk = json_chunk.ok_or_else(|| err(12, "missing JSON chunk".to_string()))?;
+    let json_start = 20; // 12-byte header, then the chunk's length and type
+    let text = std::str::from_utf8(json_chunk)
+        .map_err(|e| err(json_start + e.valid_up_to(), "JSON chunk is not valid UTF-8".to_string()))?;
+    let root = json::parse(text).map_err(|e| err(json_start + e.offset, e.message))?;
+
+    let mut gltf = Gltf { root: &root, json_start, source, base_dir, buffers: Vec::new() };
+    gltf.load_buffers(bin_chunk)?;
+    gltf.bake()
+}
+
+// A parsed glTF document and its buffers
+struct Gltf<'a> {
+    root: &'a Json,
+    json_start: usize, // offset of the JSON text in the file
+    source: &'a str,
+    base_dir: &'a Path,
+    buffers: Vec<Vec<u8>>,
+}
+
+// Element layout of an accessor
+struct Accessor {
+    values: Vec<f64>,
+    components: usize,
+}
+
+impl<'a> Gltf<'a> {
+    // Error pointing at a JSON value
+    fn err(&self, at: &Json, message: String) -> ModelError {
+        ModelError { source: self.source.to_string(), location: Location::Offset(self.json_start + at.offset), message }
+    }
+
+    // Entry `index` of the top-level array `name`
+    fn item(&self, name: &str, index: &Json) -> Result<&'a Json, ModelError> {
+        let i = index.as_usize().ok_or_else(|| self.err(index, format!("expected an index into {}", name)))?;
+        self.root
+            .get(name)
+            .and_then(|a| a.as_array())
+            .and_then(|a| a.get(i))
+            .ok_or_else(|| self.err(index, format!("{}[{}] does not exist", name, i)))
+    }
+
+    fn usize_field(&self, object: &Json, key: &str, default: Option<usize>) -> Result<usize, ModelError> {
+        match object.get(key) {
+            Some(value) => value.as_usize().ok_or_else(|| self.err(value, format!("`{}` must be a non-negative integer", key))),
+            None => default.ok_or_else(|| self.err(object, format!("missing `{}`", key))),
+        }
+    }
+
+    fn read_external(&self, at: &Json, uri: &str) -> Result<Vec<u8>, ModelError> {
+        if uri.starts_with("data:") {
+            return Err(self.err(at, "embedded data URIs are not supported".to_string()));
+        }
+        let path = self.base_dir.join(uri);
+        std::fs::read(&path).map_err(|e| self.err(at, format!("{}: {}", path.display(), e)))
+    }
+
+    fn load_buffers(&mut self, bin_chunk: Option<&[u8]>) -> Result<(), ModelError> {
+        let Some(buffers) = self.root.get("buffers").and_then(|b| b.as_array()) else {
+            return Ok(());
+        };
+        for (i, buffer) in buffers.iter().enumerate() {
+            let mut data = match buffer.get("uri").and_then(|u| u.as_str()) {
+                Some(uri) => self.read_external(buffer, uri)?,
+                // Only the first buffer may live in the BIN chunk
+                None if i == 0 => bin_chunk.ok_or_else(|| self.err(buffer, "buffer 0 has no uri and there is no BIN chunk".to_string()))?.to_vec(),
+                None => return Err(self.err(buffer, format!("buffer {} has no uri", i))),
+            };
+            let byte_length = self.usize_field(buffer, "byteLength", None)?;
+            if data.len() < byte_length {
+                return Err(self.err(buffer, format!("buffer {} holds {} bytes, expected {}", i, data.len(), byte_length)));
+            }
+            // The BIN chunk is padded to four bytes; views stop at the buffer's end
+            data.truncate(byte_length);
+            self.buffers.push(data);
+        }
+        Ok(())
+    }
+
+    // Bytes of a buffer view, and its stride when it has one
+    fn buffer_view(&self, index: &Json) -> Result<(&[u8], Option<usize>), ModelError> {
+        let view = self.item("bufferViews", index)?;
+        let buffer = self.usize_field(view, "buffer", None)?;
+        let data = self.buffers.get(buffer).ok_or_else(|| self.err(view, format!("buffer {} does not exist", buffer)))?;
+        let offset = self.usize_field(view, "byteOffset", Some(0))?;
+        let length = self.usize_field(view, "byteLength", None)?;
+        let bytes = offset
+            .checked_add(length)
+            .and_then(|end| data.get(offset..end))
+            .ok_or_else(|| self.err(view, format!("view of {} bytes at {} runs past the end of buffer {}", length, offset, buffer)))?;
+        let stride = view.get("byteStride").and_then(|s| s.as_usize());
+        Ok((bytes, stride))
+    }
+
+    // Every component of an accessor as floating point, with normalized
+    // integers mapped to 0..1 (or -1..1)
+    fn accessor(&self, index: &Json) -> Result<Accessor, ModelError> {
+        let accessor = self.item("accessors", index)?;
+        if let Some(sparse) = accessor.get("sparse") {
+            return Err(self.err(sparse, "sparse accessors are not supported".to_string()));
+        }
+        let count = self.usize_field(accessor, "count", None)?;
+        let kind = accessor.get("type").ok_or_else(|| self.err(accessor, "missing `type`".to_string()))?;
+        let components = match kind.as_str() {
+            Some("SCALAR") => 1,
+            Some("VEC2") => 2,
+            Some("VEC3") => 3,
+            Some("VEC4") => 4,
+            Some("MAT2") => 4,
+            Some("MAT3") => 9,
+            Some("MAT4") => 16,
+            _ => return Err(self.err(kind, "unknown accessor type".to_string())),
+        };
+        let component_type = self.usize_field(accessor, "componentType", None)?;
+        let normalized = accessor.get("normalized").and_then(|n| n.as_bool()).unwrap_or(false);
+        let size = match component_type {
+            5120 | 5121 => 1,
+            5122 | 5123 => 2,
+            5125 | 5126 => 4,
+            other => return Err(self.err(accessor, format!("unknown componentType {}", other))),
+        };
+        let total = count.checked_mul(components).filter(|n| *n <= MAX_ACCESSOR_VALUES);
+        let Some(total) = total else {
+            return Err(self.err(accessor, format!("{} elements are more than an accessor may hold", count)));
+        };
+        // Without a view the accessor is all zeros
+        let Some(view) = accessor.get("bufferView") else {
+            return Ok(Accessor { values: vec![0.0; total], components });
+        };
+        let (bytes, stride) = self.buffer_view(view)?;
+        let offset = self.usize_field(accessor, "byteOffset", Some(0))?;
+        let element = size * components;
+        let stride = stride.unwrap_or(element);
+        if count > 0 {
+            let end = stride
+                .checked_mul(count - 1)
+                .and_then(|span| span.checked_add(offset))
+                .and_then(|start| start.checked_add(element))
+                .filter(|end| *end <= bytes.len());
+            if end.is_none() {
+                return Err(self.err(accessor, format!("{} elements at offset {} run past the end of the view", count, offset)));
+            }
+        }
+
+        let mut values = Vec::with_capacity(total);
+        for e in 0..count {
+            for c in 0..components {
+                let at = offset + e * stride + c * size;
+                let b = &bytes[at..at + size];
+                let value = match component_type {
+                    5120 => b[0] as i8 as f64,
+                    5121 => b[0] as f64,
+                    5122 => i16::from_le_bytes([b[0], b[1]]) as f64,
+                    5123 => u16::from_le_bytes([b[0], b[1]]) as f64,
+                    5125 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    _ => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                };
+                let value = match (normalized, component_type) {
+                    (true, 5120) => (value / 127.0).max(-1.0),
+                    (true, 5121) => value / 255.0,
+                    (true, 5122) => (value / 32767.0).max(-1.0),
+                    (true, 5123) => value / 65535.0,
+                    _ => value,
+                };
+                values.push(value);
+            }
+        }
+        Ok(Accessor { values, components })
+    }
+
+    fn vectors<const N: usize>(&self, index: &Json) -> Result<Vec<[f32; N]>, ModelError> {
+        let accessor = self.accessor(index)?;
+        if accessor.components != N {
+            return Err(self.err(index, format!("expected {} components per element, found {}", N, accessor.components)));
+        }
+        Ok(accessor.values.chunks(N).map(|c| std::array::from_fn(|k| c[k] as f32)).collect())
+    }
+
+    fn material(&self, material: &Json) -> Result<Material, ModelError> {
+        let mut result = Material::default();
+        let Some(pbr) = material.get("pbrMetallicRoughness") else {
+            return Ok(result);
+        };
+        if let Some(factor) = pbr.get("baseColorFactor") {
+            let rgba = factor.as_array().filter(|f| f.len() == 4).ok_or_else(|| self.err(factor, "baseColorFactor needs 4 numbers".to_string()))?;
+            let channel = |k: usize| rgba[k].as_f64().map(|v| v as f32).ok_or_else(|| self.err(&rgba[k], "expected a number".to_string()));
+            result.base_color = glm::vec3(channel(0)?, channel(1)?, channel(2)?);
+        }
+        if let Some(info) = pbr.get("baseColorTexture") {
+            let index = info.get("index").ok_or_else(|| self.err(info, "missing `index`".to_string()))?;
+            if info.get("texCoord").and_then(|t| t.as_usize()).unwrap_or(0) != 0 {
+                return Err(self.err(info, "only TEXCOORD_0 is supported for textures".to_string()));
+            }
+            let texture = self.item("textures", index)?;
+            let image_index = texture.get("source").ok_or_else(|| self.err(texture, "texture has no image source".to_string()))?;
+            result.texture = Some(self.image(image_index)?);
+        }
+        Ok(result)
+    }
+
+    fn image(&self, index: &Json) -> Result<Texture, ModelError> {
+        let image = self.item("images", index)?;
+        let decoded = match (image.get("bufferView"), image.get("uri").and_then(|u| u.as_str())) {
+            (Some(view), _) => image::load_from_memory(self.buffer_view(view)?.0),
+            (None, Some(uri)) => image::load_from_memory(&self.read_external(image, uri)?),
+            (None, None) => return Err(self.err(image, "image has neither bufferView nor uri".to_string())),
+        };
+        let decoded = decoded.map_err(|e| self.err(image, format!("cannot decode image: {}", e)))?;
+        Ok(Texture::from_image(&decoded))
+    }
+
+    fn node_transform(&self, node: &Json) -> Result<Mat4, ModelError> {
+        let numbers = |key: &str, len: usize| -> Result<Option<Vec<f32>>, ModelError> {
+            let Some(value) = node.get(key) else {
+                return Ok(None);
+            };
+            value
+                .as_array()
+                .filter(|a| a.len() == len)
+                .and_then(|a| a.iter().map(|v| v.as_f64().map(|v| v as f32)).collect::<Option<Vec<f32>>>())
+                .map(Some)
+                .ok_or_else(|| self.err(value, format!("`{}` needs {} numbers", key, len)))
+        };
+        // Column-major, like glm's own storage
+        if let Some(m) = numbers("matrix", 16)? {
+            return Ok(glm::make_mat4(&m));
+        }
+        let mut transform = Mat4::identity();
+        if let Some(t) = numbers("translation", 3)? {
+            transform *= glm::translation(&glm::vec3(t[0], t[1], t[2]));
+        }
+        if let Some(r) = numbers("rotation", 4)? {
+            transform *= glm::quat_to_mat4(&glm::quat(r[0], r[1], r[2], r[3]));
+        }
+        if let Some(s) = numbers("scale", 3)? {
+            transform *= glm::scaling(&glm::vec3(s[0], s[1], s[2]));
+        }
+        Ok(transform)
+    }
+
+    // Walk the default scene and flatten everything into one mesh
+    fn bake(&self) -> Result<Mesh, ModelError> {
+        let gltf_materials = self.root.get("materials").and_then(|m| m.as_array()).unwrap_or(&[]);
+        let mut materials = gltf_materials.iter().map(|m| self.material(m)).collect::<Result<Vec<_>, _>>()?;
+        // For primitives without a material
+        materials.push(Material::default());
+        let mut baker = Baker {
+            mesh: Mesh { vertices: Vec::new(), indices: Vec::new(), materials, triangle_materials: Vec::new(), colors: None },
+            has_normals: Vec::new(),
+            has_tangents: Vec::new(),
+            depth_limit: self.root.get("nodes").and_then(|n| n.as_array()).map_or(0, |n| n.len()),
+        };
+
+        for root in self.scene_roots()? {
+            self.bake_node(&mut baker, root, &Mat4::identity(), 0)?;
+        }
+        if baker.mesh.indices.is_empty() {
+            return Err(file_error(self.source, "no triangles in the default scene".to_string()));
+        }
+        // Fill in normals and tangents for primitives without them, keeping the rest
+        if baker.has_normals.contains(&false) {
+            let given: Vec<[f32; 3]> = baker.mesh.vertices.iter().map(|v| v.normal).collect();
+            baker.mesh.recompute_normals();
+            for ((vertex, normal), has) in baker.mesh.vertices.iter_mut().zip(given).zip(&baker.has_normals) {
+                if *has {
+                    vertex.normal = normal;
+                }
+            }
+        }
+        if baker.has_tangents.contains(&false) {
+            let given: Vec<[f32; 4]> = baker.mesh.vertices.iter().map(|v| v.tangent).collect();
+            baker.mesh.recompute_tangents();
+            for ((vertex, tangent), has) in baker.mesh.vertices.iter_mut().zip(given).zip(&baker.has_tangents) {
+                if *has {
+                    vertex.tangent = tangent;
+                }
+            }
+        }
+        Ok(baker.mesh)
+    }
+
+    // Root nodes of the default scene. Files without scenes get every node
+    // that is not some other node's child.
+    fn scene_roots(&self) -> Result<Vec<&'a Json>, ModelError> {
+        let scenes = self.root.get("scenes").and_then(|s| s.as_array()).unwrap_or(&[]);
+        if !scenes.is_empty() {
+            let chosen = match self.root.get("scene") {
+                Some(index) => self.item("scenes", index)?,
+                None => &scenes[0],
+            };
+            let nodes = chosen.get("nodes").and_then(|n| n.as_array()).unwrap_or(&[]);
+            return nodes.iter().map(|index| self.item("nodes", index)).collect();
+        }
+        let nodes = self.root.get("nodes").and_then(|n| n.as_array()).unwrap_or(&[]);
+        let children: Vec<usize> = nodes
+            .iter()
+            .flat_map(|n| n.get("children").and_then(|c| c.as_array()).unwrap_or(&[]))
+            .filter_map(|c| c.as_usize())
+            .collect();
+        Ok(nodes.iter().enumerate().filter(|(i, _)| !children.contains(i)).map(|(_, n)| n).collect())
+    }
+
+    fn bake_node(&self, baker: &mut Baker, node: &Json, parent: &Mat4, depth: usize) -> Result<(), ModelError> {
+        if depth > baker.depth_limit {
+            return Err(self.err(node, "node hierarchy has a cycle".to_string()));
+        }
+        let transform = parent * self.node_transform(node)?;
+        if let Some(index) = node.get("mesh") {
+            let mesh = self.item("meshes", index)?;
+            let primitives = mesh.get("primitives").and_then(|p| p.as_array()).ok_or_else(|| self.err(mesh, "mesh has no primitives".to_string()))?;
+            for primitive in primitives {
+                self.bake_primitive(baker, primitive, &transform)?;
+            }
+        }
+        for child in node.get("children").and_then(|c| c.as_array()).unwrap_or(&[]) {
+            self.bake_node(baker, self.item("nodes", child)?, &transform, depth + 1)?;
+        }
+        Ok(())
+    }
+
+    fn bake_primitive(&self, baker: &mut Baker, primitive: &Json, transform: &Mat4) -> Result<(), ModelError> {
+        let mode = self.usize_field(primitive, "mode", Some(MODE_TRIANGLES))?;
+        if mode != MODE_TRIANGLES {
+            // Points and lines have no surface to render
+            return Ok(());
+        }
+        let attributes = primitive.get("attributes").ok_or_else(|| self.err(primitive, "primitive has no attributes".to_string()))?;
+        let position = attributes.get("POSITION").ok_or_else(|| self.err(attributes, "missing POSITION attribute".to_string()))?;
+        let positions = self.vectors::<3>(position)?;
+        let normals = attributes.get("NORMAL").map(|n| self.vectors::<3>(n)).transpose()?;
+        let uvs = attributes.get("TEXCOORD_0").map(|t| self.vectors::<2>(t)).transpose()?;
+        // Tangents only mean something next to the normals they go with
+        let tangents = match normals {
+            Some(_) => attributes.get("TANGENT").map(|t| self.vectors::<4>(t)).transpose()?,
+            None => None,
+        };
+        let counts = [
+            ("NORMAL", normals.as_ref().map(|n| n.len())),
+            ("TEXCOORD_0", uvs.as_ref().map(|t| t.len())),
+            ("TANGENT", tangents.as_ref().map(|t| t.len())),
+        ];
+        for (name, len) in counts {
+            if len.is_some_and(|len| len != positions.len()) {
+                return Err(self.err(attributes.get(name).unwrap_or(attributes), format!("{} count differs from POSITION", name)));
+            }
+        }
+
+        let corners: Vec<usize> = match primitive.get("indices") {
+            Some(index) => {
+                let accessor = self.accessor(index)?;
+                if accessor.components != 1 {
+                    return Err(self.err(index, "indices must be scalars".to_string()));
+                }
+                let corners: Vec<usize> = accessor.values.iter().map(|&i| i as usize).collect();
+                if corners.len() % 3 != 0 {
+                    return Err(self.err(index, format!("{} indices do not make whole triangles", corners.len())));
+                }
+                if let Some(bad) = corners.iter().find(|&&i| i >= positions.len()) {
+                    return Err(self.err(index, format!("index {} out of range for {} vertices", bad, positions.len())));
+                }
+                corners
+            }
+            None if positions.len() % 3 != 0 => {
+                return Err(self.err(position, format!("{} vertices do not make whole triangles", positions.len())));
+            }
+            None => (0..positions.len()).collect(),
+        };
+        let material = match primitive.get("material") {
+            Some(index) => {
+                self.item("materials", index)?;
+                index.as_usize().unwrap_or(0)
+            }
+            None => baker.mesh.materials.len() - 1,
+        };
+
+        // Normals go through the inverse transpose; mirroring transforms
+        // flip the winding, which would turn the faces inside out
+        let linear = glm::mat4_to_mat3(transform);
+        let normal_matrix = linear.try_inverse().map_or(linear, |m| m.transpose());
+        let mirrored = linear.determinant() < 0.0;
+        let base = baker.mesh.vertices.len();
+        for (i, p) in positions.iter().enumerate() {
+            let world = transform * glm::vec4(p[0], p[1], p[2], 1.0);
+            let normal = normals.as_ref().map_or(glm::vec3(0.0, 0.0, 0.0), |n| {
+                let n: Vec3 = normal_matrix * glm::vec3(n[i][0], n[i][1], n[i][2]);
+                n.try_normalize(1e-12).unwrap_or(n)
+            });
+            // Tangents lie in the surface, so they move with the surface itself
+            let tangent = tangents.as_ref().map_or([0.0; 4], |t| {
+                let v: Vec3 = linear * glm::vec3(t[i][0], t[i][1], t[i][2]);
+                let v = v.try_normalize(1e-12).unwrap_or(v);
+                [v.x, v.y, v.z, if mirrored { -t[i][3] } else { t[i][3] }]
+            });
+            baker.mesh.vertices.push(Vertex {
+                pos: [world.x, world.y, world.z],
+                normal: [normal.x, normal.y, normal.z],
+                uv: uvs.as_ref().map_or([0.0; 2], |t| t[i]),
+                tangent,
+            });
+            baker.has_normals.push(normals.is_some());
+            baker.has_tangents.push(tangents.is_some());
+        }
+        for tri in corners.chunks_exact(3) {
+            let [a, b, c] = [tri[0] + base, tri[1] + base, tri[2] + base];
+            baker.mesh.indices.push(if mirrored { [a, c, b] } else { [a, b, c] });
+            baker.mesh.triangle_materials.push(material);
+        }
+        Ok(())
+    }
+}
+
+struct Baker {
+    mesh: Mesh,
+    has_normals: Vec<bool>, // per vertex, whether the file gave its normal
+    has_tangents: Vec<bool>, // and its tangent
+    depth_limit: usize, // deeper than the node count means a cycle
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn positions(mesh: &Mesh) -> Vec<[f32; 3]> {
+        mesh.vertices.iter().map(|v| v.pos).collect()
+    }
+
+    // Binary glTF around a JSON document and an optional BIN chunk
+    fn glb(json: &str, bin: &[u8]) -> Vec<u8> {
+        let mut json = json.as_bytes().to_vec();
+        json.resize(json.len().next_multiple_of(4), b' ');
+        let mut bin = bin.to_vec();
+        bin.resize(bin.len().next_multiple_of(4), 0);
+        let mut chunks = Vec::new();
+        for (kind, data) in [(CHUNK_JSON, &json), (CHUNK_BIN, &bin)] {
+            if kind == CHUNK_BIN && data.is_empty() {
+                continue;
+            }
+            chunks.extend((data.len() as u32).to_le_bytes());
+            chunks.extend(kind.to_le_bytes());
+            chunks.extend(data);
+        }
+        let mut file = Vec::new();
+        file.extend(GLB_MAGIC.to_le_bytes());
+        file.extend(2u32.to_le_bytes());
+        file.extend(((12 + chunks.len()) as u32).to_le_bytes());
+        file.extend(chunks);
+        file
+    }
+
+    // One triangle with interleaved positions and normals, and u16 indices
+    // in a second view
+    fn triangle_bin() -> Vec<u8> {
+        let mut bin = Vec::new();
+        for p in [[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]] {
+            for x in p.into_iter().chain([0.0, 0.0, 1.0]) {
+                bin.extend(x.to_le_bytes());
+            }
+        }
+        for i in [0u16, 1, 2] {
+            bin.extend(i.to_le_bytes());
+        }
+        bin
+    }
+
+    fn triangle_gltf(view: &str, position_count: &str) -> String {
+        format!(
+            r#"{{
+                "asset": {{"version": "2.0"}},
+                "buffers": [{{"byteLength": 78}}],
+                "bufferViews": [{view}, {{"buffer": 0, "byteOffset": 72, "byteLength": 6}}],
+                "accessors": [
+                    {{"bufferView": 0, "componentType": 5126, "type": "VEC3", "count": {position_count}}},
+                    {{"bufferView": 0, "byteOffset": 12, "componentType": 5126, "type": "VEC3", "count": 3}},
+                    {{"bufferView": 1, "componentType": 5123, "type": "SCALAR", "count": 3}}
+                ],
+                "meshes": [{{"primitives": [{{"attributes": {{"POSITION": 0, "NORMAL": 1}}, "indices": 2}}]}}],
+                "nodes": [{{"mesh": 0, "translation": [0, 0, 5]}}],
+                "scenes": [{{"nodes": [0]}}]
+            }}"#
+        )
+    }
+
+    const INTERLEAVED_VIEW: &str = r#"{"buffer": 0, "byteLength": 72, "byteStride": 24}"#;
+
+    fn load(json: &str) -> Result<Mesh, ModelError> {
+        parse_glb(&glb(json, &triangle_bin()), "test.glb", Path::new(""))
+    }
+
+    #[test]
+    fn obj_resolves_negative_indices() {
+        let text = "v 0 0 0\nv 1 0 0\nv 1 1 0\nvn 0 0 1\nf -3//-1 -2//-1 -1//-1\nv 0 1 0\nf 1//1 3//1 -1//1\n";
+        let mesh = parse_obj(text, "test.obj").unwrap();
+        assert_eq!(mesh.indices, [[0, 1, 2], [0, 2, 3]]);
+        assert_eq!(positions(&mesh), [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]]);
+        assert!(mesh.vertices.iter().all(|v| v.normal == [0.0, 0.0, 1.0]));
+    }
+
+    #[test]
+    fn obj_splits_polygons_into_fans() {
+        let text = "v 1 0 0\nv 0 1 0\nv -1 0 0\nv 0 -1 0\nv 0.5 -0.5 0\nf 1 2 3 4 5\n";
+        let mesh = parse_obj(text, "test.obj").unwrap();
+        assert_eq!(mesh.indices, [[0, 1, 2], [0, 2, 3], [0, 3, 4]]);
+    }
+
+    #[test]
+    fn obj_reports_bad_indices_by_line() {
+        for (text, line) in [("v 0 0 0\nv 1 0 0\nv 0 1 0\n\nf 1 2 4\n", 5), ("v 0 0 0\nf 0 1 1\n", 2), ("v 0 0 0\nf 1 -2 1\n", 2), ("v 0 0\n", 1)] {
+            let error = parse_obj(text, "test.obj").err().unwrap();
+            assert!(matches!(error.location, Location::Line(l) if l == line), "{:?}: {}", text, error);
+        }
+    }
+
+    #[test]
+    fn obj_export_round_trips() {
+        let mut mesh = Mesh::icosphere(2, 0.7);
+        mesh.displace_by_height(|p| 0.1 * p.x.abs());
+        mesh.colors = Some(mesh.vertices.iter().map(|v| glm::make_vec3(&v.pos).abs() / 0.8).collect());
+        let mut out = Vec::new();
+        write_obj(&mesh, &mut out).unwrap();
+        let read = parse_obj(std::str::from_utf8(&out).unwrap(), "export.obj").unwrap();
+
+        // The loader numbers vertices as faces first use them, so triangles
+        // are compared corner by corner
+        assert_eq!(read.vertices.len(), mesh.vertices.len());
+        assert_eq!(read.indices.len(), mesh.indices.len());
+        let (read_colors, colors) = (read.colors.as_ref().unwrap(), mesh.colors.as_ref().unwrap());
+        for (read_tri, tri) in read.indices.iter().zip(&mesh.indices) {
+            for (&i, &j) in read_tri.iter().zip(tri) {
+                let (a, b) = (&read.vertices[i], &mesh.vertices[j]);
+                assert_eq!(a.pos, b.pos);
+                assert_eq!(a.normal, b.normal);
+                assert!((a.uv[0] - b.uv[0]).abs() < 1e-6 && (a.uv[1] - b.uv[1]).abs() < 1e-6);
+                assert_eq!(read_colors[i], colors[j]);
+            }
+        }
+    }
+
+    #[test]
+    fn glb_reads_interleaved_accessors() {
+        let mesh = load(&triangle_gltf(INTERLEAVED_VIEW, "3")).unwrap();
+        assert_eq!(positions(&mesh), [[0.0, 0.0, 5.0], [1.0, 0.0, 5.0], [0.0, 1.0, 5.0]]);
+        assert!(mesh.vertices.iter().all(|v| v.normal == [0.0, 0.0, 1.0]));
+        assert_eq!(mesh.indices, [[0, 1, 2]]);
+    }
+
+    #[test]
+    fn glb_rejects_views_and_accessors_out_of_range() {
+        let views = [
+            r#"{"buffer": 0, "byteLength": 80, "byteStride": 24}"#,
+            r#"{"buffer": 0, "byteOffset": 8, "byteLength": 72, "byteStride": 24}"#,
+            r#"{"buffer": 0, "byteOffset": 1e30, "byteLength": 72}"#,
+            r#"{"buffer": 0, "byteOffset": 18446744073709551615, "byteLength": 72}"#,
+            r#"{"buffer": 0, "byteOffset": 9007199254740992, "byteLength": 9007199254740992}"#,
+            r#"{"buffer": 1, "byteLength": 72}"#,
+            r#"{"buffer": 0, "byteLength": 72, "byteStride": 9007199254740992}"#,
+        ];
+        for view in views {
+            let error = load(&triangle_gltf(view, "3")).err().unwrap();
+            assert!(matches!(error.location, Location::Offset(_)), "{}: {}", view, error);
+        }
+        for count in ["4", "1e30", "9007199254740992", "-1"] {
+            assert!(load(&triangle_gltf(INTERLEAVED_VIEW, count)).is_err(), "count {}", count);
+        }
+    }
+
+    #[test]
+    fn glb_rejects_partial_triangles() {
+        let json = triangle_gltf(INTERLEAVED_VIEW, "3").replace(r#""SCALAR", "count": 3"#, r#""SCALAR", "count": 2"#);
+        let error = load(&json).err().unwrap();
+        assert!(error.message.contains("whole triangles"), "{}", error);
+
+        let json = triangle_gltf(INTERLEAVED_VIEW, "2").replace(r#", "indices": 2"#, "").replace(r#", "NORMAL": 1"#, "");
+        let error = load(&json).err().unwrap();
+        assert!(error.message.contains("whole triangles"), "{}", error);
+    }
+
+    #[test]
+    fn glb_rejects_bad_containers() {
+        let file = glb(&triangle_gltf(INTERLEAVED_VIEW, "3"), &triangle_bin());
+        for bad in [&file[..10], &file[..file.len() - 8]] {
+            assert!(parse_glb(bad, "test.glb", Path::new("")).is_err());
+        }
+        let mut version = file.clone();
+        version[4] = 1;
+        assert!(parse_glb(&version, "test.glb", Path::new("")).is_err());
+    }
+}
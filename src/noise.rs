@@ -0,0 +1,114 @@
+use nalgebra_glm as glm;
+use crate::math::Vec3;
+
+// Integer hash (lowbias32). Cheap and well distributed, good enough for
+// procedural patterns where we need the same value for the same input.
+pub fn hash_u32(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb_352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846c_a68b);
+    x ^= x >> 16;
+    x
+}
+
+// Hash a lattice point into a u32
+pub fn hash3(x: i32, y: i32, z: i32) -> u32 {
+    let h = hash_u32(x as u32);
+    let h = hash_u32(h ^ (y as u32).wrapping_mul(0x9e37_79b9));
+    hash_u32(h ^ (z as u32).wrapping_mul(0x85eb_ca6b))
+}
+
+// Map a hash to [0, 1)
+pub fn hash_to_unit(h: u32) -> f32 {
+    (h >> 8) as f32 / (1u32 << 24) as f32
+}
+
+// Convenience: n-th random number in [0, 1) of a seeded stream
+pub fn hash_f32(seed: u32, n: u32) -> f32 {
+    hash_to_unit(hash_u32(seed ^ hash_u32(n.wrapping_add(0x632b_e5ab))))
+}
+
+// Fold a 64-bit scene seed into the 32 bits the hashes work with
+pub fn seed32(seed: u64) -> u32 {
+    hash_u32(seed as u32 ^ hash_u32((seed >> 32) as u32))
+}
+
+// Per-consumer shift of the noise domain derived from a scene seed, so each
+// seed samples a different region of the same noise. `stream` tells apart
+// the things sharing one seed (terrain, clouds, ...). Kept small enough that
+// high-frequency patterns do not lose float precision.
+pub fn seed_offset(seed: u32, stream: u32) -> Vec3 {
+    let h = hash_u32(seed ^ hash_u32(stream.wrapping_mul(0x9e37_79b9)));
+    glm::vec3(hash_f32(h, 0), hash_f32(h, 1), hash_f32(h, 2)) * 32.0
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+// Trilinear value noise in [0, 1]
+pub fn value_noise(p: &Vec3) -> f32 {
+    let ix = p.x.floor();
+    let iy = p.y.floor();
+    let iz = p.z.floor();
+    let fx = fade(p.x - ix);
+    let fy = fade(p.y - iy);
+    let fz = fade(p.z - iz);
+    let (ix, iy, iz) = (ix as i32, iy as i32, iz as i32);
+
+    let v = |dx: i32, dy: i32, dz: i32| hash_to_unit(hash3(ix + dx, iy + dy, iz + dz));
+    let x00 = v(0, 0, 0) + (v(1, 0, 0) - v(0, 0, 0)) * fx;
+    let x10 = v(0, 1, 0) + (v(1, 1, 0) - v(0, 1, 0)) * fx;
+    let x01 = v(0, 0, 1) + (v(1, 0, 1) - v(0, 0, 1)) * fx;
+    let x11 = v(0, 1, 1) + (v(1, 1, 1) - v(0, 1, 1)) * fx;
+    let y0 = x00 + (x10 - x00) * fy;
+    let y1 = x01 + (x11 - x01) * fy;
+    y0 + (y1 - y0) * fz
+}
+
+// Fractal sum of value noise, normalized back to [0, 1]
+pub fn fbm(p: &Vec3, octaves: u32) -> f32 {
+    let mut sum = 0.0;
+    let mut amp = 0.5;
+    let mut norm = 0.0;
+    let mut q = *p;
+    for _ in 0..octaves {
+        sum += value_noise(&q) * amp;
+        norm += amp;
+        amp *= 0.5;
+        q = q * 2.03 + glm::vec3(17.1, 3.7, 9.3);
+    }
+    sum / norm
+}
+
+// Cellular (Worley) noise: distances to the closest and second closest
+// jittered feature points, in cell units.
+pub fn worley(p: &Vec3) -> (f32, f32) {
+    let cx = p.x.floor() as i32;
+    let cy = p.y.floor() as i32;
+    let cz = p.z.floor() as i32;
+    let mut f1 = f32::INFINITY;
+    let mut f2 = f32::INFINITY;
+    for dz in -1..=1 {
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let (x, y, z) = (cx + dx, cy + dy, cz + dz);
+                let h = hash3(x, y, z);
+                let feature = glm::vec3(
+                    x as f32 + hash_f32(h, 0),
+                    y as f32 + hash_f32(h, 1),
+                    z as f32 + hash_f32(h, 2),
+                );
+                let d = glm::distance2(&feature, p);
+                if d < f1 {
+                    f2 = f1;
+                    f1 = d;
+                } else if d < f2 {
+                    f2 = d;
+                }
+            }
+        }
+    }
+    (f1.sqrt(), f2.sqrt())
+}
@@ -0,0 +1,160 @@
+use nalgebra_glm as glm;
+use crate::math::Vec3;
+
+// Gravitational parameter of the sun in scene units. Chosen so a circular
+// orbit at the rocky planet's radius (2.0) matches its 0.6 rad/s.
+pub const SUN_GM: f32 = 2.88;
+
+// Keplerian orbit around the sun at the origin. The reference plane is the
+// XZ plane the planets move in, with angles increasing from +X towards +Z
+// like theirs. Angles are in radians.
+pub struct KeplerOrbit {
+    pub semi_major_axis: f32,
+    pub eccentricity: f32,   // 0 = circle, must stay below 1
+    pub inclination: f32,    // tilt of the orbit against the XZ plane
+    pub ascending_node: f32, // where the orbit rises through the XZ plane
+    pub periapsis_arg: f32,  // angle from the ascending node to periapsis
+    pub mean_anomaly: f32,   // mean anomaly at time 0
+}
+
+impl KeplerOrbit {
+    pub fn circular(radius: f32, phase: f32) -> Self {
+        KeplerOrbit {
+            semi_major_axis: radius,
+            eccentricity: 0.0,
+            inclination: 0.0,
+            ascending_node: 0.0,
+            periapsis_arg: 0.0,
+            mean_anomaly: phase,
+        }
+    }
+
+    // Average angular speed along the orbit
+    pub fn mean_motion(&self) -> f32 {
+        (SUN_GM / self.semi_major_axis.powi(3)).sqrt()
+    }
+
+    pub fn period(&self) -> f32 {
+        2.0 * std::f32::consts::PI / self.mean_motion()
+    }
+
+    // Distance from the sun and true anomaly at `time`
+    fn radius_and_anomaly(&self, time: f32) -> (f32, f32) {
+        let e = self.eccentricity;
+        let m = self.mean_anomaly + self.mean_motion() * time;
+        let ea = eccentric_anomaly(m, e);
+        let nu = 2.0 * ((1.0 + e).sqrt() * (ea * 0.5).sin()).atan2((1.0 - e).sqrt() * (ea * 0.5).cos());
+        (self.semi_major_axis * (1.0 - e * ea.cos()), nu)
+    }
+
+    pub fn position(&self, time: f32) -> Vec3 {
+        let (r, nu) = self.radius_and_anomaly(time);
+        self.to_world(r, self.periapsis_arg + nu)
+    }
+
+    // Velocity from the vis-viva speed and the flight path angle
+    pub fn velocity(&self, time: f32) -> Vec3 {
+        let (r, nu) = self.radius_and_anomaly(time);
+        let e = self.eccentricity;
+        let p = self.semi_major_axis * (1.0 - e * e);
+        let h = (SUN_GM * p).sqrt();
+        let radial = SUN_GM / h * e * nu.sin();
+        let tangential = h / r;
+        let u = self.periapsis_arg + nu;
+        let out = self.to_world(1.0, u);
+        let along = self.to_world(1.0, u + std::f32::consts::FRAC_PI_2);
+        out * radial + along * tangential
+    }
+
+    // Point at distance `r` and argument of latitude `u` (angle from the
+    // ascending node within the orbital plane)
+    fn to_world(&self, r: f32, u: f32) -> Vec3 {
+        let (sn, cn) = self.ascending_node.sin_cos();
+        let (si, ci) = self.inclination.sin_cos();
+        let (su, cu) = u.sin_cos();
+        glm::vec3(
+            r * (cn * cu - sn * su * ci),
+            r * su * si,
+            r * (sn * cu + cn * su * ci),
+        )
+    }
+}
+
+// Solve Kepler's equation M = E - e sin E for the eccentric anomaly with
+// Newton's method. Starting from pi handles eccentricities close to 1.
+pub fn eccentric_anomaly(mean_anomaly: f32, eccentricity: f32) -> f32 {
+    let m = mean_anomaly.rem_euclid(2.0 * std::f32::consts::PI);
+    let mut ea = if eccentricity > 0.8 { std::f32::consts::PI } else { m };
+    for _ in 0..12 {
+        let f = ea - eccentricity * ea.sin() - m;
+        let step = f / (1.0 - eccentricity * ea.cos());
+        ea -= step;
+        if step.abs() < 1e-6 {
+            break;
+        }
+    }
+    ea
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    fn tilted(eccentricity: f32) -> KeplerOrbit {
+        KeplerOrbit {
+            semi_major_axis: 5.0,
+            eccentricity,
+            inclination: 0.4,
+            ascending_node: 1.1,
+            periapsis_arg: 2.3,
+            mean_anomaly: 0.0,
+        }
+    }
+
+    #[test]
+    fn circular_orbit_keeps_its_radius_and_speed() {
+        let orbit = tilted(0.0);
+        let speed = (SUN_GM / orbit.semi_major_axis).sqrt();
+        for i in 0..50 {
+            let t = orbit.period() * i as f32 / 50.0;
+            assert!((glm::length(&orbit.position(t)) - 5.0).abs() < 1e-4);
+            assert!((glm::length(&orbit.velocity(t)) - speed).abs() < 1e-4);
+            // Motion is all along the circle
+            assert!(glm::dot(&orbit.position(t), &orbit.velocity(t)).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn periapsis_and_apoapsis_distances() {
+        for e in [0.1, 0.5, 0.9, 0.99] {
+            let orbit = tilted(e);
+            assert!((glm::length(&orbit.position(0.0)) - 5.0 * (1.0 - e)).abs() < 1e-4, "e = {}", e);
+            assert!((glm::length(&orbit.position(0.5 * orbit.period())) - 5.0 * (1.0 + e)).abs() < 1e-3, "e = {}", e);
+        }
+    }
+
+    #[test]
+    fn one_period_comes_back_to_the_start() {
+        for e in [0.0, 0.3, 0.8, 0.97] {
+            let orbit = KeplerOrbit { mean_anomaly: 0.7, ..tilted(e) };
+            for start in [0.0, 1.3, 4.0] {
+                let a = orbit.position(start);
+                let b = orbit.position(start + orbit.period());
+                assert!(glm::distance(&a, &b) < 1e-3, "e = {}: {:?} vs {:?}", e, a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn solver_converges_for_eccentricities_near_one() {
+        for e in [0.0, 0.5, 0.9, 0.99, 0.999] {
+            for i in 0..=200 {
+                let m = 2.0 * PI * i as f32 / 200.0 + 1e-3;
+                let ea = eccentric_anomaly(m, e);
+                let residual = ea - e * ea.sin() - m.rem_euclid(2.0 * PI);
+                assert!(residual.abs() < 1e-5, "e = {}, M = {}: residual {}", e, m, residual);
+            }
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use nalgebra_glm as glm;
+use crate::color::Color;
+use crate::framebuffer::Framebuffer;
+use crate::math::{self, Vec3};
+use rand::Rng;
+use std::path::Path;
+
+// Settings for Scene::path_trace_pass
+pub struct PathTraceOptions {
+    pub time: f32,
+    pub max_bounces: u32, // surfaces a path reflects off after the first, 0 for direct light only
+    pub seed: u64,        // picks the random numbers; passes differ by their index
+}
+
+impl Default for PathTraceOptions {
+    fn default() -> Self {
+        PathTraceOptions { time: 0.0, max_bounces: 4, seed: 0 }
+    }
+}
+
+// Running sum of path-traced passes. The image is their mean, which
+// converges on the rendering as passes are added.
+pub struct Accumulator {
+    pub width: usize,
+    pub height: usize,
+    sum: Vec<Vec3>,
+    passes: u32,
+}
+
+impl Accumulator {
+    pub fn new(width: usize, height: usize) -> Self {
+        Accumulator { width, height, sum: vec![glm::vec3(0.0, 0.0, 0.0); width * height], passes: 0 }
+    }
+
+    pub fn passes(&self) -> u32 {
+        self.passes
+    }
+
+    // Add one pass, the size of the accumulator. Colors are summed before
+    // clamping, so bright samples keep their weight.
+    pub fn add(&mut self, pass: &Framebuffer) {
+        for (sum, color) in self.sum.iter_mut().zip(&pass.pixels) {
+            *sum += color.to_vec3();
+        }
+        self.passes += 1;
+    }
+
+    // Mean of the passes so far
+    pub fn image(&self) -> Framebuffer {
+        let mut image = Framebuffer::new(self.width, self.height);
+        let scale = 1.0 / self.passes.max(1) as f32;
+        for (pixel, sum) in image.pixels.iter_mut().zip(&self.sum) {
+            *pixel = Color::from_vec3(&(sum * scale));
+        }
+        image
+    }
+
+    // Write the mean as an image, in any format the image crate can pick
+    // from the extension
+    pub fn save(&self, path: &Path) -> image::ImageResult<()> {
+        let pixels = self.image().pixels.iter().flat_map(|c| c.clamp_u8()).collect();
+        let buffer = image::RgbImage::from_raw(self.width as u32, self.height as u32, pixels).expect("buffer matches the image size");
+        buffer.save(path)
+    }
+}
+
+// Direction on the hemisphere around `normal` with density cos(theta) / pi
+pub fn cosine_hemisphere(normal: &Vec3, rng: &mut impl Rng) -> Vec3 {
+    let (u, v): (f32, f32) = (rng.gen(), rng.gen());
+    let (r, phi) = (u.sqrt(), 2.0 * std::f32::consts::PI * v);
+    let (e1, e2) = math::perpendiculars(normal);
+    e1 * (r * phi.cos()) + e2 * (r * phi.sin()) + normal * (1.0 - u).sqrt()
+}
+
+// Direction spread evenly over the solid angle of the cone around `axis`
+// out to `cos_max`, such as the disk of a sphere seen from outside
+pub fn cone_direction(axis: &Vec3, cos_max: f32, rng: &mut impl Rng) -> Vec3 {
+    let (u, v): (f32, f32) = (rng.gen(), rng.gen());
+    let cos_theta = 1.0 - u * (1.0 - cos_max);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * std::f32::consts::PI * v;
+    let (e1, e2) = math::perpendiculars(axis);
+    e1 * (sin_theta * phi.cos()) + e2 * (sin_theta * phi.sin()) + axis * cos_theta
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    const SAMPLES: usize = 20000;
+
+    fn axes(rng: &mut StdRng) -> Vec<Vec3> {
+        let mut axes = vec![glm::vec3(0.0, 1.0, 0.0), glm::vec3(0.0, -1.0, 0.0), glm::vec3(1.0, 0.0, 0.0)];
+        axes.extend((0..5).map(|_| glm::normalize(&glm::vec3(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0)))));
+        axes
+    }
+
+    #[test]
+    fn cosine_hemisphere_stays_on_the_normal_side() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for normal in axes(&mut rng) {
+            let mut mean_cos = 0.0;
+            for _ in 0..SAMPLES {
+                let d = cosine_hemisphere(&normal, &mut rng);
+                assert!((glm::length(&d) - 1.0).abs() < 1e-4);
+                let cos = glm::dot(&d, &normal);
+                assert!(cos >= -1e-6);
+                mean_cos += cos / SAMPLES as f32;
+            }
+            // Cosine weighting averages cos(theta) to 2/3
+            assert!((mean_cos - 2.0 / 3.0).abs() < 0.01, "mean cosine {}", mean_cos);
+        }
+    }
+
+    #[test]
+    fn cone_direction_stays_inside_the_cone() {
+        let mut rng = StdRng::seed_from_u64(2);
+        for axis in axes(&mut rng) {
+            for cos_max in [0.999, 0.9, 0.5, 0.0] {
+                let mut mean_cos = 0.0;
+                for _ in 0..SAMPLES {
+                    let d = cone_direction(&axis, cos_max, &mut rng);
+                    assert!((glm::length(&d) - 1.0).abs() < 1e-4);
+                    let cos = glm::dot(&d, &axis);
+                    assert!(cos >= cos_max - 1e-5, "{} outside {}", cos, cos_max);
+                    mean_cos += cos / SAMPLES as f32;
+                }
+                // Even over the solid angle averages cos(theta) halfway
+                assert!((mean_cos - (1.0 + cos_max) / 2.0).abs() < 0.01, "mean cosine {} for {}", mean_cos, cos_max);
+            }
+        }
+    }
+
+    #[test]
+    fn identical_passes_average_to_themselves() {
+        let (width, height) = (7, 5);
+        let mut pass = Framebuffer::new(width, height);
+        for (i, pixel) in pass.pixels.iter_mut().enumerate() {
+            // Values past 1 too, which must not be clamped on the way
+            *pixel = Color::new(i as f32 * 0.1, 0.25, 3.0 - i as f32 * 0.05);
+        }
+        for passes in [1, 3, 16] {
+            let mut accumulator = Accumulator::new(width, height);
+            for _ in 0..passes {
+                accumulator.add(&pass);
+            }
+            assert_eq!(accumulator.passes(), passes);
+            for (mean, expected) in accumulator.image().pixels.iter().zip(&pass.pixels) {
+                assert!(glm::distance(&mean.to_vec3(), &expected.to_vec3()) < 1e-5);
+            }
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use nalgebra_glm as glm;
+use crate::framebuffer::Framebuffer;
+use crate::math::{Camera, Mat4, Vec3};
+
+// Geometry closer to the camera than its near plane is clipped away. Depth
+// is kept as a distance rather than 1/w, so the plane can sit close enough
+// for views from just above a surface.
+const NEAR: f32 = 1e-3;
+
+// Triangle corner in world space
+pub struct Corner {
+    pub pos: Vec3,
+    pub normal: Vec3,
+}
+
+// A pixel covered by a triangle, with the corners' attributes interpolated
+// perspective-correctly
+pub struct Fragment {
+    pub x: usize,
+    pub y: usize,
+    pub depth: f32, // distance from the camera, like the ray caster's hit distance
+    pub pos: Vec3,
+    pub normal: Vec3,
+}
+
+// Corner after projection. World attributes stay as they are; they are
+// weighted by `inv_w` while interpolating.
+struct Projected {
+    x: f32,
+    y: f32,
+    inv_w: f32,
+    pos: Vec3,
+    normal: Vec3,
+}
+
+// Half-space triangle rasterizer. Pixels are sampled at the same points the
+// ray caster shoots its rays through, so both backends cover the same pixels.
+pub struct Rasterizer {
+    view_proj: Mat4,
+    eye: Vec3,
+    width: usize,
+    height: usize,
+}
+
+impl Rasterizer {
+    pub fn new(camera: &Camera, width: usize, height: usize) -> Self {
+        Rasterizer { view_proj: camera.view_projection(), eye: camera.position, width, height }
+    }
+
+    // Draw a triangle against the depth buffer of `target`. `fragment` is
+    // called for every pixel closer than what is already there; it returns
+    // false to discard the pixel, otherwise its depth is written. Colors
+    // are left to the caller.
+    pub fn draw_triangle(&self, target: &mut Framebuffer, corners: [&Corner; 3], cull_back: bool, mut fragment: impl FnMut(&Fragment) -> bool) {
+        if cull_back {
+            // Counter-clockwise corners face out of the surface
+            let face = glm::cross(&(corners[1].pos - corners[0].pos), &(corners[2].pos - corners[0].pos));
+            if glm::dot(&face, &(self.eye - corners[0].pos)) <= 0.0 {
+                return;
+            }
+        }
+
+        // Clip against the near plane; a triangle becomes up to a quad
+        let clip: Vec<(glm::Vec4, &Corner)> = corners.iter().map(|c| (self.view_proj * glm::vec4(c.pos.x, c.pos.y, c.pos.z, 1.0), *c)).collect();
+        let mut polygon: Vec<(glm::Vec4, Vec3, Vec3)> = Vec::with_capacity(4);
+        for i in 0..3 {
+            let (a, ca) = clip[i];
+            let (b, cb) = clip[(i + 1) % 3];
+            if a.w >= NEAR {
+                polygon.push((a, ca.pos, ca.normal));
+            }
+            if (a.w >= NEAR) != (b.w >= NEAR) {
+                let s = (NEAR - a.w) / (b.w - a.w);
+                polygon.push((a + (b - a) * s, ca.pos + (cb.pos - ca.pos) * s, ca.normal + (cb.normal - ca.normal) * s));
+            }
+        }
+        if polygon.len() < 3 {
+            return;
+        }
+
+        // Clip space to pixels, matching the ray caster's u = x / width,
+        // v = 1 - y / height
+        let projected: Vec<Projected> = polygon
+            .into_iter()
+            .map(|(c, pos, normal)| {
+                let inv_w = 1.0 / c.w;
+                Projected {
+                    x: (c.x * inv_w + 1.0) * 0.5 * self.width as f32,
+                    y: (1.0 - c.y * inv_w) * 0.5 * self.height as f32,
+                    inv_w,
+                    pos,
+                    normal,
+                }
+            })
+            .collect();
+        for i in 1..projected.len() - 1 {
+            self.fill(target, [&projected[0], &projected[i], &projected[i + 1]], &mut fragment);
+        }
+    }
+
+    fn fill(&self, target: &mut Framebuffer, [a, b, c]: [&Projected; 3], fragment: &mut impl FnMut(&Fragment) -> bool) {
+        let area = edge(a, b, c.x, c.y);
+        if area.abs() < 1e-9 {
+            return;
+        }
+        let min_x = a.x.min(b.x).min(c.x).ceil().max(0.0);
+        let min_y = a.y.min(b.y).min(c.y).ceil().max(0.0);
+        let max_x = a.x.max(b.x).max(c.x).floor().min(self.width as f32 - 1.0);
+        let max_y = a.y.max(b.y).max(c.y).floor().min(self.height as f32 - 1.0);
+        if min_x > max_x || min_y > max_y {
+            return;
+        }
+
+        // Inside is where the edge functions have the area's sign. Pixels
+        // exactly on an edge go to the triangle it is a top or left edge of,
+        // so triangles sharing an edge never both draw a pixel, nor skip it.
+        let side = area.signum();
+        let owned = [owns_edge(b, c, side), owns_edge(c, a, side), owns_edge(a, b, side)];
+        for y in min_y as usize..=max_y as usize {
+            for x in min_x as usize..=max_x as usize {
+                let (px, py) = (x as f32, y as f32);
+                let e = [shared_edge(b, c, px, py), shared_edge(c, a, px, py), shared_edge(a, b, px, py)];
+                if (0..3).any(|i| e[i] * side < 0.0 || (e[i] == 0.0 && !owned[i])) {
+                    continue;
+                }
+                // Barycentric weights from the edge functions; dividing by
+                // the area makes them positive inside for either winding
+                let (wa, wb) = (e[0] / area, e[1] / area);
+                let wc = 1.0 - wa - wb;
+                // Screen-space weights would bend attributes towards the far
+                // corner; weighting by 1/w undoes the perspective divide
+                let (ka, kb, kc) = (wa * a.inv_w, wb * b.inv_w, wc * c.inv_w);
+                let norm = 1.0 / (ka + kb + kc);
+                let pos = (a.pos * ka + b.pos * kb + c.pos * kc) * norm;
+                let depth = glm::distance(&pos, &self.eye);
+                let index = y * self.width + x;
+                if depth >= target.depth[index] {
+                    continue;
+                }
+                let normal = (a.normal * ka + b.normal * kb + c.normal * kc) * norm;
+                if fragment(&Fragment { x, y, depth, pos, normal }) {
+                    target.depth[index] = depth;
+                }
+            }
+        }
+    }
+}
+
+// Twice the signed area of the triangle (a, b, p)
+fn edge(a: &Projected, b: &Projected, px: f32, py: f32) -> f32 {
+    (b.x - a.x) * (py - a.y) - (b.y - a.y) * (px - a.x)
+}
+
+// `edge`, evaluated with the ends in a fixed order, so that the triangles on
+// either side of an edge get exactly opposite values
+fn shared_edge(a: &Projected, b: &Projected, px: f32, py: f32) -> f32 {
+    if (a.y, a.x) <= (b.y, b.x) {
+        edge(a, b, px, py)
+    } else {
+        -edge(b, a, px, py)
+    }
+}
+
+// Whether the edge from `a` to `b` is a top or left edge of a triangle
+// lying on its `side` (the sign of `edge` inside): the triangle is to its
+// right, or straight below a horizontal edge. Of two triangles sharing an
+// edge, exactly one sees it that way.
+fn owns_edge(a: &Projected, b: &Projected, side: f32) -> bool {
+    // Direction `edge` grows in, into the triangle
+    let (gx, gy) = ((a.y - b.y) * side, (b.x - a.x) * side);
+    gx > 0.0 || (gx == 0.0 && gy > 0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const WIDTH: usize = 64;
+    const HEIGHT: usize = 48;
+
+    // How many times each pixel is drawn when every triangle of a grid
+    // over the plane z = -1, wider than the view, is filled
+    fn coverage(jitter: f32, seed: u64) -> Vec<u32> {
+        let camera = Camera::new(glm::vec3(0.0, 0.0, 0.0), glm::vec3(0.0, 0.0, -1.0), glm::vec3(0.0, 1.0, 0.0), std::f32::consts::FRAC_PI_2, WIDTH as f32 / HEIGHT as f32);
+        let rasterizer = Rasterizer::new(&camera, WIDTH, HEIGHT);
+        let mut target = Framebuffer::new(WIDTH, HEIGHT);
+        let mut rng = StdRng::seed_from_u64(seed);
+        // Grid lines through the middle of the view fall on pixel centers.
+        // Jittered corners stay too close to home for a triangle to fold over.
+        let n = 16;
+        let corner = |i: usize, j: usize, rng: &mut StdRng| {
+            let border = i == 0 || j == 0 || i == n || j == n;
+            let offset = if border { 0.0 } else { jitter };
+            let (x, y) = (i as f32 / n as f32 * 4.0 - 2.0, j as f32 / n as f32 * 4.0 - 2.0);
+            Corner { pos: glm::vec3(x + rng.gen_range(-1.0..=1.0) * offset, y + rng.gen_range(-1.0..=1.0) * offset, -1.0), normal: glm::vec3(0.0, 0.0, 1.0) }
+        };
+        let corners: Vec<Vec<Corner>> = (0..=n).map(|j| (0..=n).map(|i| corner(i, j, &mut rng)).collect()).collect();
+        let mut counts = vec![0u32; WIDTH * HEIGHT];
+        for j in 0..n {
+            for i in 0..n {
+                let [a, b, c, d] = [&corners[j][i], &corners[j][i + 1], &corners[j + 1][i + 1], &corners[j + 1][i]];
+                // Alternate the diagonal so edges run both ways
+                let triangles = if (i + j) % 2 == 0 { [[a, b, c], [a, c, d]] } else { [[a, b, d], [b, c, d]] };
+                for triangle in triangles {
+                    rasterizer.draw_triangle(&mut target, triangle, true, |f| {
+                        counts[f.y * WIDTH + f.x] += 1;
+                        false
+                    });
+                }
+            }
+        }
+        counts
+    }
+
+    #[test]
+    fn shared_edges_cover_each_pixel_once() {
+        for (jitter, seed) in [(0.0, 0), (0.05, 1), (0.05, 2), (0.05, 3)] {
+            let counts = coverage(jitter, seed);
+            for (index, &count) in counts.iter().enumerate() {
+                assert_eq!(count, 1, "pixel ({}, {}) drawn {} times with jitter {}", index % WIDTH, index / WIDTH, count, jitter);
+            }
+        }
+    }
+}
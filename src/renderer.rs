@@ -1,7 +1,25 @@
+use crate::asteroids::{AsteroidBelt, BeltFrame, BeltParams};
+use crate::bvh::{Aabb, Bvh, Hit, Hittable};
+use crate::catalog;
+use crate::color::{self, Color};
+use crate::comet::{Comet, Sprite};
+use crate::framebuffer::Framebuffer;
+use crate::galaxy::MilkyWay;
+use crate::lod::SphereLod;
 use crate::math::{Vec3, Ray, Camera, Sphere};
-use crate::shader::{sun_shader, rocky_shader, gas_giant_shader};
-use minifb::{Window, WindowOptions};
-use rand::Rng;
+use crate::mesh::{Mesh, Model, Vertex};
+use crate::orbit::KeplerOrbit;
+use crate::pathtracer::{cone_direction, cosine_hemisphere, Accumulator, PathTraceOptions};
+use crate::sdf::Sdf;
+use crate::raster::{Corner, Fragment, Rasterizer};
+use crate::starfield::{self, Scintillation, Star, StarField};
+use crate::shader::{star_prominences, sphere_uv, BodyParams, PbrMaterial, ShaderRegistry, ShadingContext, StarParams, SurfaceShader};
+use crate::shader::{cloud_shader, cloud_shadow, CloudLayer, OceanParams};
+use crate::shader::{secondary_rays, SecondaryRay, GLOSSY_LIMIT};
+use crate::shader::{asteroid_shader, pbr_reflectance, terrain_height, Lighting, NightEmission, Reflector};
+use crate::noise;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use nalgebra_glm as glm;
 use std::f32::consts::PI;
 use rayon::prelude::*;
@@ -9,27 +27,245 @@ use rayon::prelude::*;
 pub const WIDTH: usize = 800;
 pub const HEIGHT: usize = 600;
 
+// Widest star image the sky grid is built for, in radians. Covers the
+// brightest star's halo down to a FOV of about 20 degrees.
+const STAR_REACH: f32 = 0.03;
+
+// Seed used when none is given, so plain runs always look the same
+pub const DEFAULT_SEED: u64 = 5;
+
+// Noise streams derived from the scene seed, one per consumer
+const STREAM_ROCKY: u32 = 1;
+const STREAM_CLOUDS: u32 = 2;
+const STREAM_GAS: u32 = 3;
+const STREAM_GALAXY: u32 = 4;
+const STREAM_ASTEROIDS: u32 = 5;
+const STREAM_COMETS: u32 = 6;
+const STREAM_MOONS: u32 = 7;
+const STREAM_SDF_MOONS: u32 = 8;
+const STREAM_SHUTTER: u32 = 9;
+
+// Comet nuclei are among the darkest objects in the solar system
+const NUCLEUS_ALBEDO: f32 = 0.05;
+// Surfaces whose shader is not registered
+const MISSING_SHADER: Vec3 = Vec3::new(1.0, 0.0, 1.0);
+// Largest sprite drawn, as a Gaussian sigma in pixels
+const MAX_SPRITE_PIXELS: f32 = 48.0;
+// Path-traced rays leave a surface this far out along its normal, clear of
+// the sphere tracer's hit tolerance
+const RAY_OFFSET: f32 = 1e-3;
+
+// Tessellation for the rasterizer: icosphere subdivisions for belt rocks.
+// Planets and stars follow Scene::sphere_lod.
+const ROCK_SUBDIVISIONS: usize = 1;
+
+// How frames are drawn. Both backends run the same shaders and produce the
+// same picture, up to tessellation; the rasterizer draws belt rocks as
+// plain spheres at every distance.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Backend {
+    RayCasting,
+    Rasterization,
+}
+
+// How a small body moves: circling a parent body, spinning as it goes
+pub struct Satellite {
+    pub parent: BodyId,     // body it circles: the sun or a planet
+    pub orbit_radius: f32,
+    pub orbit_speed: f32,   // radians per second
+    pub orbit_phase: f32,   // angle along the orbit at time 0
+    pub orbit_tilt: f32,    // inclination against the XZ plane, radians
+    pub spin_axis: Vec3,
+    pub spin_speed: f32,
+}
+
+impl Satellite {
+    // Circling `parent` at `orbit_radius` in its equatorial plane, with a
+    // slow spin about Y
+    pub fn circling(parent: BodyId, orbit_radius: f32) -> Self {
+        Satellite {
+            parent,
+            orbit_radius,
+            // Kepler's third law, scaled from the default moon's 0.9 rad/s at 2.0
+            orbit_speed: 0.9 * (2.0 / orbit_radius.max(0.1)).powf(1.5),
+            orbit_phase: 0.0,
+            orbit_tilt: 0.0,
+            spin_axis: glm::vec3(0.0, 1.0, 0.0),
+            spin_speed: 0.5,
+        }
+    }
+}
+
+// A triangle mesh placed in the system
+pub struct MeshBody {
+    pub model: Model,
+    pub scale: f32,
+    pub material: PbrMaterial, // the model's own colors tint the albedo
+    pub satellite: Satellite,
+    pub shader: String, // surface shader, by its registered name
+}
+
+impl MeshBody {
+    // `model` circling `parent` at `orbit_radius`, scaled so it reaches
+    // `radius` from its center, with the rest left plain
+    pub fn orbiting(model: Model, parent: BodyId, orbit_radius: f32, radius: f32) -> Self {
+        let scale = radius / model.radius().max(1e-6);
+        MeshBody {
+            model,
+            scale,
+            material: PbrMaterial::regolith(glm::vec3(0.8, 0.8, 0.8)),
+            satellite: Satellite::circling(parent, orbit_radius),
+            shader: "regolith".to_string(),
+        }
+    }
+
+    // A world ray in the mesh's own space, for the body at `center` spun
+    // by `angle`. The direction is scaled along with the origin, so
+    // distances along the ray carry over.
+    fn local_ray(&self, ray: &Ray, center: &Vec3, angle: f32) -> Ray {
+        let to_local = |v: &Vec3| glm::rotate_vec3(v, -angle, &self.satellite.spin_axis) / self.scale;
+        Ray { origin: to_local(&(ray.origin - center)), direction: to_local(&ray.direction) }
+    }
+
+    // Albedo where `ray` meets the body, including the model's materials
+    fn albedo_along(&self, ray: &Ray, center: &Vec3, angle: f32) -> Vec3 {
+        match self.model.intersect(&self.local_ray(ray, center, angle), f32::INFINITY) {
+            Some((triangle, hit)) => self.material.albedo.component_mul(&self.model.mesh.surface_color(triangle, &hit.point)),
+            None => self.material.albedo,
+        }
+    }
+}
+
+// A shape given by a signed distance function, sphere traced where the
+// other bodies are intersected analytically
+pub struct SdfBody {
+    pub shape: Sdf, // in the body's own frame, at world scale
+    pub material: PbrMaterial,
+    pub satellite: Satellite,
+    pub shader: String, // surface shader, by its registered name
+}
+
+impl SdfBody {
+    // Sphere around the body at `center` spun by `angle`
+    fn world_bounds(&self, center: &Vec3, angle: f32) -> Sphere {
+        let bounds = self.shape.bounds();
+        Sphere::new(center + glm::rotate_vec3(&bounds.center, angle, &self.satellite.spin_axis), bounds.radius)
+    }
+
+    // The first crossing of the surface by a world ray, for the body at
+    // `center` spun by `angle`
+    fn intersect(&self, ray: &Ray, center: &Vec3, angle: f32, t_max: f32) -> Option<Hit> {
+        let axis = &self.satellite.spin_axis;
+        let local = Ray {
+            origin: glm::rotate_vec3(&(ray.origin - center), -angle, axis),
+            direction: glm::rotate_vec3(&ray.direction, -angle, axis),
+        };
+        self.shape.intersect(&local, t_max).map(|hit| Hit {
+            t: hit.t,
+            point: ray.origin + ray.direction * hit.t,
+            normal: glm::rotate_vec3(&hit.normal, angle, axis),
+        })
+    }
+}
+
+// A body of the system, for picking one out of the scene
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BodyId {
+    Sun,
+    Rocky,
+    Gas,
+    Comet(usize), // nucleus of `comets[i]`
+    Mesh(usize),  // `mesh_bodies[i]`
+    Sdf(usize),   // `sdf_bodies[i]`
+}
+
+// How Scene::bake_body turns a body into a mesh
+pub struct BakeOptions {
+    pub time: f32,           // moment whose surface and lights are baked
+    pub subdivisions: usize, // icosphere subdivisions for the spherical bodies
+    pub relief: f32,         // rocky terrain at full height, as a fraction of the radius above sea level
+    pub overhead_sun: bool,  // light every vertex from straight above rather than from where the sun is
+}
+
+impl Default for BakeOptions {
+    fn default() -> Self {
+        BakeOptions { time: 0.0, subdivisions: 6, relief: 0.05, overhead_sun: true }
+    }
+}
+
 pub struct Scene {
+    pub seed: u64, // everything procedural in the scene derives from this
+    pub backend: Backend,
+    pub sphere_lod: SphereLod, // how finely the rasterizer tessellates planets and stars
+    pub ray_depth: u32,        // reflections and refractions followed from a pixel, one after another
+    pub shutter: f32,          // scene seconds a frame's exposure lasts from its time, 0 for sharp frames
+    pub motion_samples: u32,   // instants averaged over an open shutter
     pub camera: Camera,
+    pub shaders: ShaderRegistry,
+    // Surface shaders of the sun, the planets and comet nuclei, by name
+    pub sun_shader: String,
+    pub rocky_shader: String,
+    pub gas_shader: String,
+    pub nucleus_shader: String,
     pub sun: Sphere,
+    pub sun_params: StarParams,
     pub rocky_planet: Sphere,
     pub gas_giant: Sphere,
-    pub stars: Vec<(Vec3, f32, Vec3)>, // direction (unit), brightness, color
+    pub stars: StarField,
     pub sky_rotation: f32, // radians
+    pub milky_way: Option<MilkyWay>,
+    pub scintillation: Option<Scintillation>, // twinkle while inside a planet's atmosphere
     // orbital + spin parameters
     pub rocky_orbit_center: Vec3,
     pub rocky_orbit_radius: f32,
     pub rocky_orbit_speed: f32,
     pub rocky_spin_speed: f32,
+    pub rocky_clouds: Option<CloudLayer>,
+    pub rocky_ocean: Option<OceanParams>,
+    pub rocky_night: Option<NightEmission>,
+    pub rocky_noise_offset: Vec3,
+    pub rocky_relief: f32, // terrain height the rasterizer displaces the ground by, as in BakeOptions::relief
+    pub rocky_material: PbrMaterial, // land; the ocean has its own
 
     pub gas_orbit_center: Vec3,
     pub gas_orbit_radius: f32,
     pub gas_orbit_speed: f32,
     pub gas_spin_speed: f32,
+    pub gas_noise_offset: Vec3,
+    pub gas_material: PbrMaterial,
+
+    pub asteroid_belt: Option<AsteroidBelt>,
+    pub mesh_bodies: Vec<MeshBody>,
+    pub sdf_bodies: Vec<SdfBody>,
+    pub comets: Vec<Comet>,
+
+    // lighting
+    pub sun_intensity: f32,    // irradiance at unit distance from the sun
+    pub ambient_light: f32,
+    pub terminator_width: f32,
+    pub earthshine: bool,      // light bounced between the planets
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Scene {
+    // Solar system in front of the bundled bright star catalog
     pub fn new() -> Self {
+        Self::with_seed(DEFAULT_SEED)
+    }
+
+    pub fn with_seed(seed: u64) -> Self {
+        Self::with_catalog(catalog::bundled(), seed)
+    }
+
+    // `catalog` stars are shown as-is; a faint procedural background fills
+    // in the sky between them. The same seed always builds the same scene.
+    pub fn with_catalog(catalog: Vec<Star>, seed: u64) -> Self {
+        let noise_seed = noise::seed32(seed);
         // Place the camera further back to improve composition and view
         let camera = Camera::new(
             glm::vec3(0.0, 0.0, 10.0), // moved back on Z
@@ -47,258 +283,1117 @@ impl Scene {
         let rocky_planet = Sphere::new(glm::vec3(2.0, 0.0, 0.0), 0.5);
         let gas_giant = Sphere::new(glm::vec3(3.5, 0.0, 0.0), 0.8);
 
-        // Generate random stars as directions on the unit sphere
-        let mut rng = rand::thread_rng();
-        let mut stars = Vec::new();
-        for _ in 0..2000 {
+        let milky_way = MilkyWay {
+            noise_offset: noise::seed_offset(noise_seed, STREAM_GALAXY),
+            ..MilkyWay::default()
+        };
+
+        // Generate faint random stars as directions on the unit sphere,
+        // crowding towards the galactic plane (rejection sampling)
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut stars = catalog;
+        let mut generated = 0;
+        while generated < 2000 {
             let z: f32 = rng.gen_range(-1.0..1.0);
             let theta: f32 = rng.gen_range(0.0..(2.0 * PI));
             let r = (1.0 - z * z).max(0.0).sqrt();
             let x = r * theta.cos();
             let y = r * theta.sin();
             let dir = glm::vec3(x, y, z);
-            let brightness = rng.gen_range(0.5..1.0);
-            // Slight color tint: many stars are slightly yellow/white/blue
-            let t: f32 = rng.gen_range(0.0..1.0);
-            let color = if t < 0.6 {
-                glm::vec3(1.0, 0.95, 0.9) // warm-white
-            } else if t < 0.9 {
-                glm::vec3(0.9, 0.95, 1.0) // cool-white
-            } else {
-                glm::vec3(1.0, 0.9, 0.7) // more yellowish
-            };
-            stars.push((dir, brightness, color));
+            if rng.gen_range(0.0f32..1.0) > milky_way.star_density(&dir) {
+                continue;
+            }
+            generated += 1;
+            let brightness = rng.gen_range(0.08..0.3);
+            // Star tint from its effective temperature, skewed towards cooler
+            // stars. Desaturated a little since faint stars look almost white.
+            let temperature = 3200.0 + 9000.0 * rng.gen_range(0.0f32..1.0).powf(1.8);
+            let (h, sat, val) = color::blackbody(temperature).to_hsv();
+            let color = Color::from_hsv(h, sat * 0.6, val).to_vec3();
+            stars.push(Star { dir, brightness, color });
         }
 
+        let mut belt_rng = StdRng::seed_from_u64(seed ^ u64::from(noise::hash_u32(STREAM_ASTEROIDS)));
+        let asteroid_belt = AsteroidBelt::generate(BeltParams::default(), &mut belt_rng);
+
+        // A long-period comet rounding the sun a few seconds in
+        let mut comet_orbit = KeplerOrbit {
+            semi_major_axis: 7.0,
+            eccentricity: 0.8,
+            inclination: 0.35,
+            ascending_node: 0.8,
+            periapsis_arg: 2.2,
+            mean_anomaly: 0.0,
+        };
+        comet_orbit.mean_anomaly = -comet_orbit.mean_motion() * 8.0;
+        let comet = Comet::new(comet_orbit, noise::hash_u32(noise_seed ^ STREAM_COMETS));
+
+        // A lumpy captured moon around the gas giant
+        let mut moon = Mesh::icosphere(5, 1.0);
+        let moon_offset = noise::seed_offset(noise_seed, STREAM_MOONS);
+        moon.displace(|p| p.component_mul(&glm::vec3(1.3, 0.9, 1.0)));
+        moon.displace_by_height(|p| 0.5 * noise::fbm(&(p * 1.8 + moon_offset), 4) - 0.25);
+        let moon = MeshBody {
+            model: Model::new(moon),
+            scale: 0.12,
+            material: PbrMaterial::regolith(glm::vec3(0.42, 0.38, 0.34)),
+            satellite: Satellite {
+                orbit_phase: 1.0,
+                orbit_tilt: 0.35,
+                spin_speed: 0.9,
+                ..Satellite::circling(BodyId::Gas, 2.0)
+            },
+            shader: "regolith".to_string(),
+        };
+
+        // A cratered moon close around the rocky planet, and a contact
+        // binary, two rubble piles resting together, further out around the giant
+        let moon_offset = noise::seed_offset(noise_seed, STREAM_SDF_MOONS);
+        let cratered = SdfBody {
+            shape: Sdf::Cratered { sphere: Sphere::new(glm::vec3(0.0, 0.0, 0.0), 0.07), depth: 0.03, frequency: 4.0, offset: moon_offset },
+            material: PbrMaterial::regolith(glm::vec3(0.5, 0.49, 0.47)),
+            satellite: Satellite { orbit_phase: 2.5, orbit_tilt: -0.2, spin_speed: 0.3, ..Satellite::circling(BodyId::Rocky, 0.9) },
+            shader: "regolith".to_string(),
+        };
+        let lobe = |x: f32, radius: f32, offset: Vec3| Sdf::Lumpy {
+            sphere: Sphere::new(glm::vec3(x, 0.0, 0.0), radius),
+            amplitude: 0.15,
+            frequency: 2.5,
+            offset,
+        };
+        let binary = SdfBody {
+            shape: lobe(-0.045, 0.05, moon_offset + glm::vec3(31.0, 0.0, 0.0)).blend(lobe(0.045, 0.038, moon_offset + glm::vec3(0.0, 47.0, 0.0)), 0.03),
+            material: PbrMaterial::regolith(glm::vec3(0.36, 0.31, 0.27)),
+            satellite: Satellite {
+                orbit_phase: 3.5,
+                orbit_tilt: -0.3,
+                spin_axis: glm::normalize(&glm::vec3(0.2, 1.0, 0.1)),
+                spin_speed: 1.1,
+                ..Satellite::circling(BodyId::Gas, 2.7)
+            },
+            shader: "regolith".to_string(),
+        };
+        // A small moon of clear ice, refracting the giant behind it
+        let ice = SdfBody {
+            shape: Sdf::Lumpy { sphere: Sphere::new(glm::vec3(0.0, 0.0, 0.0), 0.06), amplitude: 0.04, frequency: 1.5, offset: moon_offset + glm::vec3(0.0, 0.0, 59.0) },
+            material: PbrMaterial::dielectric(glm::vec3(0.85, 0.94, 1.0), 1.31, 0.05),
+            satellite: Satellite { orbit_phase: 5.2, orbit_tilt: 0.15, spin_speed: 0.4, ..Satellite::circling(BodyId::Gas, 2.35) },
+            shader: "regolith".to_string(),
+        };
+
         Scene {
+            seed,
+            backend: Backend::RayCasting,
+            sphere_lod: SphereLod::default(),
+            ray_depth: 4,
+            shutter: 0.0,
+            motion_samples: 8,
             camera,
+            shaders: ShaderRegistry::default(),
+            sun_shader: "sun".to_string(),
+            rocky_shader: "rocky".to_string(),
+            gas_shader: "gas_giant".to_string(),
+            nucleus_shader: "regolith".to_string(),
             sun,
+            sun_params: StarParams { seed: noise_seed, ..StarParams::default() },
             rocky_planet,
             gas_giant,
-            stars,
+            stars: StarField::new(stars, STAR_REACH),
             sky_rotation: 0.0,
+            milky_way: Some(milky_way),
+            scintillation: Some(Scintillation::default()),
             // rocky planet orbits around world origin at radius ~2.0
             rocky_orbit_center: glm::vec3(0.0, 0.0, 0.0),
             rocky_orbit_radius: 2.0,
             rocky_orbit_speed: 0.6, // radians per second
             rocky_spin_speed: 2.0, // spin radians per second
+            rocky_clouds: Some(CloudLayer {
+                offset: noise::seed_offset(noise_seed, STREAM_CLOUDS),
+                ..CloudLayer::default()
+            }),
+            rocky_ocean: Some(OceanParams::default()),
+            rocky_night: Some(NightEmission::CityLights {
+                color: glm::vec3(1.0, 0.78, 0.45),
+                intensity: 1.4,
+            }),
+            rocky_noise_offset: noise::seed_offset(noise_seed, STREAM_ROCKY),
+            rocky_relief: 0.05,
+            rocky_material: PbrMaterial { roughness: 0.6, diffuse_roughness: 0.3, ..PbrMaterial::default() },
             // gas giant orbits a bit farther
             gas_orbit_center: glm::vec3(0.0, 0.0, 0.0),
             gas_orbit_radius: 3.5,
             gas_orbit_speed: 0.3,
             gas_spin_speed: 1.2,
+            gas_noise_offset: noise::seed_offset(noise_seed, STREAM_GAS),
+            gas_material: PbrMaterial { roughness: 0.7, ..PbrMaterial::default() },
+            asteroid_belt: Some(asteroid_belt),
+            mesh_bodies: vec![moon],
+            sdf_bodies: vec![cratered, binary, ice],
+            comets: vec![comet],
+            sun_intensity: 5.0,
+            ambient_light: 0.02,
+            terminator_width: 0.15,
+            earthshine: true,
         }
     }
 
     pub fn render(&self, time: f32) -> Vec<u32> {
-        // Use ray_casting which parallelizes per-row for better performance
-        self.ray_casting(time)
-    }
-
-    // Find closest intersection of ray with scene objects.
-    // Returns Some((t, point, normal, type)) where type: 1=sun,2=rocky,3=gas
-    // time in seconds drives orbital positions and spin
-    fn ray_intersect(&self, ray: &Ray, time: f32) -> Option<(f32, Vec3, Vec3, u8)> {
-        let mut closest_hit = f32::INFINITY;
-        let mut hit_point = None;
-        let mut hit_normal = None;
-        let mut hit_type: u8 = 0;
-        // Sun (static)
-        if let Some(t) = self.sun.intersect(ray) {
-            if t < closest_hit {
-                closest_hit = t;
-                let point = ray.origin + ray.direction * t;
-                hit_point = Some(point);
-                hit_normal = Some(self.sun.normal_at(&point));
-                hit_type = 1;
-            }
-        }
-
-        // Rocky planet: compute orbital center
-        let rocky_angle = self.rocky_orbit_speed * time;
-        let rocky_center = glm::vec3(
-            self.rocky_orbit_center.x + self.rocky_orbit_radius * rocky_angle.cos(),
-            self.rocky_orbit_center.y,
-            self.rocky_orbit_center.z + self.rocky_orbit_radius * rocky_angle.sin(),
-        );
-        if let Some(t) = intersect_sphere(&rocky_center, self.rocky_planet.radius, ray) {
-            if t < closest_hit {
-                closest_hit = t;
-                let point = ray.origin + ray.direction * t;
-                hit_point = Some(point);
-                hit_normal = Some(glm::normalize(&(point - rocky_center)));
-                hit_type = 2;
+        self.render_exposure(time).to_u32()
+    }
+
+    // One frame as a camera with an open shutter records it: the mean of
+    // `motion_samples` renders over [time, time + shutter], each at a
+    // jittered instant in its own slice of the interval. Bodies, spins and
+    // comet tails move between them, so whatever crosses pixels during the
+    // exposure smears along its path. A closed shutter renders `time` alone.
+    pub fn render_exposure(&self, time: f32) -> Framebuffer {
+        let times = self.shutter_times(time, time.to_bits());
+        if let [instant] = times[..] {
+            return self.render_instant(instant);
+        }
+        let mut sum = vec![glm::vec3(0.0, 0.0, 0.0); WIDTH * HEIGHT];
+        let mut exposure = Framebuffer::new(WIDTH, HEIGHT);
+        for &instant in &times {
+            let framebuffer = self.render_instant(instant);
+            for (sum, color) in sum.iter_mut().zip(&framebuffer.pixels) {
+                *sum += color.to_vec3();
+            }
+            for (depth, d) in exposure.depth.iter_mut().zip(&framebuffer.depth) {
+                *depth = depth.min(*d);
             }
         }
+        let scale = 1.0 / times.len() as f32;
+        for (pixel, sum) in exposure.pixels.iter_mut().zip(&sum) {
+            *pixel = Color::from_vec3(&(sum * scale));
+        }
+        exposure
+    }
+
+    // Instants sampling the exposure that starts at `time`, stratified so
+    // every slice of the shutter is seen once. `key` picks the jitter, so
+    // successive frames and passes land at different instants and the
+    // blur converges instead of showing as the same copies every frame.
+    fn shutter_times(&self, time: f32, key: u32) -> Vec<f32> {
+        if self.shutter <= 0.0 {
+            return vec![time];
+        }
+        let n = self.motion_samples.max(1);
+        let seed = noise::hash_u32(noise::seed32(self.seed) ^ STREAM_SHUTTER) ^ noise::hash_u32(key);
+        (0..n).map(|i| time + self.shutter * (i as f32 + noise::hash_f32(seed, i)) / n as f32).collect()
+    }
+
+    fn render_instant(&self, time: f32) -> Framebuffer {
+        let mut framebuffer = match self.backend {
+            Backend::RayCasting => self.ray_casting(time),
+            Backend::Rasterization => self.rasterization(time),
+        };
+        // Glowing comet material goes on top, hidden behind closer surfaces
+        let mut sprites = Vec::new();
+        for comet in &self.comets {
+            comet.sprites(time, &mut sprites);
+        }
+        self.draw_sprites(&mut framebuffer, &sprites);
+        framebuffer
+    }
+
+    // Orbital centers at a given time (circular orbits in the XZ plane)
+    fn rocky_center(&self, time: f32) -> Vec3 {
+        let angle = self.rocky_orbit_speed * time;
+        glm::vec3(
+            self.rocky_orbit_center.x + self.rocky_orbit_radius * angle.cos(),
+            self.rocky_orbit_center.y,
+            self.rocky_orbit_center.z + self.rocky_orbit_radius * angle.sin(),
+        )
+    }
 
-        // Gas giant
-        let gas_angle = self.gas_orbit_speed * time;
-        let gas_center = glm::vec3(
-            self.gas_orbit_center.x + self.gas_orbit_radius * gas_angle.cos(),
+    fn gas_center(&self, time: f32) -> Vec3 {
+        let angle = self.gas_orbit_speed * time;
+        glm::vec3(
+            self.gas_orbit_center.x + self.gas_orbit_radius * angle.cos(),
             self.gas_orbit_center.y,
-            self.gas_orbit_center.z + self.gas_orbit_radius * gas_angle.sin(),
-        );
-        if let Some(t) = intersect_sphere(&gas_center, self.gas_giant.radius, ray) {
-            if t < closest_hit {
-                closest_hit = t;
-                let point = ray.origin + ray.direction * t;
-                hit_point = Some(point);
-                hit_normal = Some(glm::normalize(&(point - gas_center)));
-                hit_type = 3;
+            self.gas_orbit_center.z + self.gas_orbit_radius * angle.sin(),
+        )
+    }
+
+    fn satellite_center(&self, satellite: &Satellite, time: f32) -> Vec3 {
+        let parent = match satellite.parent {
+            BodyId::Rocky => self.rocky_center(time),
+            BodyId::Gas => self.gas_center(time),
+            _ => self.sun.center,
+        };
+        let angle = satellite.orbit_phase + satellite.orbit_speed * time;
+        let (s, c) = angle.sin_cos();
+        parent + glm::vec3(c, s * satellite.orbit_tilt.sin(), s * satellite.orbit_tilt.cos()) * satellite.orbit_radius
+    }
+
+    // Lighting for a body shaded in a frame rotated by `angle` around `center`
+    // (angle 0 is world space). `body` is the body being lit, if any, which
+    // is left out of the earthshine reflectors.
+    fn lighting(&self, time: f32, center: &Vec3, angle: f32, body: Option<BodyId>) -> Lighting {
+        let to_frame = |p: &Vec3| rotate_point_around_y(p, center, angle);
+        let mut reflectors = Vec::new();
+        if self.earthshine {
+            if body != Some(BodyId::Rocky) {
+                reflectors.push(Reflector { center: to_frame(&self.rocky_center(time)), radius: self.rocky_planet.radius, albedo: 0.3 });
+            }
+            if body != Some(BodyId::Gas) {
+                reflectors.push(Reflector { center: to_frame(&self.gas_center(time)), radius: self.gas_giant.radius, albedo: 0.5 });
             }
         }
+        Lighting {
+            sun_pos: to_frame(&self.sun.center),
+            sun_color: color::kelvin_to_linear_srgb(self.sun_params.temperature).to_vec3(),
+            sun_intensity: self.sun_intensity,
+            ambient: self.ambient_light,
+            terminator_width: self.terminator_width,
+            reflectors,
+        }
+    }
 
-        if let (Some(p), Some(n)) = (hit_point, hit_normal) {
-            Some((closest_hit, p, n, hit_type))
-        } else {
-            None
+    // Everything a ray can hit at `time`, for this frame's hierarchy.
+    // Orbits move every primitive, so the hierarchy is rebuilt each frame.
+    fn primitives<'a>(&'a self, time: f32, belt: Option<&'a BeltFrame<'a>>) -> Vec<Primitive<'a>> {
+        let gas_center = self.gas_center(time);
+        let mut primitives = vec![
+            Primitive::Body(Sphere::new(self.sun.center, self.sun.radius), BodyId::Sun),
+            Primitive::Body(Sphere::new(self.rocky_center(time), self.rocky_planet.radius), BodyId::Rocky),
+            Primitive::Body(Sphere::new(gas_center, self.gas_giant.radius), BodyId::Gas),
+        ];
+        for (i, comet) in self.comets.iter().enumerate() {
+            primitives.push(Primitive::Body(Sphere::new(comet.orbit.position(time), comet.nucleus_radius), BodyId::Comet(i)));
+        }
+        for body in &self.mesh_bodies {
+            primitives.push(Primitive::Mesh(body, self.satellite_center(&body.satellite, time), body.satellite.spin_speed * time));
+        }
+        for body in &self.sdf_bodies {
+            primitives.push(Primitive::Sdf(body, self.satellite_center(&body.satellite, time), body.satellite.spin_speed * time));
         }
+        if let Some(belt) = belt {
+            primitives.extend((0..belt.len()).map(|i| Primitive::Rock(belt, i)));
+        }
+        primitives
     }
 
-    // Parallel ray casting: render rows in parallel using rayon
-    fn ray_casting(&self, time: f32) -> Vec<u32> {
-        // For each row (y), produce a Vec<u32> for that row, then flatten
-        let rows: Vec<Vec<u32>> = (0..HEIGHT).into_par_iter().map(|y| {
-            let mut row = vec![0u32; WIDTH];
-            for x in 0..WIDTH {
-                let u = x as f32 / WIDTH as f32;
-                let v = 1.0 - (y as f32 / HEIGHT as f32);
-                let ray = self.camera.get_ray(u, v);
+    // Lights and per-body frames every pixel of the frame at `time` shares
+    fn frame_state(&self, time: f32) -> FrameState {
+        // Per-body frames: shading happens in each planet's spinning frame
+        // (inverse rotation to simulate texture rotation)
+        let rocky_center = self.rocky_center(time);
+        let rocky_angle = -self.rocky_spin_speed * time;
+        let gas_center = self.gas_center(time);
+        let gas_angle = -self.gas_spin_speed * time;
+        let sky_rotation = self.sky_rotation + time * 0.05;
+        FrameState {
+            time,
+            rocky_center,
+            rocky_angle,
+            rocky_light: self.lighting(time, &rocky_center, rocky_angle, Some(BodyId::Rocky)),
+            cloud_light: self.lighting(time, &rocky_center, 0.0, Some(BodyId::Rocky)),
+            gas_center,
+            gas_angle,
+            gas_light: self.lighting(time, &gas_center, gas_angle, Some(BodyId::Gas)),
+            world_light: self.lighting(time, &glm::vec3(0.0, 0.0, 0.0), 0.0, None),
+            sky_rotation,
+            star_view: self.star_view(time, sky_rotation),
+        }
+    }
+
+    // Ray through the sample point of pixel (x, y)
+    fn pixel_ray(&self, x: usize, y: usize) -> Ray {
+        let u = x as f32 / WIDTH as f32;
+        let v = 1.0 - (y as f32 / HEIGHT as f32);
+        self.camera.get_ray(u, v)
+    }
+
+    // Parallel ray casting: find each pixel's surface through the hierarchy
+    fn ray_casting(&self, time: f32) -> Framebuffer {
+        let frame = self.frame_state(time);
+        let belt = self.asteroid_belt.as_ref().map(|belt| belt.at(time, frame.star_view.pixel_angle));
+        let hierarchy = Hierarchy::new(self.primitives(time, belt.as_ref()));
+        self.shade_image(&frame, &hierarchy, |_, _, ray| hierarchy.intersect(ray))
+    }
+
+    // Rasterize tessellated primitives into the depth buffer, keeping the
+    // nearest surface under each pixel, then shade every pixel once. Shading
+    // costs far more than coverage, so it is left for the parallel pass.
+    fn rasterization(&self, time: f32) -> Framebuffer {
+        let frame = self.frame_state(time);
+        let belt = self.asteroid_belt.as_ref().map(|belt| belt.at(time, frame.star_view.pixel_angle));
+        let primitives = self.primitives(time, belt.as_ref());
+        let rasterizer = Rasterizer::new(&self.camera, WIDTH, HEIGHT);
+        let mut depth = Framebuffer::new(WIDTH, HEIGHT);
+        // Nearest surface so far under each pixel: primitive, point, normal
+        let mut surfaces: Vec<Option<(usize, Vec3, Vec3)>> = vec![None; WIDTH * HEIGHT];
+        let rock = Mesh::icosphere(ROCK_SUBDIVISIONS, 1.0);
 
-                let pixel = match self.ray_intersect(&ray, time) {
-                    Some((_t, point, normal, hit_type)) => {
-                        match hit_type {
-                            1 => vec3_to_color(&sun_shader(&point, &normal, &ray.direction, time)),
-                            2 => {
-                                // rotate texture coordinates by planet spin
-                                let rocky_spin = self.rocky_spin_speed * time;
-                                let rocky_angle = -rocky_spin; // inverse to simulate texture rotation
-                                let rocky_center = glm::vec3(
-                                    self.rocky_orbit_center.x + self.rocky_orbit_radius * (self.rocky_orbit_speed * time).cos(),
-                                    self.rocky_orbit_center.y,
-                                    self.rocky_orbit_center.z + self.rocky_orbit_radius * (self.rocky_orbit_speed * time).sin(),
-                                );
-                                let rp = rotate_point_around_y(&point, &rocky_center, rocky_angle);
-                                let rn = rotate_vector_around_y(&normal, rocky_angle);
-                                vec3_to_color(&rocky_shader(&rp, &rn, &ray.direction, time))
-                            }
-                            3 => {
-                                let gas_spin = self.gas_spin_speed * time;
-                                let gas_angle = -gas_spin;
-                                let gas_center = glm::vec3(
-                                    self.gas_orbit_center.x + self.gas_orbit_radius * (self.gas_orbit_speed * time).cos(),
-                                    self.gas_orbit_center.y,
-                                    self.gas_orbit_center.z + self.gas_orbit_radius * (self.gas_orbit_speed * time).sin(),
-                                );
-                                let rp = rotate_point_around_y(&point, &gas_center, gas_angle);
-                                let rn = rotate_vector_around_y(&normal, gas_angle);
-                                vec3_to_color(&gas_giant_shader(&rp, &rn, &ray.direction, time))
-                            }
-                            _ => 0,
+        for (index, primitive) in primitives.iter().enumerate() {
+            let mut keep = |fragment: &Fragment, point: Vec3, normal: Vec3| {
+                surfaces[fragment.y * WIDTH + fragment.x] = Some((index, point, normal));
+                true
+            };
+            // Facets only decide coverage; the shaders see the true sphere
+            let on_sphere = |fragment: &Fragment, sphere: &Sphere| {
+                let normal = glm::normalize(&(fragment.pos - sphere.center));
+                (sphere.center + normal * sphere.radius, normal)
+            };
+            match primitive {
+                Primitive::Body(body, BodyId::Rocky) if self.rocky_relief > 0.0 => {
+                    // The ground is raised into its terrain, in the planet's
+                    // spinning frame, and shaded where the relief puts it
+                    let angle = frame.rocky_angle;
+                    let eye = rotate_vector_around_y(&(self.camera.position - body.center), angle) / body.radius;
+                    let lod = SphereLod { relief: self.rocky_relief, ..self.sphere_lod };
+                    let ground = lod.mesh(&eye, frame.star_view.pixel_angle, |d| self.rocky_terrain(self.rocky_relief, d));
+                    let place = |p: &Vec3, n: &Vec3| (body.center + rotate_vector_around_y(p, -angle) * body.radius, rotate_vector_around_y(n, -angle));
+                    draw_mesh(&rasterizer, &mut depth, &ground, place, true, |f| keep(f, f.pos, glm::normalize(&f.normal)));
+                }
+                Primitive::Body(body, _) => {
+                    // Fine where the camera is close, coarse elsewhere
+                    let eye = (self.camera.position - body.center) / body.radius;
+                    let sphere = self.sphere_lod.mesh(&eye, frame.star_view.pixel_angle, |_| 0.0);
+                    let place = |p: &Vec3, n: &Vec3| (body.center + p * body.radius, *n);
+                    draw_mesh(&rasterizer, &mut depth, &sphere, place, true, |f| {
+                        let (point, normal) = on_sphere(f, body);
+                        keep(f, point, normal)
+                    });
+                }
+                Primitive::Rock(belt, i) => {
+                    // Every rock is drawn as its impostor sphere
+                    let distance = glm::distance(&belt.rock_bounds(*i).center(), &self.camera.position).max(1e-4);
+                    let impostor = belt.impostor(*i, distance);
+                    let place = |p: &Vec3, n: &Vec3| (impostor.center + p * impostor.radius, *n);
+                    draw_mesh(&rasterizer, &mut depth, &rock, place, true, |f| {
+                        let (point, normal) = on_sphere(f, &impostor);
+                        keep(f, point, normal)
+                    });
+                }
+                Primitive::Mesh(body, center, angle) => {
+                    let place = |p: &Vec3, n: &Vec3| {
+                        let axis = &body.satellite.spin_axis;
+                        (center + glm::rotate_vec3(&(p * body.scale), *angle, axis), glm::rotate_vec3(n, *angle, axis))
+                    };
+                    // Imported models need not be closed, so nothing is culled
+                    draw_mesh(&rasterizer, &mut depth, &body.model.mesh, place, false, |f| keep(f, f.pos, glm::normalize(&f.normal)));
+                }
+                Primitive::Sdf(body, center, angle) => {
+                    // The bounds decide which pixels to trace; depth stays
+                    // that of the bounds, a little in front of the surface
+                    let bounds = body.world_bounds(center, *angle);
+                    let eye = (self.camera.position - bounds.center) / bounds.radius;
+                    let sphere = self.sphere_lod.mesh(&eye, frame.star_view.pixel_angle, |_| 0.0);
+                    let place = |p: &Vec3, n: &Vec3| (bounds.center + p * bounds.radius, *n);
+                    draw_mesh(&rasterizer, &mut depth, &sphere, place, true, |f| {
+                        match primitive.intersect(&self.pixel_ray(f.x, f.y), f32::INFINITY) {
+                            Some(hit) => keep(f, hit.point, hit.normal),
+                            None => false,
                         }
-                    }
-                    None => {
-                        let color = self.skybox_color(&ray.direction, self.sky_rotation + time * 0.05);
-                        vec3_to_color(&color)
-                    }
-                };
+                    });
+                }
+            }
+        }
+
+        // Reflected and refracted rays are traced, as in the ray caster
+        let hierarchy = Hierarchy::new(primitives);
+        self.shade_image(&frame, &hierarchy, |x, y, _| {
+            let index = y * WIDTH + x;
+            surfaces[index].map(|(i, point, normal)| (i, Hit { t: depth.depth[index], point, normal }))
+        })
+    }
 
-                row[x] = pixel;
+    // Add one path-traced sample of every pixel to `image`, at a random
+    // point in the pixel. Each path takes sunlight from a random point of
+    // the sun's disk wherever it lands, which softens shadow edges, and
+    // bounces on off diffusely up to options.max_bounces times, so moons
+    // pick up planetshine. Clouds, prominences and comet comae are laid on
+    // top as in render; the other stand-ins for indirect light, earthshine,
+    // ambient and the planets' rims, are left to the paths.
+    pub fn path_trace_pass(&self, options: &PathTraceOptions, image: &mut Accumulator) {
+        // Each pass sees the scene at its own set of instants over the
+        // shutter, and every pixel sample picks one of them at random
+        let pass = u64::from(image.passes());
+        let times = self.shutter_times(options.time, noise::seed32(options.seed ^ pass));
+        let frames: Vec<FrameState> = times.iter().map(|&t| self.frame_state(t)).collect();
+        let belts: Vec<Option<BeltFrame>> = frames.iter().map(|frame| {
+            self.asteroid_belt.as_ref().map(|belt| belt.at(frame.time, frame.star_view.pixel_angle))
+        }).collect();
+        let hierarchies: Vec<Hierarchy> = frames.iter().zip(&belts).map(|(frame, belt)| {
+            Hierarchy::new(self.primitives(frame.time, belt.as_ref()))
+        }).collect();
+        let rows: Vec<Vec<(Vec3, f32)>> = (0..HEIGHT).into_par_iter().map(|y| {
+            let stream = u64::from(noise::hash_u32((pass as u32).wrapping_mul(HEIGHT as u32).wrapping_add(y as u32)));
+            let mut rng = StdRng::seed_from_u64(options.seed ^ (pass << 32) ^ stream);
+            (0..WIDTH).map(|x| {
+                let u = (x as f32 + rng.gen::<f32>()) / WIDTH as f32;
+                let v = 1.0 - (y as f32 + rng.gen::<f32>()) / HEIGHT as f32;
+                let ray = self.camera.get_ray(u, v);
+                let instant = if frames.len() > 1 { rng.gen_range(0..frames.len()) } else { 0 };
+                let (frame, hierarchy) = (&frames[instant], &hierarchies[instant]);
+                let (mut color, t_hit) = self.trace_path(frame, hierarchy, &ray, options.max_bounces, &mut rng);
+                color = self.composite_layers(&ray, frame.time, t_hit, color, &frame.cloud_light);
+                color += star_prominences(&ray, &self.sun.center, self.sun.radius, &self.sun_params, frame.time, t_hit);
+                (color, t_hit)
+            }).collect()
+        }).collect();
+
+        let mut framebuffer = Framebuffer::new(WIDTH, HEIGHT);
+        for (i, (color, depth)) in rows.into_iter().flatten().enumerate() {
+            framebuffer.pixels[i] = Color::from_vec3(&color);
+            framebuffer.depth[i] = depth;
+        }
+        let mut sprites = Vec::new();
+        let sprite_time = times[pass as usize % times.len()];
+        for comet in &self.comets {
+            comet.sprites(sprite_time, &mut sprites);
+        }
+        self.draw_sprites(&mut framebuffer, &sprites);
+        image.add(&framebuffer);
+    }
+
+    // Height of the rocky planet's ground above sea level in direction
+    // `dir` of its spinning frame, in radii, with the highest terrain at
+    // `relief`. Oceans stay flat at the sphere.
+    fn rocky_terrain(&self, relief: f32, dir: &Vec3) -> f32 {
+        let sea_level = self.rocky_ocean.as_ref().map_or(0.0, |ocean| ocean.sea_level);
+        let height = terrain_height(dir, &self.rocky_noise_offset);
+        relief * (height - sea_level).max(0.0) / (1.0 - sea_level)
+    }
+
+    // Tessellate a body and bake its shading into per-vertex colors. The
+    // mesh is centered on the origin in the body's own spinning frame, at
+    // the scene's scale; the rocky planet gets its terrain as real relief.
+    // Colors are the surface shaders seen from straight above each vertex,
+    // so the view-dependent glints and rims drop out. Clouds are a separate
+    // layer and are left out. None if `body` is not in the scene.
+    pub fn bake_body(&self, body: BodyId, options: &BakeOptions) -> Option<Mesh> {
+        let frame = self.frame_state(options.time);
+        let sphere = |radius: f32| {
+            let mut mesh = Mesh::icosphere(options.subdivisions, 1.0);
+            mesh.displace(|p| p * radius);
+            mesh
+        };
+        // The mesh, its primitive, and its placement: a center and the spin
+        // taking the mesh into the world, about Y for the spheres and about
+        // the body's own axis for mesh bodies
+        let (mut mesh, primitive, center, angle) = match body {
+            BodyId::Sun => (sphere(self.sun.radius), Primitive::Body(Sphere::new(self.sun.center, self.sun.radius), body), self.sun.center, 0.0),
+            BodyId::Rocky => {
+                let radius = self.rocky_planet.radius;
+                let mut mesh = Mesh::icosphere(options.subdivisions, 1.0);
+                mesh.displace_by_height(|p| self.rocky_terrain(options.relief, p));
+                mesh.displace(|p| p * radius);
+                (mesh, Primitive::Body(Sphere::new(frame.rocky_center, radius), body), frame.rocky_center, -frame.rocky_angle)
+            }
+            BodyId::Gas => {
+                let radius = self.gas_giant.radius;
+                (sphere(radius), Primitive::Body(Sphere::new(frame.gas_center, radius), body), frame.gas_center, -frame.gas_angle)
+            }
+            BodyId::Comet(i) => {
+                let comet = self.comets.get(i)?;
+                let center = comet.orbit.position(options.time);
+                (sphere(comet.nucleus_radius), Primitive::Body(Sphere::new(center, comet.nucleus_radius), body), center, 0.0)
+            }
+            BodyId::Mesh(i) => {
+                let body = self.mesh_bodies.get(i)?;
+                let center = self.satellite_center(&body.satellite, options.time);
+                let source = &body.model.mesh;
+                let vertices = source.vertices.iter().map(|v| Vertex {
+                    pos: v.pos.map(|x| x * body.scale),
+                    normal: v.normal,
+                    uv: v.uv,
+                    tangent: v.tangent,
+                }).collect();
+                let mut mesh = Mesh::new(vertices, source.indices.clone());
+                mesh.colors = source.colors.clone();
+                let angle = body.satellite.spin_speed * options.time;
+                (mesh, Primitive::Mesh(body, center, angle), center, angle)
+            }
+            BodyId::Sdf(i) => {
+                let body = self.sdf_bodies.get(i)?;
+                let center = self.satellite_center(&body.satellite, options.time);
+                let angle = body.satellite.spin_speed * options.time;
+                // Taken as star-shaped around the middle of its bounds: each
+                // direction out from there crosses the surface once
+                let bounds = body.shape.bounds();
+                let mut mesh = Mesh::icosphere(options.subdivisions, 1.0);
+                mesh.displace(|d| {
+                    let ray = Ray { origin: bounds.center + d * (bounds.radius * 1.01), direction: -d };
+                    body.shape.intersect(&ray, f32::INFINITY).map_or(bounds.center, |hit| hit.point)
+                });
+                mesh.recompute_normals();
+                (mesh, Primitive::Sdf(body, center, angle), center, angle)
+            }
+        };
+        let to_world = |v: &Vec3| match &primitive {
+            Primitive::Mesh(body, ..) => glm::rotate_vec3(v, angle, &body.satellite.spin_axis),
+            Primitive::Sdf(body, ..) => glm::rotate_vec3(v, angle, &body.satellite.spin_axis),
+            _ => rotate_vector_around_y(v, angle),
+        };
+
+        // Material color at each vertex of a mesh body, from any triangle using it
+        let mut vertex_albedo = vec![glm::vec3(1.0, 1.0, 1.0); mesh.vertices.len()];
+        if let Primitive::Mesh(body, ..) = &primitive {
+            for (tri, corners) in body.model.mesh.indices.iter().enumerate() {
+                for &i in corners {
+                    let p = body.model.mesh.vertices[i].pos;
+                    vertex_albedo[i] = body.material.albedo.component_mul(&body.model.mesh.surface_color(tri, &glm::vec3(p[0], p[1], p[2])));
+                }
             }
-            row
+        }
+
+        let sun = self.sun.center;
+        let colors = mesh.vertices.par_iter().zip(vertex_albedo.par_iter()).map_init(|| self.frame_state(options.time), |frame, (v, albedo)| {
+            // Spheres are shaded where the renderer sees them, on the
+            // sphere itself; meshes at their vertices
+            let (point, normal) = match &primitive {
+                Primitive::Body(sphere, _) => {
+                    let normal = to_world(&glm::normalize(&glm::make_vec3(&v.pos)));
+                    (sphere.center + normal * sphere.radius, normal)
+                }
+                _ => (center + to_world(&glm::make_vec3(&v.pos)), glm::normalize(&to_world(&glm::make_vec3(&v.normal)))),
+            };
+            if options.overhead_sun {
+                // Same sunlight, arriving along the normal
+                let sun_pos = point + normal * glm::distance(&sun, &point);
+                frame.world_light.sun_pos = sun_pos;
+                frame.cloud_light.sun_pos = sun_pos;
+                frame.rocky_light.sun_pos = rotate_point_around_y(&sun_pos, &frame.rocky_center, frame.rocky_angle);
+                frame.gas_light.sun_pos = rotate_point_around_y(&sun_pos, &frame.gas_center, frame.gas_angle);
+            }
+            let ray = Ray { origin: point + normal, direction: -normal };
+            let albedo = matches!(primitive, Primitive::Mesh(..)).then_some(*albedo);
+            self.shade_body(frame, &primitive, &ray, &Hit { t: 1.0, point, normal }, albedo)
         }).collect();
+        mesh.colors = Some(colors);
+        Some(mesh)
+    }
 
-        // Flatten rows into a single buffer
-        let mut buffer = Vec::with_capacity(WIDTH * HEIGHT);
-        for r in rows {
-            buffer.extend(r);
+    // Color every pixel from the surface `surface_at(x, y, ray)` finds for
+    // it, with the sky behind and the translucent layers in front. Rows are
+    // shaded in parallel. Ray directions are unit length, so hit distances
+    // double as depth. `surface_at` gives the index of the primitive hit
+    // in `hierarchy`, which secondary rays search.
+    fn shade_image(&self, frame: &FrameState, hierarchy: &Hierarchy, surface_at: impl Fn(usize, usize, &Ray) -> Option<(usize, Hit)> + Sync) -> Framebuffer {
+        // For each row (y), produce the row's colors and hit distances, then flatten
+        let rows: Vec<Vec<(Vec3, f32)>> = (0..HEIGHT).into_par_iter().map(|y| {
+            (0..WIDTH).map(|x| {
+                let ray = self.pixel_ray(x, y);
+                let (t_hit, mut color) = match surface_at(x, y, &ray) {
+                    Some((index, hit)) => (hit.t, self.shade_traced(frame, hierarchy, index, &ray, &hit, 0)),
+                    None => (f32::INFINITY, self.skybox_color(&ray.direction, frame.sky_rotation, &frame.star_view, frame.time)),
+                };
+
+                // Semi-transparent layers in front of the opaque hit
+                color = self.composite_layers(&ray, frame.time, t_hit, color, &frame.cloud_light);
+
+                // Flare prominences glow above the solar limb, in front of
+                // whatever the ray hit
+                color += star_prominences(&ray, &self.sun.center, self.sun.radius, &self.sun_params, frame.time, t_hit);
+                (color, t_hit)
+            }).collect()
+        }).collect();
+
+        let mut framebuffer = Framebuffer::new(WIDTH, HEIGHT);
+        for (i, (color, depth)) in rows.into_iter().flatten().enumerate() {
+            framebuffer.pixels[i] = Color::from_vec3(&color);
+            framebuffer.depth[i] = depth;
         }
-        buffer
+        framebuffer
     }
 
-    fn ray_color(&self, ray: &Ray, time: f32) -> u32 {
-        // Background skybox sampling
-        // If no object hit, sample skybox based on ray direction and current sky rotation
+    // Shade the surface `ray` hit, adding what the rays it reflects and
+    // refracts find, while `depth` is below ray_depth. Only bodies made of
+    // a smooth or clear material send rays on. Seen from inside, the
+    // surface only passes light through.
+    fn shade_traced(&self, frame: &FrameState, hierarchy: &Hierarchy, index: usize, ray: &Ray, hit: &Hit, depth: u32) -> Vec3 {
+        let primitive = &hierarchy.primitives[index];
+        let inside = glm::dot(&hit.normal, &ray.direction) > 0.0;
+        let local = if inside { glm::vec3(0.0, 0.0, 0.0) } else { self.shade_surface(frame, primitive, ray, hit) };
+        // Look at the body's own material first: shaders are costly, and most
+        // bodies send nothing on
+        let traced = self.body_material(primitive).is_some_and(|m| m.transmission > 0.0 || m.roughness < GLOSSY_LIMIT);
+        if depth >= self.ray_depth || !traced {
+            return local;
+        }
+        let Some((material, normal, _)) = self.surface_material(frame, primitive, ray, hit) else {
+            return local;
+        };
+        let (reflect, refract) = secondary_rays(&material, &normal, &ray.direction);
+        let mut color = local;
+        for secondary in reflect.into_iter().chain(refract) {
+            // Start on the side of the surface the ray goes to
+            let side = glm::dot(&hit.normal, &secondary.direction).signum();
+            let next = Ray { origin: hit.point + hit.normal * (RAY_OFFSET * side), direction: secondary.direction };
+            let light = match hierarchy.intersect(&next) {
+                Some((index, next_hit)) => self.shade_traced(frame, hierarchy, index, &next, &next_hit, depth + 1),
+                None => self.skybox_color(&next.direction, frame.sky_rotation, &frame.star_view, frame.time),
+            };
+            color += secondary.weight.component_mul(&light);
+        }
+        color
+    }
 
-        // Check object intersections
-        let mut closest_hit = f32::INFINITY;
-        let mut hit_point = None;
-        let mut hit_normal = None;
-        let mut hit_type = 0; // 0=none, 1=sun, 2=rocky, 3=gas
+    // Material a body is given, before its shader varies it across the
+    // surface. None for belt rocks.
+    fn body_material(&self, primitive: &Primitive) -> Option<PbrMaterial> {
+        match primitive {
+            Primitive::Body(_, BodyId::Rocky) => Some(self.rocky_material),
+            Primitive::Body(_, BodyId::Gas) => Some(self.gas_material),
+            Primitive::Mesh(body, ..) => Some(body.material),
+            Primitive::Sdf(body, ..) => Some(body.material),
+            _ => None,
+        }
+    }
 
-        // Sun intersection
-        if let Some(t) = self.sun.intersect(ray) {
-            if t < closest_hit {
-                closest_hit = t;
-                let point = ray.origin + ray.direction * t;
-                hit_point = Some(point);
-                hit_normal = Some(self.sun.normal_at(&point));
-                hit_type = 1;
+    // Run the shader of the primitive `ray` hit
+    fn shade_surface(&self, frame: &FrameState, primitive: &Primitive, ray: &Ray, hit: &Hit) -> Vec3 {
+        let (point, normal) = (hit.point, hit.normal);
+        match primitive {
+            Primitive::Rock(belt, i) => asteroid_shader(&point, &normal, &ray.direction, &belt.albedo(*i, hit.t), &frame.world_light),
+            Primitive::Mesh(body, center, angle) => {
+                let albedo = body.albedo_along(ray, center, *angle);
+                self.shade_body(frame, primitive, ray, hit, Some(albedo))
             }
+            _ => self.shade_body(frame, primitive, ray, hit, None),
         }
+    }
+
+    // Run the surface shader a body names, with `albedo` standing in for
+    // the body's own when given. Shaders nobody registered show magenta.
+    fn shade_body(&self, frame: &FrameState, primitive: &Primitive, ray: &Ray, hit: &Hit, albedo: Option<Vec3>) -> Vec3 {
+        self.with_body_context(frame, primitive, ray, hit, albedo, |shader, ctx, _| match shader {
+            Some(shader) => shader.shade(ctx),
+            None => MISSING_SHADER,
+        })
+        .unwrap_or(glm::vec3(0.0, 0.0, 0.0))
+    }
 
-        // Rocky planet intersection
-        if let Some(t) = self.rocky_planet.intersect(ray) {
-            if t < closest_hit {
-                closest_hit = t;
-                let point = ray.origin + ray.direction * t;
-                hit_point = Some(point);
-                hit_normal = Some(self.rocky_planet.normal_at(&point));
-                hit_type = 2;
+    // Hand `f` the shader a body names, if registered, and the context to
+    // run it in, as shade_body does, along with the angle the context's
+    // frame is spun by around Y. None for primitives that are not bodies.
+    fn with_body_context<R>(
+        &self,
+        frame: &FrameState,
+        primitive: &Primitive,
+        ray: &Ray,
+        hit: &Hit,
+        albedo: Option<Vec3>,
+        f: impl FnOnce(Option<&dyn SurfaceShader>, &ShadingContext, f32) -> R,
+    ) -> Option<R> {
+        let (point, normal, time) = (hit.point, hit.normal, frame.time);
+        // Point, normal and view in a frame spun by `angle` around Y through `center`
+        let spun = |center: &Vec3, angle: f32| {
+            (rotate_point_around_y(&point, center, angle), rotate_vector_around_y(&normal, angle), rotate_vector_around_y(&ray.direction, angle))
+        };
+        let world = (point, normal, ray.direction);
+        let (shader, lighting, mut body, (frame_point, frame_normal, frame_view), object_point, spin) = match primitive {
+            Primitive::Body(sphere, BodyId::Sun) => {
+                let body = BodyParams { star: Some(&self.sun_params), ..BodyParams::new(sphere.center, sphere.radius) };
+                (&self.sun_shader, &frame.world_light, body, world, point - sphere.center, 0.0)
+            }
+            Primitive::Body(sphere, BodyId::Rocky) => {
+                // Clouds above shade the ground; the layer has its own spin
+                let visibility = match &self.rocky_clouds {
+                    Some(layer) => {
+                        let cloud_angle = -layer.spin_speed * time;
+                        let shell = self.rocky_planet.radius + layer.altitude;
+                        cloud_shadow(&point, &frame.rocky_center, shell, |d| rotate_vector_around_y(d, cloud_angle), layer, time, &frame.cloud_light)
+                    }
+                    None => 1.0,
+                };
+                let body = BodyParams {
+                    material: self.rocky_material,
+                    noise_offset: self.rocky_noise_offset,
+                    light_visibility: visibility,
+                    ocean: self.rocky_ocean.as_ref(),
+                    night: self.rocky_night.as_ref(),
+                    ..BodyParams::new(sphere.center, sphere.radius)
+                };
+                // Surface patterns turn with the planet's spin
+                let spun = spun(&frame.rocky_center, frame.rocky_angle);
+                (&self.rocky_shader, &frame.rocky_light, body, spun, spun.0 - sphere.center, frame.rocky_angle)
+            }
+            Primitive::Body(sphere, BodyId::Gas) => {
+                let body = BodyParams { material: self.gas_material, noise_offset: self.gas_noise_offset, ..BodyParams::new(sphere.center, sphere.radius) };
+                let spun = spun(&frame.gas_center, frame.gas_angle);
+                (&self.gas_shader, &frame.gas_light, body, spun, spun.0 - sphere.center, frame.gas_angle)
+            }
+            Primitive::Body(sphere, BodyId::Comet(_)) => {
+                let body = BodyParams { material: PbrMaterial::regolith(glm::vec3(1.0, 0.92, 0.85) * NUCLEUS_ALBEDO), ..BodyParams::new(sphere.center, sphere.radius) };
+                (&self.nucleus_shader, &frame.world_light, body, world, point - sphere.center, 0.0)
             }
+            Primitive::Mesh(mesh_body, center, angle) => {
+                let body = BodyParams { material: mesh_body.material, ..BodyParams::new(*center, mesh_body.model.radius() * mesh_body.scale) };
+                let object_point = glm::rotate_vec3(&(point - center), -angle, &mesh_body.satellite.spin_axis);
+                (&mesh_body.shader, &frame.world_light, body, world, object_point, 0.0)
+            }
+            Primitive::Sdf(sdf_body, center, angle) => {
+                let body = BodyParams { material: sdf_body.material, ..BodyParams::new(*center, sdf_body.world_bounds(center, *angle).radius) };
+                let object_point = glm::rotate_vec3(&(point - center), -angle, &sdf_body.satellite.spin_axis);
+                (&sdf_body.shader, &frame.world_light, body, world, object_point, 0.0)
+            }
+            // Not bodies with a surface of their own
+            Primitive::Body(..) | Primitive::Rock(..) => return None,
+        };
+        if let Some(albedo) = albedo {
+            body.material.albedo = albedo;
         }
+        let ctx = ShadingContext {
+            point,
+            normal,
+            view: ray.direction,
+            frame_point,
+            frame_normal,
+            frame_view,
+            object_point,
+            uv: sphere_uv(&(object_point / glm::length(&object_point).max(1e-12))),
+            lighting,
+            time,
+            body: &body,
+        };
+        Some(f(self.shaders.get(shader), &ctx, spin))
+    }
+
+    // Light arriving back along `ray` from a path of up to `max_bounces`
+    // bounces, and the distance to the first surface. The sun only counts
+    // where seen directly or through a clear body; paths that scattered off
+    // a surface before running into it have already been given its light by
+    // the sampling at that surface. Clear bodies refract as often as their
+    // material lets light through, and a path inside one reflects or
+    // refracts in proportion to what each carries.
+    fn trace_path(&self, frame: &FrameState, hierarchy: &Hierarchy, ray: &Ray, max_bounces: u32, rng: &mut StdRng) -> (Vec3, f32) {
+        let primitives = &hierarchy.primitives;
+        let Some((mut index, mut hit)) = hierarchy.intersect(ray) else {
+            return (self.skybox_color(&ray.direction, frame.sky_rotation, &frame.star_view, frame.time), f32::INFINITY);
+        };
+        let t_hit = hit.t;
+        let mut ray = Ray { origin: ray.origin, direction: ray.direction };
+        let mut radiance = glm::vec3(0.0, 0.0, 0.0);
+        let mut throughput = glm::vec3(1.0, 1.0, 1.0);
+        // Whether the path last went straight on through a clear body
+        let mut specular = true;
+        for bounce in 0..=max_bounces {
+            let primitive = &primitives[index];
+            let is_sun = matches!(primitive, Primitive::Body(_, BodyId::Sun));
+            if is_sun && !specular {
+                break;
+            }
+            let Some((material, normal, visibility)) = self.surface_material(frame, primitive, &ray, &hit) else {
+                break;
+            };
+            radiance += throughput.component_mul(&material.emission);
+            if is_sun {
+                break;
+            }
+            let to_eye = -ray.direction;
+            let origin = hit.point + hit.normal * (RAY_OFFSET * glm::dot(&hit.normal, &to_eye).signum());
+
+            // Sunlight from a random point of the disk, if nothing is in the way
+            let to_sun = self.sun.center - origin;
+            let distance = glm::length(&to_sun);
+            if distance > self.sun.radius {
+                let cos_max = (1.0 - (self.sun.radius / distance).powi(2)).sqrt();
+                let light_dir = cone_direction(&(to_sun / distance), cos_max, rng);
+                let shadow = Ray { origin, direction: light_dir };
+                let lit = match hierarchy.intersect(&shadow) {
+                    Some((blocker, _)) => matches!(primitives[blocker], Primitive::Body(_, BodyId::Sun)),
+                    None => true,
+                };
+                if lit && glm::dot(&hit.normal, &light_dir) * glm::dot(&hit.normal, &to_eye) > 0.0 {
+                    let (_, irradiance) = frame.world_light.sun_at(&origin);
+                    let sun = frame.world_light.sun_color * (irradiance * visibility);
+                    radiance += throughput.component_mul(&pbr_reflectance(&material, &normal, &to_eye, &light_dir).component_mul(&sun));
+                }
+            }
+            if bounce == max_bounces {
+                break;
+            }
 
-        // Gas giant intersection
-        if let Some(t) = self.gas_giant.intersect(ray) {
-            if t < closest_hit {
-                let point = ray.origin + ray.direction * t;
-                hit_point = Some(point);
-                hit_normal = Some(self.gas_giant.normal_at(&point));
-                hit_type = 3;
+            // Through the surface of a clear body, or within one. Outside,
+            // reflections are left to the scattering below, which has the
+            // GGX lobe in it.
+            let inside = glm::dot(&hit.normal, &to_eye) < 0.0;
+            let (reflect, refract) = if material.transmission > 0.0 { secondary_rays(&material, &normal, &ray.direction) } else { (None, None) };
+            let events: Vec<SecondaryRay> = refract.into_iter().chain(reflect.filter(|_| inside)).collect();
+            let chance = |event: &SecondaryRay| (event.weight.x + event.weight.y + event.weight.z) / 3.0;
+            let total: f32 = events.iter().map(chance).sum();
+            let mut pick = rng.gen::<f32>() * if inside { total } else { 1.0 };
+            let event = events.into_iter().find(|event| {
+                pick -= chance(event);
+                pick < 0.0
+            });
+            let direction = match event {
+                Some(event) => {
+                    let p = chance(&event) / if inside { total } else { 1.0 };
+                    throughput = throughput.component_mul(&event.weight) / p.max(1e-6);
+                    specular = true;
+                    event.direction
+                }
+                None if inside => break,
+                None => {
+                    // Bounce in a cosine-weighted direction, which cancels
+                    // the n.l and the pi in pbr_reflectance against the pdf
+                    let direction = cosine_hemisphere(&normal, rng);
+                    let n_dot_l = glm::dot(&normal, &direction);
+                    if n_dot_l <= 0.0 || glm::dot(&hit.normal, &direction) <= 0.0 {
+                        break;
+                    }
+                    let p = 1.0 - total;
+                    throughput = throughput.component_mul(&pbr_reflectance(&material, &normal, &to_eye, &direction)) / (n_dot_l * p.max(1e-6));
+                    specular = false;
+                    direction
+                }
+            };
+            let side = glm::dot(&hit.normal, &direction).signum();
+            ray = Ray { origin: hit.point + hit.normal * (RAY_OFFSET * side), direction };
+            match hierarchy.intersect(&ray) {
+                Some((next, next_hit)) => (index, hit) = (next, next_hit),
+                None => {
+                    let sky = if specular {
+                        self.skybox_color(&direction, frame.sky_rotation, &frame.star_view, frame.time)
+                    } else {
+                        self.sky_glow(&rotate_vector_around_y(&direction, -frame.sky_rotation))
+                    };
+                    radiance += throughput.component_mul(&sky);
+                    break;
+                }
             }
         }
+        (radiance, t_hit)
+    }
 
-        match (hit_point, hit_normal, hit_type) {
-            (Some(point), Some(normal), 1) => vec3_to_color(&sun_shader(&point, &normal, &ray.direction, time)),
-            (Some(point), Some(normal), 2) => vec3_to_color(&rocky_shader(&point, &normal, &ray.direction, time)),
-            (Some(point), Some(normal), 3) => vec3_to_color(&gas_giant_shader(&point, &normal, &ray.direction, time)),
+    // Material, world-space shading normal and share of the sunlight let
+    // through above the surface `ray` hit. Belt rocks are plain regolith;
+    // bodies ask their shader.
+    fn surface_material(&self, frame: &FrameState, primitive: &Primitive, ray: &Ray, hit: &Hit) -> Option<(PbrMaterial, Vec3, f32)> {
+        match primitive {
+            Primitive::Rock(belt, i) => Some((PbrMaterial::regolith(belt.albedo(*i, hit.t)), hit.normal, 1.0)),
             _ => {
-                let color = self.skybox_color(&ray.direction, self.sky_rotation + time * 0.05);
-                vec3_to_color(&color)
+                let albedo = match primitive {
+                    Primitive::Mesh(body, center, angle) => Some(body.albedo_along(ray, center, *angle)),
+                    _ => None,
+                };
+                self.with_body_context(frame, primitive, ray, hit, albedo, |shader, ctx, spin| {
+                    let (material, normal) = match shader {
+                        Some(shader) => shader.surface(ctx),
+                        None => (PbrMaterial { albedo: glm::vec3(0.0, 0.0, 0.0), emission: MISSING_SHADER, ..PbrMaterial::default() }, ctx.frame_normal),
+                    };
+                    (material, rotate_vector_around_y(&normal, -spin), ctx.body.light_visibility)
+                })
+            }
+        }
+    }
+
+    // Splat Gaussian sprites additively. Sprites smaller than a pixel keep
+    // their total light but spread over one, so they do not shimmer.
+    fn draw_sprites(&self, framebuffer: &mut Framebuffer, sprites: &[Sprite]) {
+        let pixel_angle = self.camera.pixel_angle(HEIGHT);
+        for sprite in sprites {
+            let Some((u, v, depth)) = self.camera.project(&sprite.pos) else {
+                continue;
+            };
+            let sigma = sprite.radius / (depth * pixel_angle);
+            let (sigma, color) = if sigma < 0.6 {
+                (0.6, sprite.color * (sigma / 0.6).powi(2))
+            } else {
+                (sigma.min(MAX_SPRITE_PIXELS), sprite.color)
+            };
+            // Sprites are fuzzy volumes: let their near side show in front
+            // of a surface inside them, like a nucleus in its coma
+            let front = depth - sprite.radius;
+            let cx = u * WIDTH as f32;
+            let cy = (1.0 - v) * HEIGHT as f32;
+            let reach = (sigma * 3.0).ceil() as i32;
+            if cx + (reach as f32) < 0.0 || cy + (reach as f32) < 0.0 || cx - reach as f32 > WIDTH as f32 || cy - reach as f32 > HEIGHT as f32 {
+                continue;
+            }
+            for y in (cy as i32 - reach)..=(cy as i32 + reach) {
+                for x in (cx as i32 - reach)..=(cx as i32 + reach) {
+                    let dx = x as f32 + 0.5 - cx;
+                    let dy = y as f32 + 0.5 - cy;
+                    let w = (-0.5 * (dx * dx + dy * dy) / (sigma * sigma)).exp();
+                    framebuffer.add_light_at(x, y, front, Color::from_vec3(&(color * w)));
+                }
+            }
+        }
+    }
+
+    // Alpha-composite every semi-transparent shell crossing in front of the
+    // opaque hit at `t_max` over its color, back to front. A ray grazing a
+    // planet can cross the same shell twice.
+    fn composite_layers(&self, ray: &Ray, time: f32, t_max: f32, color: Vec3, cloud_light: &Lighting) -> Vec3 {
+        let mut samples: Vec<(f32, Vec3, f32)> = Vec::new();
+        if let Some(layer) = &self.rocky_clouds {
+            let center = self.rocky_center(time);
+            let radius = self.rocky_planet.radius + layer.altitude;
+            if let Some((t0, t1)) = intersect_sphere_span(&center, radius, ray) {
+                let angle = -layer.spin_speed * time;
+                for t in [t0, t1] {
+                    if t > 0.0 && t < t_max {
+                        let p = ray.origin + ray.direction * t;
+                        let n = glm::normalize(&(p - center));
+                        let (c, a) = cloud_shader(&p, &n, &rotate_vector_around_y(&n, angle), layer, time, cloud_light);
+                        samples.push((t, c, a));
+                    }
+                }
             }
         }
+        samples.sort_by(|a, b| b.0.total_cmp(&a.0));
+        samples.into_iter().fold(color, |acc, (_, c, a)| acc * (1.0 - a) + c * a)
     }
 
-    fn skybox_color(&self, dir: &Vec3, rotation: f32) -> Vec3 {
+    // Star drawing for this frame: image size from the camera, twinkle when
+    // the camera sits inside a planet's atmosphere
+    fn star_view(&self, time: f32, rotation: f32) -> StarView {
+        let pixel_angle = self.camera.pixel_angle(HEIGHT);
+        let reach = starfield::psf_reach(self.stars.max_brightness(), pixel_angle).min(self.stars.max_radius());
+        let twinkle = self.scintillation.as_ref().and_then(|scintillation| {
+            let bodies = [
+                (self.rocky_center(time), self.rocky_planet.radius),
+                (self.gas_center(time), self.gas_giant.radius),
+            ];
+            bodies.iter().find_map(|(center, radius)| {
+                let offset = self.camera.position - center;
+                let height = glm::length(&offset) - radius;
+                if height >= scintillation.altitude {
+                    return None;
+                }
+                let depth = 1.0 - (height / scintillation.altitude).max(0.0);
+                // Into the sky's frame, like the view directions in skybox_color
+                let up = rotate_vector_around_y(&glm::normalize(&offset), -rotation);
+                Some((up, depth))
+            })
+        });
+        StarView { pixel_angle, reach, twinkle }
+    }
+
+    fn skybox_color(&self, dir: &Vec3, rotation: f32, view: &StarView, time: f32) -> Vec3 {
         // Rotate the view direction around Y by -rotation (so sky appears to rotate)
-        let c = rotation.cos();
-        let s = rotation.sin();
-        let rx = c * dir.x + s * dir.z;
-        let rz = -s * dir.x + c * dir.z;
-        let rdir = glm::vec3(rx, dir.y, rz);
+        let rdir = rotate_vector_around_y(dir, -rotation);
 
-        // Sample stars: find if any star lies within a small angular radius of rdir
+        // Sum the point spread of every star close enough to rdir. The star
+        // field only hands back stars from the grid cells around rdir.
         let mut accum = glm::vec3(0.0, 0.0, 0.0);
-        for (star_dir, brightness, color) in &self.stars {
-            let d = glm::dot(&rdir, star_dir);
-            // angular radius threshold (cos of angle). Smaller value => larger apparent star size
-            let threshold = 0.9995_f32; // about ~0.03 rad
-            if d > threshold {
-                // soft falloff
-                let intensity = ((d - threshold) / (1.0 - threshold)).powf(2.0) * *brightness;
-                accum += color * intensity;
+        self.stars.for_each_near(&rdir, view.reach, |star| {
+            // Chord length is the angle to well within a pixel this close
+            let angle = glm::length(&(rdir - star.dir));
+            if angle > starfield::psf_reach(star.brightness, view.pixel_angle) {
+                return;
             }
-        }
+            let mut brightness = star.brightness;
+            if let (Some(scintillation), Some((up, depth))) = (&self.scintillation, &view.twinkle) {
+                brightness *= scintillation.factor(star, up, *depth, time);
+            }
+            accum += star.color * starfield::star_psf(angle, brightness, view.pixel_angle);
+        });
 
+        self.sky_glow(&rdir) + accum
+    }
+
+    // The sky without its stars, along `rdir` in the sky's frame. Stars
+    // are points, which diffuse bounces would only pick up as fireflies.
+    fn sky_glow(&self, rdir: &Vec3) -> Vec3 {
         // Add subtle gradient for space
         let space_color = glm::vec3(0.02, 0.03, 0.06);
-        space_color + accum
+        let galaxy = match &self.milky_way {
+            Some(milky_way) => milky_way.radiance(rdir),
+            None => glm::vec3(0.0, 0.0, 0.0),
+        };
+        space_color + galaxy
     }
 
 }
 
-// Helper: sphere intersection with explicit center
-fn intersect_sphere(center: &Vec3, radius: f32, ray: &Ray) -> Option<f32> {
+// One entry of the per-frame scene hierarchy
+enum Primitive<'a> {
+    // Sun, planet or comet nucleus sphere, and which body it is
+    Body(Sphere, BodyId),
+    Rock(&'a BeltFrame<'a>, usize),
+    // Mesh body with its center and spin angle
+    Mesh(&'a MeshBody, Vec3, f32),
+    // Distance field body with its center and spin angle
+    Sdf(&'a SdfBody, Vec3, f32),
+}
+
+impl Hittable for Primitive<'_> {
+    fn bounds(&self) -> Aabb {
+        match self {
+            Primitive::Body(sphere, _) => sphere.bounds(),
+            Primitive::Rock(belt, i) => belt.rock_bounds(*i),
+            Primitive::Mesh(body, center, _) => Aabb::around_sphere(center, body.model.radius() * body.scale),
+            Primitive::Sdf(body, center, angle) => body.world_bounds(center, *angle).bounds(),
+        }
+    }
+
+    fn intersect(&self, ray: &Ray, t_max: f32) -> Option<Hit> {
+        match self {
+            Primitive::Body(sphere, _) => Hittable::intersect(sphere, ray, t_max),
+            Primitive::Rock(belt, i) => belt.intersect_rock(*i, ray, t_max),
+            Primitive::Mesh(body, center, angle) => {
+                body.model.intersect(&body.local_ray(ray, center, *angle), t_max).map(|(_, hit)| Hit {
+                    t: hit.t,
+                    point: ray.origin + ray.direction * hit.t,
+                    normal: glm::rotate_vec3(&hit.normal, *angle, &body.satellite.spin_axis),
+                })
+            }
+            Primitive::Sdf(body, center, angle) => body.intersect(ray, center, *angle, t_max),
+        }
+    }
+}
+
+// A frame's primitives and the bounding volume hierarchy over them
+struct Hierarchy<'a> {
+    primitives: Vec<Primitive<'a>>,
+    bvh: Bvh,
+}
+
+impl<'a> Hierarchy<'a> {
+    fn new(primitives: Vec<Primitive<'a>>) -> Self {
+        let bvh = Bvh::build(&primitives);
+        Hierarchy { primitives, bvh }
+    }
+
+    // Closest hit, with the index of the primitive hit
+    fn intersect(&self, ray: &Ray) -> Option<(usize, Hit)> {
+        self.bvh.intersect(&self.primitives, ray, f32::INFINITY)
+    }
+}
+
+// Per-frame state shared by every pixel, see Scene::frame_state
+struct FrameState {
+    time: f32,
+    rocky_center: Vec3,
+    rocky_angle: f32,
+    rocky_light: Lighting,
+    cloud_light: Lighting,
+    gas_center: Vec3,
+    gas_angle: f32,
+    gas_light: Lighting,
+    world_light: Lighting,
+    sky_rotation: f32,
+    star_view: StarView,
+}
+
+// Rasterize `mesh` with its vertices and normals moved into the world by
+// `place`
+fn draw_mesh(rasterizer: &Rasterizer, target: &mut Framebuffer, mesh: &Mesh, place: impl Fn(&Vec3, &Vec3) -> (Vec3, Vec3), cull_back: bool, mut fragment: impl FnMut(&Fragment) -> bool) {
+    let corners: Vec<Corner> = mesh.vertices.iter().map(|v| {
+        let (pos, normal) = place(&glm::make_vec3(&v.pos), &glm::make_vec3(&v.normal));
+        Corner { pos, normal }
+    }).collect();
+    for tri in &mesh.indices {
+        rasterizer.draw_triangle(target, tri.map(|i| &corners[i]), cull_back, &mut fragment);
+    }
+}
+
+// How stars are drawn in one frame
+struct StarView {
+    pixel_angle: f32,
+    reach: f32,                   // star lookup radius around each view direction
+    twinkle: Option<(Vec3, f32)>, // local up (sky frame) and depth in the atmosphere
+}
+
+// Both roots of a ray/sphere intersection (entry, exit); either may be behind the origin
+fn intersect_sphere_span(center: &Vec3, radius: f32, ray: &Ray) -> Option<(f32, f32)> {
     let oc = ray.origin - *center;
     let a = glm::dot(&ray.direction, &ray.direction);
     let b = 2.0 * glm::dot(&oc, &ray.direction);
@@ -307,8 +1402,8 @@ fn intersect_sphere(center: &Vec3, radius: f32, ray: &Ray) -> Option<f32> {
     if disc < 0.0 {
         None
     } else {
-        let t = (-b - disc.sqrt()) / (2.0 * a);
-        if t > 0.0 { Some(t) } else { None }
+        let sq = disc.sqrt();
+        Some(((-b - sq) / (2.0 * a), (-b + sq) / (2.0 * a)))
     }
 }
 
@@ -329,10 +1424,41 @@ fn rotate_vector_around_y(v: &Vec3, angle: f32) -> Vec3 {
     glm::vec3(v.x * c - v.z * s, v.y, v.x * s + v.z * c)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-fn vec3_to_color(v: &Vec3) -> u32 {
-    let r = (v.x.min(1.0).max(0.0) * 255.0) as u32;
-    let g = (v.y.min(1.0).max(0.0) * 255.0) as u32;
-    let b = (v.z.min(1.0).max(0.0) * 255.0) as u32;
-    (r << 16) | (g << 8) | b
-}
\ No newline at end of file
+    #[test]
+    fn hierarchy_finds_the_same_nearest_hit_as_brute_force() {
+        let scene = Scene::new();
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut hits = 0;
+        for time in [0.0, 3.0, 11.5] {
+            let frame = scene.frame_state(time);
+            let belt = scene.asteroid_belt.as_ref().map(|belt| belt.at(time, frame.star_view.pixel_angle));
+            let hierarchy = Hierarchy::new(scene.primitives(time, belt.as_ref()));
+            let primitives = &hierarchy.primitives;
+            for _ in 0..1000 {
+                // Aim most rays near a primitive so they hit something
+                let origin = glm::vec3(rng.gen_range(-10.0..10.0), rng.gen_range(-4.0..4.0), rng.gen_range(-10.0..10.0));
+                let target = primitives[rng.gen_range(0..primitives.len())].bounds().center();
+                let jitter = glm::vec3(rng.gen_range(-0.3..0.3), rng.gen_range(-0.3..0.3), rng.gen_range(-0.3..0.3));
+                let ray = Ray { origin, direction: glm::normalize(&(target + jitter - origin)) };
+                let brute = primitives.iter().enumerate()
+                    .filter_map(|(i, p)| p.intersect(&ray, f32::INFINITY).map(|hit| (i, hit.t)))
+                    .min_by(|a, b| a.1.total_cmp(&b.1));
+                let traversed = hierarchy.intersect(&ray).map(|(i, hit)| (i, hit.t));
+                match (brute, traversed) {
+                    (None, None) => {}
+                    (Some((i, t)), Some((j, u))) => {
+                        hits += 1;
+                        assert!((t - u).abs() <= 1e-4 * t.max(1.0), "t {t} vs {u}");
+                        assert!(i == j || t == u, "primitive {i} vs {j}");
+                    }
+                    _ => panic!("brute force {brute:?}, hierarchy {traversed:?}"),
+                }
+            }
+        }
+        assert!(hits > 500, "only {hits} rays hit anything");
+    }
+}
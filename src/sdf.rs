@@ -0,0 +1,241 @@
+use nalgebra_glm as glm;
+use crate::bvh::Hit;
+use crate::math::{Ray, Sphere, Vec3};
+use crate::noise;
+
+const MARCH_STEPS: usize = 128;
+// Displacement and blending stretch distances, so steps fall short of the
+// distance bound by this much
+const STEP_SCALE: f32 = 0.7;
+// Crater bowls out to this distance from their Worley feature point, and
+// rims from there out to RIM_EDGE, both in cell units
+const CRATER_EDGE: f32 = 0.35;
+const RIM_EDGE: f32 = 0.55;
+// Second, smaller generation of craters: this many times as frequent and
+// as shallow
+const SMALL_CRATERS: f32 = 2.7;
+
+// Shape given by an approximate signed distance: negative inside, positive
+// outside, never much more than the true distance to the surface. Built as
+// a tree of displaced spheres combined by unions and blends.
+pub enum Sdf {
+    Sphere(Sphere),
+    // Sphere whose radius rises and falls with fbm noise by up to
+    // `amplitude` times the radius, `frequency` lumps across a unit sphere
+    Lumpy { sphere: Sphere, amplitude: f32, frequency: f32, offset: Vec3 },
+    // Sphere pitted with bowl-shaped, rimmed craters up to `depth` times
+    // the radius deep, scattered by Worley noise: `frequency` large ones
+    // across a unit sphere, and smaller, shallower ones between them
+    Cratered { sphere: Sphere, depth: f32, frequency: f32, offset: Vec3 },
+    Union(Box<Sdf>, Box<Sdf>),
+    // Union with the seam filled in over about `k`, like two rubble piles
+    // resting against each other
+    Blend(Box<Sdf>, Box<Sdf>, f32),
+    // The first shape with the second cut out of it, the edge rounded over `k`
+    Subtract(Box<Sdf>, Box<Sdf>, f32),
+}
+
+impl Sdf {
+    pub fn union(self, other: Sdf) -> Sdf {
+        Sdf::Union(Box::new(self), Box::new(other))
+    }
+
+    pub fn blend(self, other: Sdf, k: f32) -> Sdf {
+        Sdf::Blend(Box::new(self), Box::new(other), k)
+    }
+
+    pub fn subtract(self, other: Sdf, k: f32) -> Sdf {
+        Sdf::Subtract(Box::new(self), Box::new(other), k)
+    }
+
+    pub fn distance(&self, p: &Vec3) -> f32 {
+        match self {
+            Sdf::Sphere(sphere) => glm::distance(p, &sphere.center) - sphere.radius,
+            Sdf::Lumpy { sphere, amplitude, frequency, offset } => {
+                let (len, dir) = polar(p, &sphere.center);
+                let lumps = noise::fbm(&(dir * *frequency + offset), 4) * 2.0 - 1.0;
+                len - sphere.radius * (1.0 + amplitude * lumps)
+            }
+            Sdf::Cratered { sphere, depth, frequency, offset } => {
+                let (len, dir) = polar(p, &sphere.center);
+                let q = dir * *frequency + offset;
+                let (large, _) = noise::worley(&q);
+                let (small, _) = noise::worley(&(q * SMALL_CRATERS + glm::vec3(5.3, 11.9, 2.1)));
+                let height = crater_profile(large) + crater_profile(small) / SMALL_CRATERS;
+                len - sphere.radius * (1.0 + depth * height)
+            }
+            Sdf::Union(a, b) => a.distance(p).min(b.distance(p)),
+            Sdf::Blend(a, b, k) => smooth_min(a.distance(p), b.distance(p), *k),
+            Sdf::Subtract(a, b, k) => -smooth_min(-a.distance(p), b.distance(p), *k),
+        }
+    }
+
+    // Outward normal at `p`, a point on or near the surface
+    pub fn normal(&self, p: &Vec3) -> Vec3 {
+        let e = self.bounds().radius * 1e-3;
+        let d = |q: Vec3| self.distance(&q);
+        let grad = glm::vec3(
+            d(p + glm::vec3(e, 0.0, 0.0)) - d(p - glm::vec3(e, 0.0, 0.0)),
+            d(p + glm::vec3(0.0, e, 0.0)) - d(p - glm::vec3(0.0, e, 0.0)),
+            d(p + glm::vec3(0.0, 0.0, e)) - d(p - glm::vec3(0.0, 0.0, e)),
+        );
+        glm::normalize(&grad)
+    }
+
+    // Sphere the whole surface fits inside
+    pub fn bounds(&self) -> Sphere {
+        match self {
+            Sdf::Sphere(sphere) => Sphere::new(sphere.center, sphere.radius),
+            Sdf::Lumpy { sphere, amplitude, .. } => Sphere::new(sphere.center, sphere.radius * (1.0 + amplitude.abs())),
+            // Rims stand at most a quarter of a crater's depth proud
+            Sdf::Cratered { sphere, depth, .. } => Sphere::new(sphere.center, sphere.radius * (1.0 + 0.25 * depth.abs() * (1.0 + 1.0 / SMALL_CRATERS))),
+            Sdf::Union(a, b) => enclosing(&a.bounds(), &b.bounds()),
+            // Blending swells the union by at most k / 4
+            Sdf::Blend(a, b, k) => {
+                let sphere = enclosing(&a.bounds(), &b.bounds());
+                Sphere::new(sphere.center, sphere.radius + k * 0.25)
+            }
+            Sdf::Subtract(a, ..) => a.bounds(),
+        }
+    }
+
+    // Sphere trace `ray` from where it enters the bounds to the first
+    // surface crossing closer than `t_max`. Rays starting inside the shape,
+    // such as refracted ones, find where they leave it.
+    pub fn intersect(&self, ray: &Ray, t_max: f32) -> Option<Hit> {
+        let bounds = self.bounds();
+        let oc = ray.origin - bounds.center;
+        let b = glm::dot(&oc, &ray.direction);
+        let c = glm::dot(&oc, &oc) - bounds.radius * bounds.radius;
+        let disc = b * b - c;
+        if disc < 0.0 {
+            return None;
+        }
+        let sq = disc.sqrt();
+        let (t0, t1) = (-b - sq, -b + sq);
+        if t1 <= 0.0 || t0 >= t_max {
+            return None;
+        }
+        let epsilon = bounds.radius * 1e-3;
+        let end = t1.min(t_max);
+        let mut t = t0.max(0.0);
+        let side = if self.distance(&(ray.origin + ray.direction * t)) < 0.0 { -1.0 } else { 1.0 };
+        for _ in 0..MARCH_STEPS {
+            let p = ray.origin + ray.direction * t;
+            let d = self.distance(&p) * side;
+            if d < epsilon {
+                return Some(Hit { t, point: p, normal: self.normal(&p) });
+            }
+            t += d * STEP_SCALE;
+            if t > end {
+                break;
+            }
+        }
+        None
+    }
+}
+
+// Distance of `p` from `center` and the direction to it
+fn polar(p: &Vec3, center: &Vec3) -> (f32, Vec3) {
+    let offset = p - center;
+    let len = glm::length(&offset).max(1e-6);
+    (len, offset / len)
+}
+
+// Surface height against the crater's distance from its center, in cell
+// units: a bowl sinking to -1, then a raised rim fading out into the plain
+fn crater_profile(f1: f32) -> f32 {
+    if f1 < CRATER_EDGE {
+        let x = f1 / CRATER_EDGE;
+        x * x - 1.0 + 0.25 * x * x * x * x
+    } else if f1 < RIM_EDGE {
+        let x = (RIM_EDGE - f1) / (RIM_EDGE - CRATER_EDGE);
+        0.25 * x * x
+    } else {
+        0.0
+    }
+}
+
+// Polynomial smooth minimum, blending over a width of about `k`
+fn smooth_min(a: f32, b: f32, k: f32) -> f32 {
+    if k <= 0.0 {
+        return a.min(b);
+    }
+    let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
+    b + (a - b) * h - k * h * (1.0 - h)
+}
+
+fn enclosing(a: &Sphere, b: &Sphere) -> Sphere {
+    let d = glm::distance(&a.center, &b.center);
+    if d + b.radius <= a.radius {
+        return Sphere::new(a.center, a.radius);
+    }
+    if d + a.radius <= b.radius {
+        return Sphere::new(b.center, b.radius);
+    }
+    let radius = (d + a.radius + b.radius) * 0.5;
+    let center = a.center + (b.center - a.center) * ((radius - a.radius) / d);
+    Sphere::new(center, radius)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn random_unit(rng: &mut StdRng) -> Vec3 {
+        glm::normalize(&glm::vec3(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0)))
+    }
+
+    // Ray from `origin`, outside, passing `offset` times the radius from the center
+    fn ray_past(sphere: &Sphere, origin: Vec3, offset: f32, rng: &mut StdRng) -> Ray {
+        let (distance, to_center) = (glm::distance(&origin, &sphere.center), glm::normalize(&(sphere.center - origin)));
+        let side = glm::normalize(&glm::cross(&to_center, &random_unit(rng)));
+        let sin = offset * sphere.radius / distance;
+        Ray { origin, direction: to_center * (1.0 - sin * sin).sqrt() + side * sin }
+    }
+
+    #[test]
+    fn sphere_field_hits_where_the_sphere_does() {
+        let mut rng = StdRng::seed_from_u64(5);
+        for _ in 0..500 {
+            let sphere = Sphere::new(random_unit(&mut rng) * rng.gen_range(0.0..5.0), rng.gen_range(0.1..3.0));
+            let field = Sdf::Sphere(Sphere::new(sphere.center, sphere.radius));
+            let origin = sphere.center + random_unit(&mut rng) * sphere.radius * rng.gen_range(2.5..20.0);
+
+            // Well inside the silhouette, where the march closes in steeply
+            let ray = ray_past(&sphere, origin, rng.gen_range(0.0..0.8), &mut rng);
+            let t = sphere.intersect(&ray).unwrap();
+            let hit = field.intersect(&ray, f32::INFINITY).unwrap();
+            assert!((hit.t - t).abs() < 2e-3 * sphere.radius, "{} vs {}", hit.t, t);
+            assert!(glm::distance(&hit.normal, &sphere.normal_at(&(ray.origin + ray.direction * t))) < 1e-2);
+            // Nor past t_max
+            assert!(field.intersect(&ray, t * 0.9).is_none());
+
+            let ray = ray_past(&sphere, origin, rng.gen_range(1.05..2.0), &mut rng);
+            assert!(sphere.intersect(&ray).is_none());
+            assert!(field.intersect(&ray, f32::INFINITY).is_none());
+        }
+    }
+
+    #[test]
+    fn rays_from_inside_leave_where_the_sphere_does() {
+        let mut rng = StdRng::seed_from_u64(6);
+        for _ in 0..500 {
+            let sphere = Sphere::new(random_unit(&mut rng) * rng.gen_range(0.0..5.0), rng.gen_range(0.1..3.0));
+            let field = Sdf::Sphere(Sphere::new(sphere.center, sphere.radius));
+            // Refracted rays start just under the surface they came through
+            let depth = if rng.gen_bool(0.5) { 0.99 } else { rng.gen_range(0.0..0.9) };
+            let ray = Ray { origin: sphere.center + random_unit(&mut rng) * sphere.radius * depth, direction: random_unit(&mut rng) };
+            let t = sphere.intersect(&ray).unwrap();
+            let hit = field.intersect(&ray, f32::INFINITY).unwrap();
+            // Marching stops within 1e-3 radii of the surface, which is farther
+            // along a ray leaving at a slant
+            let exit_cos = glm::dot(&ray.direction, &sphere.normal_at(&(ray.origin + ray.direction * t)));
+            assert!((hit.t - t).abs() < 1.5e-3 * sphere.radius / exit_cos, "{} vs {}", hit.t, t);
+            // Still the outward normal
+            assert!(glm::distance(&hit.normal, &sphere.normal_at(&(ray.origin + ray.direction * t))) < 1e-2);
+        }
+    }
+}
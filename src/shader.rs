@@ -1,11 +1,11 @@
 ﻿use nalgebra_glm as glm;
-use crate::math::Vec3;
-
-// Light position in world space
-const LIGHT_POS: Vec3 = Vec3::new(-2.0, 0.0, -2.0);
+use crate::color;
+use crate::math::{Ray, Vec3};
+use crate::noise;
+use std::collections::HashMap;
 
 fn saturate(x: f32) -> f32 {
-	x.max(0.0).min(1.0)
+	x.clamp(0.0, 1.0)
 }
 
 fn tri_noise(p: &Vec3, freq: f32, t: f32) -> f32 {
@@ -15,124 +15,966 @@ fn tri_noise(p: &Vec3, freq: f32, t: f32) -> f32 {
 	saturate((s * c * d + 1.0) * 0.5)
 }
 
-pub fn sun_shader(world_pos: &Vec3, normal: &Vec3, view: &Vec3, time: f32) -> Vec3 {
-	// Enhanced plasma-like colors
-	let white_hot = glm::vec3(1.0, 1.0, 0.98);
-	let yellow_bright = glm::vec3(1.0, 0.95, 0.2);
-	let yellow_deep = glm::vec3(0.95, 0.75, 0.15);
-	let orange_dark = glm::vec3(0.9, 0.4, 0.0);
-    
-	// Multi-layered noise for complex texture
-	let spots = tri_noise(world_pos, 35.0, time * 2.5);
-	let yellow_var = tri_noise(world_pos, 18.0, time * 1.4);
-	let streaks = tri_noise(world_pos, 15.0, time * 1.2);
-    
-	// Create more pronounced irregular streaks
-	let streak_pattern = (streaks * 1.5 + 
-						 (world_pos.x * 10.0 + time).sin() * 0.5 + 
-						 (world_pos.y * 8.0 - time * 0.7).cos() * 0.4 +
-						 (world_pos.z * 6.0 + time * 0.3).sin() * 0.3).abs();
-    
-	let white_intensity = saturate((spots - 0.8) * 4.0);
-	let orange_intensity = saturate((streak_pattern - 0.4) * 2.5);
-    
-	// Dynamic yellow base with variation
-	let yellow_mix = saturate(yellow_var * 1.2);
-	let base_yellow = yellow_bright * (1.0 - yellow_mix) + yellow_deep * yellow_mix;
-	let mut combined = base_yellow * (0.7 + 0.5 * tri_noise(world_pos, 12.0, time));
-    
-	// Add white spots
-	if white_intensity > 0.1 {
-		combined += white_hot * (white_intensity * 0.9);
+// A lit body that bounces sunlight onto its neighbours (earthshine)
+pub struct Reflector {
+	pub center: Vec3,
+	pub radius: f32,
+	pub albedo: f32,
+}
+
+// Light arriving at a body. Positions are in the frame the body is shaded
+// in, which for spinning planets is rotated with the surface.
+pub struct Lighting {
+	pub sun_pos: Vec3,
+	pub sun_color: Vec3,
+	pub sun_intensity: f32,    // irradiance at unit distance, falls off as 1/d^2
+	pub ambient: f32,          // stand-in for starlight and other faint sources
+	pub terminator_width: f32, // in units of n.l; 0 gives a hard Lambert edge
+	pub reflectors: Vec<Reflector>,
+}
+
+impl Lighting {
+	// Direction to the sun and the irradiance it delivers at `p`
+	pub fn sun_at(&self, p: &Vec3) -> (Vec3, f32) {
+		let to_sun = self.sun_pos - p;
+		let d2 = glm::dot(&to_sun, &to_sun).max(1e-6);
+		(to_sun / d2.sqrt(), self.sun_intensity / d2)
 	}
-    
-	// Add darker orange streaks
-	if orange_intensity > 0.0 {
-		combined += orange_dark * (orange_intensity * 0.9);
+
+	// Lambert term with the terminator spread over a band, as scattering in an
+	// atmosphere or an extended light source would do
+	pub fn diffuse(&self, n_dot_l: f32) -> f32 {
+		let w = self.terminator_width;
+		saturate((n_dot_l + w) / (1.0 + w))
 	}
 
-	// Add rim effect
-	let rim = (1.0 - saturate(glm::dot(view, normal))).powf(3.0) * 0.4;
-	combined += orange_dark * rim;
+	// Sunlight reflected by nearby bodies, each treated as a Lambertian sphere
+	pub fn earthshine(&self, p: &Vec3, normal: &Vec3) -> Vec3 {
+		let mut irradiance = 0.0;
+		for body in &self.reflectors {
+			let to_body = body.center - p;
+			let d = glm::length(&to_body);
+			if d <= body.radius {
+				continue;
+			}
+			let dir = to_body / d;
+			let facing = glm::dot(normal, &dir);
+			if facing <= 0.0 {
+				continue;
+			}
+			let (sun_dir, sun_irradiance) = self.sun_at(&body.center);
+			// Phase angle at the body between the sun and the receiver
+			let alpha = glm::dot(&sun_dir, &-dir).clamp(-1.0, 1.0).acos();
+			let phase = (alpha.sin() + (std::f32::consts::PI - alpha) * alpha.cos()) / std::f32::consts::PI;
+			let solid = (body.radius / d) * (body.radius / d);
+			irradiance += 2.0 / 3.0 * body.albedo * sun_irradiance * solid * phase * facing;
+		}
+		self.sun_color * irradiance
+	}
+}
 
-	// Final variation
-	combined * (0.8 + 0.4 * tri_noise(world_pos, 25.0, time * 1.8))
+// Physically based surface description, evaluated by `pbr_shade`
+#[derive(Clone, Copy, Debug)]
+pub struct PbrMaterial {
+	pub albedo: Vec3,            // diffuse color, or the reflectance of metals
+	pub roughness: f32,          // perceptual microfacet roughness, 0 (mirror) to 1
+	pub metallic: f32,           // 0 for rock, ice and gas, 1 for bare metal
+	pub reflectance: f32,        // normal-incidence reflectance of the non-metallic part
+	pub diffuse_roughness: f32,  // Oren-Nayar facet slope spread in radians, 0 for Lambert
+	pub emission: Vec3,          // radiance given off regardless of lighting
+	pub transmission: f32,       // share of the non-metallic part refracted into the body, 0 for opaque
+	pub ior: f32,                // index of refraction, for what is transmitted
 }
 
-pub fn rocky_shader(world_pos: &Vec3, normal: &Vec3, view: &Vec3, _time: f32) -> Vec3 {
-	let light_dir = glm::normalize(&(LIGHT_POS - world_pos));
-	let n_dot_l = saturate(glm::dot(normal, &light_dir));
-    
-	// Base colors
-	let rock_dark = glm::vec3(0.35, 0.23, 0.12);
-	let rock_light = glm::vec3(0.5, 0.5, 0.48);
-    
-	// Create banded pattern based on spherical coordinates
-	let lat = normal.y;
-	let bands = 0.5 + 0.5 * (lat * 20.0).sin();
-    
-	// Mix colors based on bands
-	let base_color = rock_dark * (1.0 - bands) + rock_light * bands;
-    
-	// Add craters using noise
-	let crater = tri_noise(world_pos, 30.0, 0.0);
-	let crater_mask = saturate((crater - 0.5) * 3.0);
-    
-	// Lighting
-	let ambient = 0.3;
-	let diffuse = n_dot_l * 0.7;
-	let mut final_color = base_color * (ambient + diffuse);
-    
-	// Darken craters
-	final_color *= 1.0 - crater_mask * 0.3;
-    
-	// Add specular highlight
-	let half_vec = glm::normalize(&(light_dir + view));
-	let spec = saturate(glm::dot(normal, &half_vec)).powf(32.0) * 0.4;
-	final_color += glm::vec3(spec, spec, spec);
+impl Default for PbrMaterial {
+	fn default() -> Self {
+		Self {
+			albedo: glm::vec3(1.0, 1.0, 1.0),
+			roughness: 0.5,
+			metallic: 0.0,
+			reflectance: 0.04,
+			diffuse_roughness: 0.0,
+			emission: glm::vec3(0.0, 0.0, 0.0),
+			transmission: 0.0,
+			ior: 1.5,
+		}
+	}
+}
 
-	// Add rim lighting
-	let rim = (1.0 - saturate(glm::dot(normal, view))).powf(3.0) * 0.14;
-	final_color += glm::vec3(0.9, 0.75, 0.55) * rim;
+impl PbrMaterial {
+	// Dusty, airless rock: no sheen, and the flat, backscattering look of
+	// a full Moon from its rough diffuse term
+	pub fn regolith(albedo: Vec3) -> Self {
+		Self { albedo, roughness: 0.9, diffuse_roughness: 0.5, ..Self::default() }
+	}
 
-	final_color
+	// Clear solid such as glass (ior 1.5) or ice (1.31), tinted by `albedo`
+	// on the way in, with the reflectance its index gives
+	pub fn dielectric(albedo: Vec3, ior: f32, roughness: f32) -> Self {
+		let f0 = ((ior - 1.0) / (ior + 1.0)).powi(2);
+		Self { albedo, roughness, reflectance: f0, transmission: 1.0, ior, ..Self::default() }
+	}
 }
 
-pub fn gas_giant_shader(world_pos: &Vec3, normal: &Vec3, view: &Vec3, time: f32) -> Vec3 {
-	// Calculate bands based on latitude (y coordinate in normal space)
-	let lat = normal.y;
-	let lon = normal.x.atan2(normal.z);
-    
-	// Create flowing band pattern
-	let flow = lon * 6.0 + time * 0.8 + (lat * 10.0).sin() * 0.5;
-	let turbulence = tri_noise(world_pos, 8.0, time * 0.5);
-	let bands = (flow + turbulence * 2.0).sin() * 0.5 + 0.5;
-    
-	// Colors for the bands
-	let color1 = glm::vec3(0.95, 0.78, 0.48); // Light band
-	let color2 = glm::vec3(0.25, 0.55, 0.85); // Dark band
-    
-	// Mix colors based on bands
-	let base_color = color1 * bands + color2 * (1.0 - bands);
+// Surfaces smoother than this reflect the scene in secondary rays. The
+// reflection fades out towards it, standing in for the blur of a glossy
+// lobe, so the change from mirror to matte is gradual.
+pub const GLOSSY_LIMIT: f32 = 0.25;
+
+// A ray a surface sends on, and the share of the light it finds that
+// comes back along the ray that arrived
+pub struct SecondaryRay {
+	pub direction: Vec3,
+	pub weight: Vec3,
+}
+
+// Rays a smooth or clear surface sends on when seen along `view`, besides
+// the light it scatters: the mirror direction and the refracted one.
+// Either is None when it carries nothing. `normal` points out of the body; seen from inside, the
+// refracted ray leaves it, or total internal reflection sends everything
+// back in.
+pub fn secondary_rays(material: &PbrMaterial, normal: &Vec3, view: &Vec3) -> (Option<SecondaryRay>, Option<SecondaryRay>) {
+	let white = glm::vec3(1.0, 1.0, 1.0);
+	let inside = glm::dot(normal, view) > 0.0;
+	let (n, eta) = if inside { (-normal, material.ior) } else { (*normal, 1.0 / material.ior) };
+	let cos_i = (-glm::dot(&n, view)).clamp(0.0, 1.0);
+	let mirror = view + n * (2.0 * cos_i);
+	let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+	let transmitted = material.transmission * (1.0 - material.metallic);
+
+	// Schlick's Fresnel, on the side of the interface with the smaller angle
+	let f0 = white * material.reflectance * (1.0 - material.metallic) + material.albedo * material.metallic;
+	let (fresnel, refracted) = if transmitted > 0.0 && sin2_t >= 1.0 {
+		(white, None)
+	} else {
+		let cos_t = (1.0 - sin2_t).max(0.0).sqrt();
+		let cos = if eta > 1.0 { cos_t } else { cos_i };
+		let fresnel = f0 + (white - f0) * (1.0 - cos).powi(5);
+		(fresnel, (transmitted > 0.0).then(|| view * eta + n * (eta * cos_i - cos_t)))
+	};
+
+	// Reflections inside a body stay sharp, or light would leak away
+	let gloss = if inside { 1.0 } else { saturate(1.0 - material.roughness / GLOSSY_LIMIT) };
+	let reflect = (gloss > 0.0).then(|| SecondaryRay { direction: mirror, weight: fresnel * gloss });
+	let refract = refracted.map(|dir| {
+		// Tinted once, on the way in
+		let tint = if inside { white } else { material.albedo };
+		SecondaryRay { direction: glm::normalize(&dir), weight: (white - fresnel).component_mul(&tint) * transmitted }
+	});
+	(reflect, refract)
+}
+
+// GGX normal distribution, with alpha = roughness^2
+fn ggx_distribution(n_dot_h: f32, alpha: f32) -> f32 {
+	let a2 = alpha * alpha;
+	let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+	a2 / (std::f32::consts::PI * d * d).max(1e-12)
+}
+
+// Smith shadowing-masking with Schlick's approximation for GGX
+fn smith_visibility(n_dot_v: f32, n_dot_l: f32, alpha: f32) -> f32 {
+	let k = alpha * 0.5;
+	let g = |x: f32| x / (x * (1.0 - k) + k);
+	g(n_dot_v) * g(n_dot_l)
+}
+
+// Oren-Nayar's factor on a Lambert surface (qualitative model): rough
+// surfaces send more light back towards the sun and look flatter
+fn oren_nayar(normal: &Vec3, to_eye: &Vec3, light_dir: &Vec3, sigma: f32) -> f32 {
+	if sigma <= 0.0 {
+		return 1.0;
+	}
+	let s2 = sigma * sigma;
+	let a = 1.0 - 0.5 * s2 / (s2 + 0.33);
+	let b = 0.45 * s2 / (s2 + 0.09);
+	let n_dot_v = saturate(glm::dot(normal, to_eye));
+	let n_dot_l = saturate(glm::dot(normal, light_dir));
+	// Cosine of the azimuth between the two directions, around the normal
+	let v_perp = to_eye - normal * n_dot_v;
+	let l_perp = light_dir - normal * n_dot_l;
+	let cos_phi = glm::dot(&v_perp, &l_perp) / (glm::length(&v_perp) * glm::length(&l_perp)).max(1e-6);
+	let (theta_v, theta_l) = (n_dot_v.acos(), n_dot_l.acos());
+	let (alpha, beta) = (theta_v.max(theta_l), theta_v.min(theta_l));
+	a + b * cos_phi.max(0.0) * alpha.sin() * beta.tan()
+}
+
+// Light `material` sends towards the eye: sunlight through a GGX
+// microfacet BRDF and an Oren-Nayar diffuse lobe, earthshine and ambient
+// light on the albedo, plus emission. Like the other shaders, radiance is
+// scaled by pi, so a white Lambert surface facing the sun returns the
+// irradiance. `sun_visibility` dims the direct light, e.g. under clouds;
+// `soft_terminator` spreads the diffuse terminator as Lighting::diffuse
+// does, for bodies with an atmosphere.
+pub fn pbr_shade(material: &PbrMaterial, world_pos: &Vec3, normal: &Vec3, view: &Vec3, sun_visibility: f32, soft_terminator: bool, lighting: &Lighting) -> Vec3 {
+	let (light_dir, irradiance) = lighting.sun_at(world_pos);
+	let sun = lighting.sun_color * (irradiance * sun_visibility);
+	let n_dot_l = glm::dot(normal, &light_dir);
+	let cos_in = if soft_terminator { lighting.diffuse(n_dot_l) } else { saturate(n_dot_l) };
+	let reflected = reflectance(material, normal, &-view, &light_dir, cos_in);
+
+	let indirect = lighting.earthshine(world_pos, normal) + glm::vec3(1.0, 1.0, 1.0) * lighting.ambient;
+	let scattering = material.albedo * (1.0 - material.transmission);
+	reflected.component_mul(&sun) + scattering.component_mul(&indirect) + material.emission
+}
+
+// Share of the irradiance arriving along `light_dir` that `material` sends
+// towards the eye: the BRDF times n.l, scaled by pi like pbr_shade's
+// radiance. Path tracers divide by the pdf of `light_dir` on top.
+pub fn pbr_reflectance(material: &PbrMaterial, normal: &Vec3, to_eye: &Vec3, light_dir: &Vec3) -> Vec3 {
+	reflectance(material, normal, to_eye, light_dir, saturate(glm::dot(normal, light_dir)))
+}
+
+// pbr_reflectance with `cos_in` standing in for n.l in the diffuse lobe
+fn reflectance(material: &PbrMaterial, normal: &Vec3, to_eye: &Vec3, light_dir: &Vec3, cos_in: f32) -> Vec3 {
+	let n_dot_l = glm::dot(normal, light_dir);
+	let n_dot_v = glm::dot(normal, to_eye).max(1e-4);
+
+	let f0 = glm::vec3(1.0, 1.0, 1.0) * material.reflectance * (1.0 - material.metallic) + material.albedo * material.metallic;
+	let half_vec = glm::normalize(&(light_dir + to_eye));
+	let v_dot_h = saturate(glm::dot(to_eye, &half_vec));
+	let fresnel = f0 + (glm::vec3(1.0, 1.0, 1.0) - f0) * (1.0 - v_dot_h).powi(5);
+
+	// Light the surface refracts into the body and scatters back out
+	let diffuse_color = material.albedo * ((1.0 - material.metallic) * (1.0 - material.transmission));
+	let diffuse = diffuse_color
+		.component_mul(&(glm::vec3(1.0, 1.0, 1.0) - fresnel))
+		* (cos_in * oren_nayar(normal, to_eye, light_dir, material.diffuse_roughness));
+
+	let specular = if n_dot_l > 0.0 {
+		let alpha = (material.roughness * material.roughness).max(1e-3);
+		let n_dot_h = saturate(glm::dot(normal, &half_vec));
+		let lobe = ggx_distribution(n_dot_h, alpha) * smith_visibility(n_dot_v, n_dot_l, alpha) / (4.0 * n_dot_v * n_dot_l).max(1e-4);
+		fresnel * (lobe * std::f32::consts::PI * n_dot_l)
+	} else {
+		glm::vec3(0.0, 0.0, 0.0)
+	};
+	diffuse + specular
+}
+
+// Everything a surface shader is told about the point it shades. The
+// frame_* vectors are in the frame `lighting` is given in, which for the
+// planets spins with the surface.
+pub struct ShadingContext<'a> {
+	pub point: Vec3,        // hit point, world space
+	pub normal: Vec3,       // world space
+	pub view: Vec3,         // direction of the ray that hit, world space
+	pub frame_point: Vec3,
+	pub frame_normal: Vec3,
+	pub frame_view: Vec3,
+	pub object_point: Vec3, // hit point relative to the body's center, turning with the body
+	pub uv: [f32; 2],       // object_point's direction mapped like Mesh::uv_sphere
+	pub lighting: &'a Lighting,
+	pub time: f32,
+	pub body: &'a BodyParams<'a>,
+}
+
+// What a shader knows about the body it shades. Features a body does not
+// have stay None.
+pub struct BodyParams<'a> {
+	pub center: Vec3,          // world space
+	pub radius: f32,           // of a sphere enclosing the body
+	pub material: PbrMaterial,
+	pub noise_offset: Vec3,    // shift of the noise domain, picks continents, bands and craters
+	pub light_visibility: f32, // direct light reaching the ground, e.g. under clouds (1 = unshadowed)
+	pub star: Option<&'a StarParams>,
+	pub ocean: Option<&'a OceanParams>,
+	pub night: Option<&'a NightEmission>,
+}
+
+impl BodyParams<'_> {
+	// A plain white body, in full sunlight
+	pub fn new(center: Vec3, radius: f32) -> Self {
+		BodyParams {
+			center,
+			radius,
+			material: PbrMaterial::default(),
+			noise_offset: glm::vec3(0.0, 0.0, 0.0),
+			light_visibility: 1.0,
+			star: None,
+			ocean: None,
+			night: None,
+		}
+	}
+}
+
+// Color of the light leaving a surface towards the viewer
+pub trait SurfaceShader: Send + Sync {
+	fn shade(&self, ctx: &ShadingContext) -> Vec3;
+
+	// Material and shading normal at the point, for renderers that work out
+	// the light transport themselves, like the path tracer. The normal is in
+	// the frame of frame_normal; light the surface gives off by itself goes
+	// in the emission. By default, the body's material on the plain normal.
+	fn surface(&self, ctx: &ShadingContext) -> (PbrMaterial, Vec3) {
+		(ctx.body.material, ctx.frame_normal)
+	}
+}
+
+// Surface shaders by name. Bodies pick their shader by name, so shaders can
+// be added or the built-in ones replaced without touching the renderer.
+pub struct ShaderRegistry {
+	shaders: HashMap<String, Box<dyn SurfaceShader>>,
+}
+
+impl ShaderRegistry {
+	pub fn empty() -> Self {
+		ShaderRegistry { shaders: HashMap::new() }
+	}
+
+	// Replaces any shader already registered under `name`
+	pub fn register(&mut self, name: &str, shader: impl SurfaceShader + 'static) {
+		self.shaders.insert(name.to_string(), Box::new(shader));
+	}
+
+	pub fn get(&self, name: &str) -> Option<&dyn SurfaceShader> {
+		self.shaders.get(name).map(|shader| shader.as_ref())
+	}
+}
+
+impl Default for ShaderRegistry {
+	// The built-in shaders: sun, rocky, gas_giant and regolith
+	fn default() -> Self {
+		let mut registry = Self::empty();
+		registry.register("sun", SunShader);
+		registry.register("rocky", RockyShader);
+		registry.register("gas_giant", GasGiantShader);
+		registry.register("regolith", RegolithShader);
+		registry
+	}
+}
+
+// Texture coordinates of a direction, as Mesh::uv_sphere lays them out
+pub fn sphere_uv(dir: &Vec3) -> [f32; 2] {
+	let tau = 2.0 * std::f32::consts::PI;
+	[(dir.z.atan2(dir.x) / tau).rem_euclid(1.0), dir.y.clamp(-1.0, 1.0).acos() / std::f32::consts::PI]
+}
+
+// Light a body's night side gives off by itself
+pub enum NightEmission {
+	CityLights { color: Vec3, intensity: f32 },
+	Lava { color: Vec3, intensity: f32 },
+}
+
+impl NightEmission {
+	// Emitted radiance at a direction from the body center (shading frame)
+	pub fn radiance(&self, dir: &Vec3, on_land: bool) -> Vec3 {
+		match self {
+			NightEmission::CityLights { color, intensity } => {
+				if !on_land {
+					return glm::vec3(0.0, 0.0, 0.0);
+				}
+				// Small bright specks gathered into metropolitan clusters
+				let region = smoothstep(0.42, 0.62, noise::fbm(&(dir * 6.0), 3));
+				let specks = smoothstep(0.55, 0.8, noise::value_noise(&(dir * 90.0)));
+				color * (region * specks * intensity)
+			}
+			NightEmission::Lava { color, intensity } => {
+				// Glowing cracks along the lanes of a cellular pattern
+				let (f1, f2) = noise::worley(&(dir * 10.0));
+				let crack = 1.0 - smoothstep(0.0, 0.12, f2 - f1);
+				color * (crack * intensity)
+			}
+		}
+	}
+}
+
+// Night-side emission only shows where the surface faces away from the sun
+fn night_fade(n_dot_l: f32) -> f32 {
+	smoothstep(0.0, 0.25, -n_dot_l)
+}
+
+// Parameters of the stellar surface model. Temperatures are in Kelvin,
+// angular speeds in radians per second and periods in seconds.
+pub struct StarParams {
+	pub temperature: f32,       // effective temperature, sets the base color
+	pub brightness: f32,        // overall radiance gain
+	pub limb_darkening: f32,    // linear coefficient u in I(mu) = 1 - u (1 - mu)
+	pub granulation_scale: f32, // convection cells across one stellar radius
+	pub rotation_speed: f32,    // equatorial rotation rate
+	pub cycle_period: f32,      // length of one activity (sunspot) cycle
+	pub spot_groups: u32,       // spot groups that may appear per cycle
+	pub flare_rate: f32,        // expected flares per second
+	pub seed: u32,              // picks which spots, flares and granules appear
+}
+
+impl StarParams {
+	pub fn new(temperature: f32) -> Self {
+		Self {
+			temperature,
+			brightness: 1.15,
+			limb_darkening: 0.6,
+			granulation_scale: 14.0,
+			rotation_speed: 0.08,
+			cycle_period: 120.0,
+			spot_groups: 120,
+			flare_rate: 0.08,
+			seed: 0,
+		}
+	}
+}
+
+impl Default for StarParams {
+	fn default() -> Self {
+		Self::new(5778.0)
+	}
+}
+
+// Flares are scheduled in fixed time slots so every pixel agrees on them
+const FLARE_SLOT: f32 = 6.0;
+const FLARE_RISE: f32 = 0.6;
+const FLARE_DECAY: f32 = 2.5;
+// Tallest prominence loop, in stellar radii above the surface
+const PROMINENCE_MAX_HEIGHT: f32 = 0.4;
+
+fn smoothstep(e0: f32, e1: f32, x: f32) -> f32 {
+	let t = saturate((x - e0) / (e1 - e0));
+	t * t * (3.0 - 2.0 * t)
+}
+
+fn unit_from_lat_lon(lat: f32, lon: f32) -> Vec3 {
+	glm::vec3(lat.cos() * lon.cos(), lat.sin(), lat.cos() * lon.sin())
+}
+
+// Differential rotation: the equator spins faster than the poles
+// (solar profile A + B sin^2 + C sin^4, normalized to the equatorial rate).
+fn star_angular_speed(params: &StarParams, sin_lat: f32) -> f32 {
+	let s2 = sin_lat * sin_lat;
+	params.rotation_speed * (1.0 - 0.163 * s2 - 0.121 * s2 * s2)
+}
+
+// Unit direction on the star -> same point in the co-rotating frame
+fn to_star_frame(n: &Vec3, params: &StarParams, time: f32) -> Vec3 {
+	let lat = n.y.clamp(-1.0, 1.0).asin();
+	let lon = n.z.atan2(n.x) - star_angular_speed(params, n.y) * time;
+	unit_from_lat_lon(lat, lon)
+}
+
+// Co-rotating frame -> world direction (inverse of to_star_frame)
+fn from_star_frame(q: &Vec3, params: &StarParams, time: f32) -> Vec3 {
+	let lat = q.y.clamp(-1.0, 1.0).asin();
+	let lon = q.z.atan2(q.x) + star_angular_speed(params, q.y) * time;
+	unit_from_lat_lon(lat, lon)
+}
+
+// Spoerer's law: spots emerge around 35 degrees at the start of a cycle and
+// drift towards ~7 degrees by its end.
+fn active_latitude(phase: f32) -> f32 {
+	(35.0 - 28.0 * phase).to_radians()
+}
+
+// Visit every spot alive at `time` as (center in star frame, umbra radius).
+// Groups are bipolar: a leader and a follower spot tilted by Joy's law.
+fn for_each_sunspot(params: &StarParams, time: f32, mut f: impl FnMut(Vec3, f32)) {
+	let period = params.cycle_period.max(1e-3);
+	let cycle = (time / period).floor() as i32;
+	for c in (cycle - 1)..=cycle {
+		let seed = noise::hash_u32(c as u32 ^ 0x5eed_5a75 ^ params.seed);
+		for i in 0..params.spot_groups {
+			let h = noise::hash_u32(seed ^ i.wrapping_mul(0x2c1b_3c6d));
+			let birth = noise::hash_f32(h, 0);
+			// Fewer groups near cycle minimum
+			let activity = (std::f32::consts::PI * birth).sin().powi(2);
+			if noise::hash_f32(h, 1) > activity {
+				continue;
+			}
+			let life = (0.05 + 0.1 * noise::hash_f32(h, 2)) * period;
+			let age = time - (c as f32 + birth) * period;
+			if age < 0.0 || age > life {
+				continue;
+			}
+			let envelope = (std::f32::consts::PI * age / life).sin().sqrt();
+			let hemisphere = if noise::hash_f32(h, 3) < 0.5 { -1.0 } else { 1.0 };
+			let lat = active_latitude(birth) + (noise::hash_f32(h, 4) - 0.5) * 0.08;
+			let lon = noise::hash_f32(h, 5) * 2.0 * std::f32::consts::PI;
+			let size = (0.02 + 0.03 * noise::hash_f32(h, 6)) * envelope;
+			let sep = size * 3.5;
+			let tilt = 0.5 * lat;
+			// Leader sits slightly closer to the equator than the follower
+			let leader = unit_from_lat_lon(hemisphere * (lat - sep * tilt.sin() * 0.5), lon + sep * 0.5);
+			let follower = unit_from_lat_lon(hemisphere * (lat + sep * tilt.sin() * 0.5), lon - sep * 0.5);
+			f(leader, size);
+			f(follower, size * 0.7);
+		}
+	}
+}
+
+struct Flare {
+	foot_a: Vec3, // footpoints in the star frame
+	foot_b: Vec3,
+	height: f32,  // loop apex above the surface (stellar radii)
+	strength: f32,
+}
+
+// Visit every flare whose light has not yet decayed at `time`
+fn for_each_flare(params: &StarParams, time: f32, mut f: impl FnMut(Flare)) {
+	let chance = (params.flare_rate * FLARE_SLOT).min(1.0);
+	let slot = (time / FLARE_SLOT).floor() as i32;
+	for k in (slot - 3)..=slot {
+		let h = noise::hash_u32(k as u32 ^ 0xf1a2_e5ed ^ params.seed);
+		if noise::hash_f32(h, 0) > chance {
+			continue;
+		}
+		let start = (k as f32 + noise::hash_f32(h, 1)) * FLARE_SLOT;
+		let age = time - start;
+		if age < 0.0 {
+			continue;
+		}
+		let strength = (age / FLARE_RISE).min(1.0) * (-(age - FLARE_RISE).max(0.0) / FLARE_DECAY).exp();
+		if strength < 0.01 {
+			continue;
+		}
+		let phase = (time / params.cycle_period.max(1e-3)).fract();
+		let hemisphere = if noise::hash_f32(h, 2) < 0.5 { -1.0 } else { 1.0 };
+		let lat = hemisphere * (active_latitude(phase) + (noise::hash_f32(h, 3) - 0.5) * 0.1);
+		let lon = noise::hash_f32(h, 4) * 2.0 * std::f32::consts::PI;
+		let span = 0.12 + 0.15 * noise::hash_f32(h, 5);
+		f(Flare {
+			foot_a: unit_from_lat_lon(lat, lon - span),
+			foot_b: unit_from_lat_lon(lat + span * 0.3, lon + span),
+			height: PROMINENCE_MAX_HEIGHT * (0.3 + 0.7 * noise::hash_f32(h, 6)),
+			strength,
+		});
+	}
+}
+
+// Brightness factor of the convection pattern: hot cell centers and dark,
+// cooler intergranular lanes. Two patterns cross-fade so cells evolve.
+fn granulation(q: &Vec3, params: &StarParams, time: f32) -> f32 {
+	let lifetime = 8.0;
+	let epoch = (time / lifetime).floor();
+	let blend = smoothstep(0.0, 1.0, time / lifetime - epoch);
+	let layer = |e: f32| {
+		let h = noise::hash_u32(e as i32 as u32 ^ params.seed);
+		let offset = glm::vec3(noise::hash_f32(h, 0), noise::hash_f32(h, 1), noise::hash_f32(h, 2)) * 100.0;
+		let (f1, f2) = noise::worley(&(q * params.granulation_scale + offset));
+		smoothstep(0.0, 0.45, f2 - f1)
+	};
+	layer(epoch) * (1.0 - blend) + layer(epoch + 1.0) * blend
+}
+
+// Photosphere of a star: granulation, spots and flare ribbons, limb
+// darkened. Uses the body's StarParams, or the Sun's without them.
+pub struct SunShader;
+
+impl SurfaceShader for SunShader {
+	fn shade(&self, ctx: &ShadingContext) -> Vec3 {
+		let sun_like;
+		let params = match ctx.body.star {
+			Some(params) => params,
+			None => {
+				sun_like = StarParams::default();
+				&sun_like
+			}
+		};
+		let (normal, view, time) = (&ctx.frame_normal, &ctx.frame_view, ctx.time);
+		// Cosine between the line of sight and the surface normal
+		let mu = saturate(-glm::dot(view, normal));
+		let q = to_star_frame(normal, params, time);
+
+		// Local temperature: granules are slightly hotter than their lanes,
+		// spots much cooler (umbra ~1900K, penumbra ~700K below photosphere).
+		let gran = granulation(&q, params, time);
+		let mut temperature = params.temperature + (gran - 0.5) * 120.0;
+		let mut spot_cooling: f32 = 0.0;
+		for_each_sunspot(params, time, |center, umbra| {
+			let d = (2.0 * (1.0 - glm::dot(&q, &center)).max(0.0)).sqrt();
+			let penumbra = umbra * 2.2;
+			let cooling = 700.0 * (1.0 - smoothstep(umbra, penumbra, d))
+				+ 1200.0 * (1.0 - smoothstep(umbra * 0.6, umbra, d));
+			spot_cooling = spot_cooling.max(cooling);
+		});
+		temperature -= spot_cooling;
+
+		// Flare ribbons brighten and heat the footpoints
+		let mut flare_heat = 0.0;
+		for_each_flare(params, time, |flare| {
+			for foot in [&flare.foot_a, &flare.foot_b] {
+				let d2 = 2.0 * (1.0 - glm::dot(&q, foot)).max(0.0);
+				flare_heat += flare.strength * (-d2 / 0.0006).exp();
+			}
+		});
+		temperature += flare_heat * 5000.0;
+
+		// Limb darkening; the limb also looks redder because we see cooler,
+		// higher layers there.
+		let limb = 1.0 - params.limb_darkening * (1.0 - mu);
+		let color_temperature = temperature * (0.88 + 0.12 * mu);
+		// Radiance scales with T^4 relative to the photosphere
+		let radiance = (temperature / params.temperature).powi(4) * limb * (0.92 + 0.12 * gran);
+
+		color::blackbody(color_temperature).to_vec3() * (radiance * params.brightness)
+	}
+
+	// Glows, reflects nothing
+	fn surface(&self, ctx: &ShadingContext) -> (PbrMaterial, Vec3) {
+		let material = PbrMaterial { albedo: glm::vec3(0.0, 0.0, 0.0), emission: self.shade(ctx), ..PbrMaterial::default() };
+		(material, ctx.frame_normal)
+	}
+}
+
+// Emission of flare prominences: loops of hot plasma above the surface that
+// show up against the sky at the limb. `t_max` stops the march at whatever
+// the ray hit first (including the star itself).
+pub fn star_prominences(ray: &Ray, center: &Vec3, radius: f32, params: &StarParams, time: f32, t_max: f32) -> Vec3 {
+	let black = glm::vec3(0.0, 0.0, 0.0);
+	let oc = center - ray.origin;
+	let tc = glm::dot(&oc, &ray.direction);
+	let outer = radius * (1.0 + PROMINENCE_MAX_HEIGHT);
+	let d2 = glm::dot(&oc, &oc) - tc * tc;
+	if d2 > outer * outer {
+		return black;
+	}
+
+	// Loop centerlines in world space, relative to the star center in radii
+	let mut loops: Vec<([Vec3; 17], f32)> = Vec::new();
+	for_each_flare(params, time, |flare| {
+		let a = from_star_frame(&flare.foot_a, params, time);
+		let b = from_star_frame(&flare.foot_b, params, time);
+		let mut pts = [black; 17];
+		for (i, pt) in pts.iter_mut().enumerate() {
+			let u = i as f32 / 16.0;
+			let base = glm::normalize(&(a * (1.0 - u) + b * u));
+			*pt = base * (1.0 + flare.height * (std::f32::consts::PI * u).sin());
+		}
+		loops.push((pts, flare.strength));
+	});
+	if loops.is_empty() {
+		return black;
+	}
+
+	let half = (outer * outer - d2).sqrt();
+	let t0 = (tc - half).max(0.0);
+	let t1 = (tc + half).min(t_max);
+	if t1 <= t0 {
+		return black;
+	}
+	let steps = 32;
+	let dt = (t1 - t0) / steps as f32;
+	let width2 = 0.04 * 0.04;
+	let mut density = 0.0;
+	for i in 0..steps {
+		let t = t0 + (i as f32 + 0.5) * dt;
+		let p = (ray.origin + ray.direction * t - center) / radius;
+		for (pts, strength) in &loops {
+			let mut best = f32::INFINITY;
+			for pt in pts.iter() {
+				best = best.min(glm::distance2(&p, pt));
+			}
+			density += strength * (-best / width2).exp() * dt / radius;
+		}
+	}
+	// H-alpha dominated emission: deep pink-red
+	glm::vec3(1.0, 0.35, 0.4) * (density * 25.0 * params.brightness)
+}
+
+// Semi-transparent cloud shell around a rocky planet. It spins on its own,
+// independently of the ground below.
+pub struct CloudLayer {
+	pub altitude: f32,   // shell height above the surface, in world units
+	pub spin_speed: f32, // radians per second
+	pub coverage: f32,   // fraction of the sky covered, 0..1
+	pub opacity: f32,    // alpha of the thickest clouds
+	pub scale: f32,      // feature frequency over the unit sphere
+	pub offset: Vec3,    // shift of the noise domain, picks the cloud pattern
+}
+
+impl Default for CloudLayer {
+	fn default() -> Self {
+		Self {
+			altitude: 0.03,
+			spin_speed: 2.6,
+			coverage: 0.45,
+			opacity: 0.85,
+			scale: 3.5,
+			offset: glm::vec3(0.0, 0.0, 0.0),
+		}
+	}
+}
+
+// Cloud alpha at a direction from the planet center, given in the cloud
+// layer's own rotating frame.
+pub fn cloud_density(dir: &Vec3, layer: &CloudLayer, time: f32) -> f32 {
+	// Slow drift on top of the rigid spin so the pattern keeps evolving
+	let drift = glm::vec3(time * 0.02, 0.0, -time * 0.015);
+	let n = noise::fbm(&((dir + layer.offset) * layer.scale + drift), 5);
+	let threshold = 1.0 - layer.coverage;
+	smoothstep(threshold - 0.08, threshold + 0.12, n * 1.1) * layer.opacity
+}
+
+// Lit cloud color and alpha where a ray crosses the shell. `world_pos` and
+// `normal` are in world space, `local_dir` in the layer's rotating frame.
+pub fn cloud_shader(world_pos: &Vec3, normal: &Vec3, local_dir: &Vec3, layer: &CloudLayer, time: f32, lighting: &Lighting) -> (Vec3, f32) {
+	let alpha = cloud_density(local_dir, layer, time);
+	let (light_dir, irradiance) = lighting.sun_at(world_pos);
+	let n_dot_l = glm::dot(normal, &light_dir);
+	let albedo = glm::vec3(0.9, 0.92, 0.95);
+	let lit = lighting.sun_color * (irradiance * lighting.diffuse(n_dot_l)) + lighting.earthshine(world_pos, normal);
+	let color = albedo.component_mul(&(lit + glm::vec3(1.0, 1.0, 1.0) * lighting.ambient));
+	(color, alpha)
+}
+
+// Fraction of light reaching a ground point through the cloud layer above.
+// Averages a few taps around the shadow point so the edges come out soft.
+pub fn cloud_shadow(ground: &Vec3, center: &Vec3, shell_radius: f32, to_local: impl Fn(&Vec3) -> Vec3, layer: &CloudLayer, time: f32, lighting: &Lighting) -> f32 {
+	let (light_dir, _) = lighting.sun_at(ground);
+	// The ground is inside the shell, so the far root is where the shadow ray leaves it
+	let oc = ground - center;
+	let b = glm::dot(&oc, &light_dir);
+	let c = glm::dot(&oc, &oc) - shell_radius * shell_radius;
+	let t = -b + (b * b - c).max(0.0).sqrt();
+	let hit = oc + light_dir * t;
+	let dir = glm::normalize(&hit);
+
+	// Tangent frame for the blur taps
+	let helper = if dir.y.abs() < 0.9 { glm::vec3(0.0, 1.0, 0.0) } else { glm::vec3(1.0, 0.0, 0.0) };
+	let tu = glm::normalize(&glm::cross(&dir, &helper));
+	let tv = glm::cross(&dir, &tu);
+	let spread = 0.025;
+	let taps = [
+		dir,
+		glm::normalize(&(dir + tu * spread)),
+		glm::normalize(&(dir - tu * spread)),
+		glm::normalize(&(dir + tv * spread)),
+		glm::normalize(&(dir - tv * spread)),
+	];
+	let mut density = 0.0;
+	for tap in taps.iter() {
+		density += cloud_density(&to_local(tap), layer, time);
+	}
+	1.0 - 0.7 * density / taps.len() as f32
+}
+
+// Oceans on a rocky body: anything whose terrain height is below
+// `sea_level` is water.
+pub struct OceanParams {
+	pub sea_level: f32,     // threshold on terrain_height (0..1)
+	pub wave_scale: f32,    // frequency of the wave pattern
+	pub wave_strength: f32, // how far waves tilt the normal
+	pub roughness: f32,     // GGX roughness of the water, sets the size of the sun glint
+}
+
+impl Default for OceanParams {
+	fn default() -> Self {
+		Self {
+			sea_level: 0.53,
+			wave_scale: 60.0,
+			wave_strength: 0.12,
+			roughness: 0.27,
+		}
+	}
+}
+
+// Procedural terrain height (0..1) for a direction from the planet center,
+// shifted by the body's noise offset so each scene seed has its own land
+pub fn terrain_height(dir: &Vec3, offset: &Vec3) -> f32 {
+	noise::fbm(&((dir + offset) * 2.2 + glm::vec3(5.3, 1.7, 8.9)), 6)
+}
+
+// Perturb a normal with the gradient of animated value noise
+fn wave_normal(normal: &Vec3, ocean: &OceanParams, time: f32) -> Vec3 {
+	let p = normal * ocean.wave_scale + glm::vec3(time * 0.3, 0.0, time * 0.2);
+	let e = 0.05;
+	let n0 = noise::value_noise(&p);
+	let grad = glm::vec3(
+		noise::value_noise(&(p + glm::vec3(e, 0.0, 0.0))) - n0,
+		noise::value_noise(&(p + glm::vec3(0.0, e, 0.0))) - n0,
+		noise::value_noise(&(p + glm::vec3(0.0, 0.0, e))) - n0,
+	) / e;
+	let tangential = grad - normal * glm::dot(&grad, normal);
+	glm::normalize(&(normal - tangential * ocean.wave_strength))
+}
+
+// Water albedo (lighter over shallow shelves) and its wave normal
+fn ocean_surface(normal: &Vec3, time: f32, ocean: &OceanParams, depth: f32) -> (Vec3, Vec3) {
+	let shallow = glm::vec3(0.06, 0.28, 0.36);
+	let deep = glm::vec3(0.01, 0.06, 0.18);
+	let water = shallow + (deep - shallow) * saturate(depth * 12.0);
+	(water, wave_normal(normal, ocean, time))
+}
+
+// Rocky planet: banded rock with dark crater patches, oceans below sea
+// level with a Fresnel sun glint, and night-side lights
+pub struct RockyShader;
+
+impl RockyShader {
+	// Material and shading normal of water or rock at the point, whether it
+	// is water, and where the surface patterns are looked up
+	fn ground(ctx: &ShadingContext) -> (PbrMaterial, Vec3, bool, Vec3) {
+		let (world_pos, normal, time) = (&ctx.frame_point, &ctx.frame_normal, ctx.time);
+		let surface = ctx.body;
+		// Patterns follow the direction from the center, so terrain raised
+		// out of the sphere keeps its coastlines; its normal only lights it
+		let up = glm::normalize(&ctx.object_point);
+		let pattern_dir = up + surface.noise_offset;
+
+		// Water below sea level, rock everywhere else
+		let water = surface.ocean.and_then(|ocean| {
+			let height = terrain_height(&up, &surface.noise_offset);
+			(height < ocean.sea_level).then(|| (ocean, ocean_surface(normal, time, ocean, ocean.sea_level - height)))
+		});
+		let (albedo, shading_normal) = match water {
+			Some((_, water_surface)) => water_surface,
+			None => {
+				// Base colors
+				let rock_dark = glm::vec3(0.35, 0.23, 0.12);
+				let rock_light = glm::vec3(0.5, 0.5, 0.48);
+
+				// Create banded pattern based on spherical coordinates
+				let lat = up.y;
+				let bands = 0.5 + 0.5 * (lat * 20.0).sin();
+
+				// Mix colors based on bands
+				let base_color = rock_dark * (1.0 - bands) + rock_light * bands;
+
+				// Darken craters, found using noise
+				let crater = tri_noise(&(world_pos + surface.noise_offset), 30.0, 0.0);
+				let crater_mask = saturate((crater - 0.5) * 3.0);
+				(base_color * (1.0 - crater_mask * 0.3), *normal)
+			}
+		};
+
+		// Land takes the body's material under its own colors; water is a
+		// smooth dielectric whose glint is a sharp GGX lobe
+		let material = match water {
+			Some((ocean, _)) => PbrMaterial { albedo, roughness: ocean.roughness, metallic: 0.0, reflectance: 0.02, diffuse_roughness: 0.0, ..surface.material },
+			None => PbrMaterial { albedo: albedo.component_mul(&surface.material.albedo), ..surface.material },
+		};
+		(material, shading_normal, water.is_some(), pattern_dir)
+	}
+}
+
+impl SurfaceShader for RockyShader {
+	fn shade(&self, ctx: &ShadingContext) -> Vec3 {
+		let (world_pos, normal, view) = (&ctx.frame_point, &ctx.frame_normal, &ctx.frame_view);
+		let (surface, lighting) = (ctx.body, ctx.lighting);
+		let (light_dir, _) = lighting.sun_at(world_pos);
+		let to_eye = -view;
+		let (material, shading_normal, is_water, pattern_dir) = Self::ground(ctx);
+		let mut final_color = pbr_shade(&material, world_pos, &shading_normal, view, surface.light_visibility, true, lighting);
+
+		let n_dot_l = glm::dot(&shading_normal, &light_dir);
+		if is_water {
+			// Sky reflection at grazing angles, only on the day side
+			let n_dot_v = saturate(glm::dot(&shading_normal, &to_eye));
+			final_color += glm::vec3(0.25, 0.35, 0.5) * (0.02 + 0.98 * (1.0 - n_dot_v).powi(5)) * 0.3 * lighting.diffuse(n_dot_l);
+		}
+
+		// Add rim lighting, only where the atmosphere is lit
+		let rim = (1.0 - saturate(glm::dot(normal, &to_eye))).powf(3.0) * 0.14 * lighting.diffuse(n_dot_l);
+		final_color += glm::vec3(0.9, 0.75, 0.55) * rim;
+
+		if let Some(night) = surface.night {
+			final_color += night.radiance(&pattern_dir, !is_water) * night_fade(n_dot_l);
+		}
+
+		final_color
+	}
+
+	// Night lights as emission; the atmosphere's rim and sky glow are left out
+	fn surface(&self, ctx: &ShadingContext) -> (PbrMaterial, Vec3) {
+		let (mut material, shading_normal, is_water, pattern_dir) = Self::ground(ctx);
+		if let Some(night) = ctx.body.night {
+			let (light_dir, _) = ctx.lighting.sun_at(&ctx.frame_point);
+			material.emission += night.radiance(&pattern_dir, !is_water) * night_fade(glm::dot(&shading_normal, &light_dir));
+		}
+		(material, shading_normal)
+	}
+}
+
+// Gas giant: turbulent latitude bands drifting in longitude
+pub struct GasGiantShader;
+
+impl GasGiantShader {
+	// The body's material under the band colors
+	fn clouds(ctx: &ShadingContext) -> PbrMaterial {
+		let (world_pos, normal, time) = (&ctx.frame_point, &ctx.frame_normal, ctx.time);
+		// Shifts the turbulence pattern, one per scene seed
+		let noise_offset = &ctx.body.noise_offset;
+		// Calculate bands based on latitude (y coordinate in normal space)
+		let lat = normal.y;
+		let lon = normal.x.atan2(normal.z);
     
-	// Lighting
-	let light_dir = glm::normalize(&(LIGHT_POS - world_pos));
-	let n_dot_l = saturate(glm::dot(normal, &light_dir));
-	let ambient = 0.3;
-	let diffuse = n_dot_l * 0.7;
+		// Create flowing band pattern
+		let flow = lon * 6.0 + time * 0.8 + (lat * 10.0).sin() * 0.5;
+		let turbulence = tri_noise(&(world_pos + noise_offset), 8.0, time * 0.5);
+		let bands = (flow + turbulence * 2.0).sin() * 0.5 + 0.5;
     
-	// Add specular highlight
-	let half_vec = glm::normalize(&(light_dir + view));
-	let spec = saturate(glm::dot(normal, &half_vec)).powf(16.0) * 0.3;
+		// Colors for the bands
+		let color1 = glm::vec3(0.95, 0.78, 0.48); // Light band
+		let color2 = glm::vec3(0.25, 0.55, 0.85); // Dark band
     
-	// Combine lighting
-	let mut final_color = base_color * (ambient + diffuse);
-	final_color += glm::vec3(spec, spec, spec);
+		// Mix colors based on bands
+		let base_color = color1 * bands + color2 * (1.0 - bands);
+		PbrMaterial { albedo: base_color.component_mul(&ctx.body.material.albedo), ..ctx.body.material }
+	}
+}
+
+impl SurfaceShader for GasGiantShader {
+	fn shade(&self, ctx: &ShadingContext) -> Vec3 {
+		let (world_pos, normal, view) = (&ctx.frame_point, &ctx.frame_normal, &ctx.frame_view);
+		let lighting = ctx.lighting;
+		let mut final_color = pbr_shade(&Self::clouds(ctx), world_pos, normal, view, 1.0, true, lighting);
     
-	// Add atmospheric rim effect
-	let rim = (1.0 - saturate(glm::dot(normal, view))).powf(3.0) * 0.2;
-	final_color += glm::vec3(0.6, 0.7, 0.95) * rim;
+		// Add atmospheric rim effect
+		let (light_dir, _) = lighting.sun_at(world_pos);
+		let n_dot_l = glm::dot(normal, &light_dir);
+		let to_eye = -view;
+		let rim = (1.0 - saturate(glm::dot(normal, &to_eye))).powf(3.0) * 0.2 * lighting.diffuse(n_dot_l);
+		final_color += glm::vec3(0.6, 0.7, 0.95) * rim;
     
-	final_color
+		final_color
+	}
+
+	fn surface(&self, ctx: &ShadingContext) -> (PbrMaterial, Vec3) {
+		(Self::clouds(ctx), ctx.frame_normal)
+	}
+}
+
+// Bare rock (asteroids): regolith in `albedo`. No atmosphere softens the
+// terminator.
+pub fn asteroid_shader(world_pos: &Vec3, normal: &Vec3, view: &Vec3, albedo: &Vec3, lighting: &Lighting) -> Vec3 {
+	pbr_shade(&PbrMaterial::regolith(*albedo), world_pos, normal, view, 1.0, false, lighting)
+}
+
+// Airless surface in the body's material, usually PbrMaterial::regolith
+pub struct RegolithShader;
+
+impl SurfaceShader for RegolithShader {
+	fn shade(&self, ctx: &ShadingContext) -> Vec3 {
+		pbr_shade(&ctx.body.material, &ctx.frame_point, &ctx.frame_normal, &ctx.frame_view, ctx.body.light_visibility, false, ctx.lighting)
+	}
 }
 
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn lighting(terminator_width: f32) -> Lighting {
+		Lighting {
+			sun_pos: glm::vec3(0.0, 0.0, 0.0),
+			sun_color: glm::vec3(1.0, 1.0, 1.0),
+			sun_intensity: 1.0,
+			ambient: 0.0,
+			terminator_width,
+			reflectors: Vec::new(),
+		}
+	}
+
+	#[test]
+	fn diffuse_is_lambert_without_a_terminator_band() {
+		let hard = lighting(0.0);
+		for i in 0..=20 {
+			let n_dot_l = i as f32 / 10.0 - 1.0;
+			assert_eq!(hard.diffuse(n_dot_l), n_dot_l.max(0.0));
+		}
+	}
+
+	#[test]
+	fn terminator_band_spreads_past_the_edge() {
+		let soft = lighting(0.2);
+		assert_eq!(soft.diffuse(1.0), 1.0);
+		assert_eq!(soft.diffuse(-0.2), 0.0);
+		assert!(soft.diffuse(-0.1) > 0.0 && soft.diffuse(0.0) > 0.0);
+		assert!(soft.diffuse(0.5) < 0.6 && soft.diffuse(0.5) > 0.5);
+	}
+}
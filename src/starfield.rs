@@ -0,0 +1,248 @@
+use nalgebra_glm as glm;
+use crate::math::Vec3;
+use crate::noise;
+
+pub struct Star {
+    pub dir: Vec3, // unit direction on the celestial sphere
+    pub brightness: f32,
+    pub color: Vec3,
+}
+
+// Stars binned into a grid on the faces of a cube around the viewer, so a
+// sky lookup only tests the few stars near the sampled direction instead of
+// the whole catalog.
+pub struct StarField {
+    stars: Vec<Star>,
+    resolution: usize,     // cells along one edge of a cube face
+    cell_start: Vec<u32>,  // offsets into cell_stars, one per cell plus an end marker
+    cell_stars: Vec<u32>,  // star indices grouped by cell
+    max_radius: f32,
+    max_brightness: f32,
+}
+
+impl StarField {
+    // `max_radius` is the largest angular radius (radians) that will be
+    // passed to `for_each_near`; cells are sized so such a query touches at
+    // most four of them near a face center. Cells shrink towards the cube's
+    // edges and corners, where a query touches a few more.
+    pub fn new(stars: Vec<Star>, max_radius: f32) -> Self {
+        let cell_angle = 3.0 * max_radius.max(1e-4);
+        let resolution = ((std::f32::consts::FRAC_PI_2 / cell_angle) as usize).clamp(1, 512);
+        let cells = 6 * resolution * resolution;
+
+        // Counting sort of the stars by cell
+        let star_cells: Vec<usize> = stars.iter().map(|s| cell_of(&s.dir, resolution)).collect();
+        let mut cell_start = vec![0u32; cells + 1];
+        for &c in &star_cells {
+            cell_start[c + 1] += 1;
+        }
+        for i in 0..cells {
+            cell_start[i + 1] += cell_start[i];
+        }
+        let mut fill = cell_start.clone();
+        let mut cell_stars = vec![0u32; stars.len()];
+        for (i, &c) in star_cells.iter().enumerate() {
+            cell_stars[fill[c] as usize] = i as u32;
+            fill[c] += 1;
+        }
+
+        let max_brightness = stars.iter().fold(0.0f32, |m, s| m.max(s.brightness));
+        StarField { stars, resolution, cell_start, cell_stars, max_radius, max_brightness }
+    }
+
+    pub fn stars(&self) -> &[Star] {
+        &self.stars
+    }
+
+    // Largest query radius the grid was built for
+    pub fn max_radius(&self) -> f32 {
+        self.max_radius
+    }
+
+    pub fn max_brightness(&self) -> f32 {
+        self.max_brightness
+    }
+
+    // Visit every star that may lie within `radius` of `dir`. Callers still
+    // do the exact angular test; this only narrows down the candidates.
+    pub fn for_each_near(&self, dir: &Vec3, radius: f32, mut f: impl FnMut(&Star)) {
+        // On each face the cap around `dir` covers a range of face
+        // coordinates; visit every cell in it. Ranges are computed rather
+        // than sampled because cells shrink and skew towards cube corners.
+        let dir = glm::normalize(dir);
+        let s = radius.clamp(0.0, std::f32::consts::FRAC_PI_2).sin();
+        let n = self.resolution;
+        let to_cell = |t: f32| (((t + 1.0) * 0.5 * n as f32) as usize).min(n - 1);
+        for face in 0..6 {
+            let (axis, other_u, other_v) = [(0, 1, 2), (1, 0, 2), (2, 0, 1)][face / 2];
+            let depth = if face % 2 == 0 { dir[axis] } else { -dir[axis] };
+            if depth <= -s {
+                continue;
+            }
+            let (Some((u0, u1)), Some((v0, v1))) = (face_range(depth, dir[other_u], s), face_range(depth, dir[other_v], s)) else {
+                continue;
+            };
+            for cu in to_cell(u0)..=to_cell(u1) {
+                for cv in to_cell(v0)..=to_cell(v1) {
+                    let c = (face * n + cu) * n + cv;
+                    let range = self.cell_start[c] as usize..self.cell_start[c + 1] as usize;
+                    for &i in &self.cell_stars[range] {
+                        f(&self.stars[i as usize]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Range of the face coordinate t = lateral / depth over the cap of angular
+// radius asin(s) around a unit direction with those two components, clipped
+// to the face. The plane t * depth = lateral meets the cap when
+// |lateral - t * depth| <= s * sqrt(1 + t^2); solving for t gives the range.
+fn face_range(depth: f32, lateral: f32, s: f32) -> Option<(f32, f32)> {
+    // Slack for stars binned right on a cell boundary
+    const PAD: f32 = 1e-4;
+    let a = depth * depth - s * s;
+    let (lo, hi) = if a <= 1e-6 {
+        // The cap reaches the face's horizon, where t is unbounded
+        (-1.0, 1.0)
+    } else {
+        let half = s * (depth * depth + lateral * lateral - s * s).max(0.0).sqrt();
+        ((depth * lateral - half) / a - PAD, (depth * lateral + half) / a + PAD)
+    };
+    (lo <= 1.0 && hi >= -1.0).then(|| (lo.max(-1.0), hi.min(1.0)))
+}
+
+// Cell index of a (not necessarily unit) direction: pick the cube face of
+// the dominant axis, then a cell from the projected face coordinates.
+fn cell_of(dir: &Vec3, resolution: usize) -> usize {
+    let (ax, ay, az) = (dir.x.abs(), dir.y.abs(), dir.z.abs());
+    let (face, u, v) = if ax >= ay && ax >= az {
+        (if dir.x > 0.0 { 0 } else { 1 }, dir.y / ax, dir.z / ax)
+    } else if ay >= az {
+        (if dir.y > 0.0 { 2 } else { 3 }, dir.x / ay, dir.z / ay)
+    } else {
+        (if dir.z > 0.0 { 4 } else { 5 }, dir.x / az, dir.y / az)
+    };
+    let to_cell = |t: f32| (((t + 1.0) * 0.5 * resolution as f32) as usize).min(resolution - 1);
+    (face * resolution + to_cell(u)) * resolution + to_cell(v)
+}
+
+// Stars are point sources: their image is the telescope/eye point spread
+// function, a Gaussian core a little over a pixel wide plus a faint wide
+// halo. Sizes are in pixels so stars look the same at any resolution or FOV.
+const PSF_CORE: f32 = 0.6;          // core sigma of a faint star, pixels
+const PSF_CORE_GROWTH: f32 = 0.35;  // extra core sigma per sqrt(brightness)
+const PSF_HALO: f32 = 4.0;          // halo sigma relative to the core
+const PSF_HALO_WEIGHT: f32 = 0.04;
+
+fn psf_sigma(brightness: f32, pixel_angle: f32) -> f32 {
+    (PSF_CORE + PSF_CORE_GROWTH * brightness.sqrt()) * pixel_angle
+}
+
+// Largest angle from a star at which `star_psf` is still worth evaluating
+pub fn psf_reach(brightness: f32, pixel_angle: f32) -> f32 {
+    psf_sigma(brightness, pixel_angle) * PSF_HALO * 3.0
+}
+
+// Radiance gain at `angle` (radians) from a star of the given brightness.
+// Bright stars also spread over a wider core, as they do on film and in the
+// eye, so magnitude reads as size once the center saturates.
+pub fn star_psf(angle: f32, brightness: f32, pixel_angle: f32) -> f32 {
+    let x = angle / psf_sigma(brightness, pixel_angle);
+    let core = (-0.5 * x * x).exp();
+    let h = x / PSF_HALO;
+    let halo = (-0.5 * h * h).exp() * PSF_HALO_WEIGHT;
+    brightness * (core + halo)
+}
+
+// Twinkling of stars seen through an atmosphere. Turbulent cells in the air
+// make each star flicker on its own, more strongly towards the horizon where
+// the light crosses more air.
+pub struct Scintillation {
+    pub strength: f32, // relative brightness swing at the zenith
+    pub speed: f32,    // flicker rate, roughly changes per second
+    pub altitude: f32, // atmosphere thickness above a planet's surface
+}
+
+impl Default for Scintillation {
+    fn default() -> Self {
+        Scintillation { strength: 0.35, speed: 9.0, altitude: 0.08 }
+    }
+}
+
+impl Scintillation {
+    // Brightness factor of `star` at `time`. `up` is the local vertical at
+    // the observer and `depth` how deep in the atmosphere it stands (1 on the
+    // ground, 0 at the top).
+    pub fn factor(&self, star: &Star, up: &Vec3, depth: f32, time: f32) -> f32 {
+        let id = noise::hash3(star.dir.x.to_bits() as i32, star.dir.y.to_bits() as i32, star.dir.z.to_bits() as i32);
+        let phase = noise::hash_to_unit(id) * 97.0;
+        // Plane-parallel airmass, capped near the horizon
+        let airmass = 1.0 / glm::dot(&star.dir, up).max(0.1);
+        let flicker = noise::fbm(&glm::vec3(time * self.speed + phase, phase, 0.0), 2) * 2.0 - 1.0;
+        (1.0 + flicker * self.strength * depth * airmass.sqrt()).max(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn random_unit(rng: &mut StdRng) -> Vec3 {
+        loop {
+            let v = glm::vec3(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+            let len = glm::length(&v);
+            if len > 0.1 && len <= 1.0 {
+                return v / len;
+            }
+        }
+    }
+
+    #[test]
+    fn queries_on_cube_edges_and_corners_visit_every_star_in_reach() {
+        let mut rng = StdRng::seed_from_u64(3);
+        // Directions on the cube's corners, edge midpoints and along its edges
+        let mut targets = Vec::new();
+        for x in [-1.0, 0.0, 1.0] {
+            for y in [-1.0, 0.0, 1.0] {
+                for z in [-1.0, 0.0, 1.0] {
+                    let corner: Vec3 = glm::vec3(x, y, z);
+                    if corner.iter().filter(|c| **c != 0.0).count() >= 2 {
+                        targets.push(glm::normalize(&corner));
+                    }
+                }
+            }
+        }
+        for _ in 0..40 {
+            let t = rng.gen_range(-1.0..1.0);
+            let mut edge = [1.0f32, t, 1.0];
+            edge.rotate_left(rng.gen_range(0..3));
+            let signs = glm::vec3(rng.gen_range(0..2) as f32 * 2.0 - 1.0, rng.gen_range(0..2) as f32 * 2.0 - 1.0, rng.gen_range(0..2) as f32 * 2.0 - 1.0);
+            targets.push(glm::normalize(&glm::vec3(edge[0], edge[1], edge[2]).component_mul(&signs)));
+        }
+
+        for max_radius in [0.01, 0.05, 0.3] {
+            // Stars crowded around the seams, tagged by their index
+            let stars: Vec<Star> = (0..20000).map(|i| {
+                let target = targets[i % targets.len()];
+                let dir = glm::normalize(&(target + random_unit(&mut rng) * rng.gen_range(0.0..2.0 * max_radius)));
+                Star { dir, brightness: i as f32, color: glm::vec3(1.0, 1.0, 1.0) }
+            }).collect();
+            let field = StarField::new(stars, max_radius);
+            for target in &targets {
+                for _ in 0..5 {
+                    let query = glm::normalize(&(target + random_unit(&mut rng) * rng.gen_range(0.0..0.5 * max_radius)));
+                    let mut visited = vec![false; field.stars().len()];
+                    field.for_each_near(&query, max_radius, |star| visited[star.brightness as usize] = true);
+                    for (i, star) in field.stars().iter().enumerate() {
+                        let angle = glm::dot(&star.dir, &query).clamp(-1.0, 1.0).acos();
+                        assert!(angle > max_radius || visited[i], "missed a star {angle} from {query:?} (radius {max_radius})");
+                    }
+                }
+            }
+        }
+    }
+}